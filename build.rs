@@ -0,0 +1,36 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// With the `capi` feature, this compiles the C test program against the
+// checked-in header. It is only linked into the unit tests of `src/capi.rs`,
+// so nothing is added to the library itself.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "capi")]
+    {
+        println!("cargo:rerun-if-changed=include/av1_grain.h");
+        println!("cargo:rerun-if-changed=tests/capi.c");
+
+        cc::Build::new()
+            .file("tests/capi.c")
+            .include("include")
+            .std("c99")
+            .warnings(true)
+            .extra_warnings(true)
+            .warnings_into_errors(true)
+            .cargo_metadata(false)
+            .compile("av1_grain_capi_test");
+        println!(
+            "cargo:rustc-link-search=native={}",
+            std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo")
+        );
+    }
+}
@@ -0,0 +1,166 @@
+/*
+ * Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+ *
+ * This source code is subject to the terms of the BSD 2 Clause License and
+ * the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+ * was not distributed with this source code in the LICENSE file, you can
+ * obtain it at www.aomedia.org/license/software. If the Alliance for Open
+ * Media Patent License 1.0 was not distributed with this source code in the
+ * PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+ */
+
+/*
+ * C API of av1-grain, available when the crate is built with the `capi`
+ * feature, for example with:
+ *
+ *     cargo rustc --release --features capi --crate-type staticlib
+ *
+ * Functions which can fail return NULL or -1, and describe the failure in
+ * a message returned by av1_grain_last_error().
+ *
+ * Structs start with a struct_size field, which callers must set to the
+ * sizeof the struct before passing it to the library, including structs
+ * the library writes to. Fields are only ever added to the end of structs,
+ * so the library rejects structs smaller than its own, ignores the fields
+ * of larger ones which it does not know, and never changes struct_size.
+ */
+
+#ifndef AV1_GRAIN_H
+#define AV1_GRAIN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define AV1_GRAIN_NUM_Y_POINTS 14
+#define AV1_GRAIN_NUM_UV_POINTS 10
+#define AV1_GRAIN_NUM_Y_COEFFS 24
+#define AV1_GRAIN_NUM_UV_COEFFS 25
+
+/* A list of grain table segments, ordered by time. */
+typedef struct Av1GrainTable Av1GrainTable;
+
+/*
+ * The film grain parameters of a segment of video from start_time to
+ * end_time, in 10,000,000ths of a second. Scaling points are pairs of
+ * a value and a scaling, and only the first num_* entries of each array
+ * are used.
+ */
+typedef struct Av1GrainSegment {
+    /* Must be set to sizeof(Av1GrainSegment) */
+    size_t struct_size;
+    uint64_t start_time;
+    uint64_t end_time;
+    uint8_t num_y_points;
+    uint8_t scaling_points_y[AV1_GRAIN_NUM_Y_POINTS][2];
+    uint8_t num_cb_points;
+    uint8_t scaling_points_cb[AV1_GRAIN_NUM_UV_POINTS][2];
+    uint8_t num_cr_points;
+    uint8_t scaling_points_cr[AV1_GRAIN_NUM_UV_POINTS][2];
+    uint8_t scaling_shift;
+    uint8_t ar_coeff_lag;
+    uint8_t num_y_coeffs;
+    int8_t ar_coeffs_y[AV1_GRAIN_NUM_Y_COEFFS];
+    uint8_t num_cb_coeffs;
+    int8_t ar_coeffs_cb[AV1_GRAIN_NUM_UV_COEFFS];
+    uint8_t num_cr_coeffs;
+    int8_t ar_coeffs_cr[AV1_GRAIN_NUM_UV_COEFFS];
+    uint8_t ar_coeff_shift;
+    uint8_t cb_mult;
+    uint8_t cb_luma_mult;
+    uint16_t cb_offset;
+    uint8_t cr_mult;
+    uint8_t cr_luma_mult;
+    uint16_t cr_offset;
+    bool overlap_flag;
+    bool chroma_scaling_from_luma;
+    uint8_t grain_scale_shift;
+    uint16_t random_seed;
+    /* Whether grain is applied, or the segment marks an interval without grain */
+    bool apply_grain;
+    /* Whether new parameters are signalled, or those of the previous segment are reused */
+    bool update_grain;
+    /* The reference frame whose parameters are reused, or -1 if unknown */
+    int8_t film_grain_params_ref_idx;
+    /* Whether samples are clipped to the studio range after adding grain */
+    bool clip_to_restricted_range;
+} Av1GrainSegment;
+
+typedef enum Av1GrainTransferFunction {
+    /* For SDR content */
+    AV1_GRAIN_TRANSFER_BT1886 = 0,
+    /* For HDR content */
+    AV1_GRAIN_TRANSFER_SMPTE2084 = 1,
+} Av1GrainTransferFunction;
+
+/* Settings and video data defining how to generate photon noise. */
+typedef struct Av1GrainNoiseArgs {
+    /* Must be set to sizeof(Av1GrainNoiseArgs) */
+    size_t struct_size;
+    uint32_t iso_setting;
+    uint32_t width;
+    uint32_t height;
+    /* One of Av1GrainTransferFunction */
+    int transfer_function;
+    /* Whether the input is full range or limited range */
+    bool full_range;
+    bool chroma_grain;
+    /* Whether to use random_seed instead of the default seed */
+    bool has_random_seed;
+    uint16_t random_seed;
+} Av1GrainNoiseArgs;
+
+/*
+ * Returns a description of the last failure on the calling thread, or NULL
+ * if the last call succeeded. The string is valid until the next call to
+ * this library on the same thread.
+ */
+const char *av1_grain_last_error(void);
+
+/* Creates an empty table, which must be freed with av1_grain_table_free(). */
+Av1GrainTable *av1_grain_table_new(void);
+
+/* Frees a table. Does nothing if table is NULL. */
+void av1_grain_table_free(Av1GrainTable *table);
+
+/*
+ * Parses a table in the filmgrn1 text format used by aomenc, rav1e and
+ * svt-av1. Returns NULL on failure.
+ */
+Av1GrainTable *av1_grain_table_parse(const char *text);
+
+/* Writes a table in the filmgrn1 text format to the file at path. */
+int av1_grain_table_write(const Av1GrainTable *table, const char *path);
+
+/* Returns the number of segments in a table. */
+size_t av1_grain_table_len(const Av1GrainTable *table);
+
+/* Copies the segment at index into out. */
+int av1_grain_table_get(const Av1GrainTable *table, size_t index, Av1GrainSegment *out);
+
+/* Replaces the segment at index. */
+int av1_grain_table_set(Av1GrainTable *table, size_t index, const Av1GrainSegment *segment);
+
+/* Appends a segment to the end of a table. */
+int av1_grain_table_push(Av1GrainTable *table, const Av1GrainSegment *segment);
+
+/* Removes the segment at index, shifting the following segments down. */
+int av1_grain_table_remove(Av1GrainTable *table, size_t index);
+
+/*
+ * Generates photon noise parameters for the segment of video from
+ * start_time to end_time, writing them to out.
+ */
+int av1_grain_generate_photon_noise(uint64_t start_time, uint64_t end_time,
+                                    const Av1GrainNoiseArgs *args,
+                                    Av1GrainSegment *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AV1_GRAIN_H */
@@ -0,0 +1,53 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This module converts between `GrainTableSegment`s and the
+// `film_grain_params()` syntax element of the AV1 frame header, described
+// in section 5.9.30 of the AV1 specification, and extracts or rewrites
+// the film grain parameters of AV1 streams. It also reads and writes
+// the AFGS1 film grain metadata of the Alliance for Open Media.
+
+mod afgs1;
+mod bits;
+mod extract;
+mod film_grain;
+mod headers;
+mod ivf;
+mod obu;
+mod rewrite;
+#[cfg(test)]
+mod test_stream;
+
+pub use self::{afgs1::*, bits::*, extract::*, film_grain::*, obu::ObuStreamFormat, rewrite::*};
+
+/// The parts of the sequence header's `color_config()` which affect
+/// how film grain parameters are signalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorConfig {
+    /// Whether the sequence only contains a luma plane
+    pub mono_chrome: bool,
+    /// Whether chroma is subsampled horizontally
+    pub subsampling_x: bool,
+    /// Whether chroma is subsampled vertically
+    pub subsampling_y: bool,
+}
+
+/// The type of an AV1 frame, as signalled by `frame_type`
+/// in the frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    /// `KEY_FRAME`
+    Key,
+    /// `INTER_FRAME`
+    Inter,
+    /// `INTRA_ONLY_FRAME`
+    IntraOnly,
+    /// `SWITCH_FRAME`
+    Switch,
+}
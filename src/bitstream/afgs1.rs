@@ -0,0 +1,903 @@
+use anyhow::{Context, Result, ensure};
+use arrayvec::ArrayVec;
+
+use super::{BitReader, BitWriter, ColorConfig, film_grain::validate_for_writing};
+use crate::{GrainTableSegment, NUM_UV_POINTS, NUM_Y_POINTS};
+
+/// The ITU-T T.35 country code of AFGS1 metadata.
+pub const AFGS1_T35_COUNTRY_CODE: u8 = 0xb5;
+/// The ITU-T T.35 terminal provider code of AOMedia.
+pub const AFGS1_T35_PROVIDER_CODE: u16 = 0x5890;
+/// The ITU-T T.35 terminal provider oriented code of AFGS1 metadata.
+pub const AFGS1_T35_PROVIDER_ORIENTED_CODE: u8 = 0x01;
+/// The maximum number of film grain sets in one AFGS1 payload.
+pub const AFGS1_MAX_SETS: usize = 8;
+
+const T35_HEADER: [u8; 4] = [
+    AFGS1_T35_COUNTRY_CODE,
+    (AFGS1_T35_PROVIDER_CODE >> 8) as u8,
+    AFGS1_T35_PROVIDER_CODE as u8,
+    AFGS1_T35_PROVIDER_ORIENTED_CODE,
+];
+
+/// The video signal characteristics of the video a film grain set
+/// was designed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Afgs1VideoSignal {
+    /// The bit depth, between 8 and 15
+    pub bit_depth: u8,
+    /// The colour description, if signalled
+    pub color_description: Option<Afgs1ColorDescription>,
+}
+
+/// The colour description of the video a film grain set was designed for,
+/// using the code points of ITU-T H.273.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Afgs1ColorDescription {
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    /// Whether the video uses the full range of sample values
+    pub full_range: bool,
+}
+
+/// A film grain set carried by an AFGS1 payload, together with
+/// the properties of the video it was designed for.
+///
+/// A payload can carry several sets, allowing a player to pick the one
+/// which matches the resolution and format of the video it outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Afgs1FilmGrainSet {
+    /// The index of the set, between 0 and 7, by which later payloads
+    /// can reuse its parameters
+    pub set_index: u8,
+    /// The film grain parameters. `start_time` and `end_time` are not
+    /// signalled, and are set to 0 when reading.
+    pub segment: GrainTableSegment,
+    /// The width of the video, in luma samples
+    pub width: u32,
+    /// The height of the video, in luma samples
+    pub height: u32,
+    /// The chroma format of the video. A monochrome set is signalled as
+    /// luma only, without its subsampling.
+    pub color_config: ColorConfig,
+    /// The video signal characteristics, if signalled
+    pub video_signal: Option<Afgs1VideoSignal>,
+}
+
+/// Writes film grain sets as an ITU-T T.35 message carrying an AFGS1
+/// payload, starting with `itu_t_t35_country_code`.
+///
+/// Every set is written with its parameters in full, without predicting
+/// scaling functions from another set, except for sets whose segment has
+/// `apply_grain` unset, which only signal that. Writing no sets produces a
+/// payload which disables film grain.
+///
+/// # Errors
+///
+/// - If there are more than 8 sets, or two sets share an index
+/// - If a set index is greater than 7
+/// - If the width or height of a set cannot be signalled, which requires
+///   both to fit in 12 bits after dividing by a common power of two
+/// - If a bit depth is outside of 8 to 15, or a colour description is
+///   provided without a bit depth
+/// - If a set uses 4:4:0 subsampling, or is monochrome and scales chroma
+/// - If the segment contains parameters outside of the ranges allowed by
+///   the AV1 specification, or scaling points which are not in
+///   increasing order
+#[inline]
+pub fn write_afgs1_payload(sets: &[Afgs1FilmGrainSet]) -> Result<Vec<u8>> {
+    ensure!(
+        sets.len() <= AFGS1_MAX_SETS,
+        "An AFGS1 payload can carry at most {AFGS1_MAX_SETS} sets, got {}",
+        sets.len()
+    );
+    let mut used_indices = 0u8;
+    for set in sets {
+        ensure!(
+            usize::from(set.set_index) < AFGS1_MAX_SETS,
+            "Set index must be between 0 and 7, got {}",
+            set.set_index
+        );
+        ensure!(
+            used_indices & (1 << set.set_index) == 0,
+            "Set index {} is used more than once",
+            set.set_index
+        );
+        used_indices |= 1 << set.set_index;
+    }
+
+    let mut writer = BitWriter::new();
+    for byte in T35_HEADER {
+        writer.write_bits(u32::from(byte), 8);
+    }
+    // afgs1_enable_flag and reserved bits
+    writer.write_bit(!sets.is_empty());
+    writer.write_bits(0, 4);
+    writer.write_bits(sets.len().saturating_sub(1) as u32, 3);
+    if sets.is_empty() {
+        return Ok(writer.into_bytes());
+    }
+
+    for set in sets {
+        // The size of each set includes its size fields,
+        // which take 9 bits when the size is written in 8 bits.
+        let mut body = BitWriter::new();
+        write_set(&mut body, set)?;
+        let payload_size = (9 + body.bit_len()).div_ceil(8);
+        ensure!(
+            payload_size <= 255,
+            "Film grain set {} is too large to signal",
+            set.set_index
+        );
+
+        // payload_less_than_4byte_flag
+        writer.write_bit(false);
+        writer.write_bits(payload_size as u32, 8);
+        write_set(&mut writer, set)?;
+        writer.byte_align();
+    }
+
+    Ok(writer.into_bytes())
+}
+
+/// Reads an ITU-T T.35 message carrying an AFGS1 payload, starting with
+/// `itu_t_t35_country_code`, and returns the film grain sets which apply
+/// to the current frame.
+///
+/// `previous` holds the sets of the last payload of the stream, which are
+/// used by sets which only update the random seed. Sets which disable film
+/// grain are not returned, and no sets are returned if the payload
+/// disables film grain entirely.
+///
+/// # Errors
+///
+/// - If the message is not an AFGS1 payload
+/// - If the data ends before the end of a set, or a set is larger than its
+///   signalled size
+/// - If a set reuses or predicts parameters which are not available
+/// - If the parameters are not valid, such as scaling points
+///   exceeding the maximum count
+#[inline]
+pub fn read_afgs1_payload(
+    data: &[u8],
+    previous: &[Afgs1FilmGrainSet],
+) -> Result<Vec<Afgs1FilmGrainSet>> {
+    ensure!(
+        data.starts_with(&T35_HEADER),
+        "Not an AFGS1 ITU-T T.35 message"
+    );
+    let mut reader = BitReader::new(data.get(T35_HEADER.len()..).unwrap_or_default());
+    let r = &mut reader;
+
+    let afgs1_enable_flag = r.read_bit()?;
+    if !afgs1_enable_flag {
+        return Ok(Vec::new());
+    }
+    // reserved bits
+    r.read_bits(4)?;
+    let num_film_grain_sets = r.read_bits(3)? + 1;
+
+    let mut sets: Vec<Afgs1FilmGrainSet> = Vec::new();
+    // The set which scaling functions can be predicted from
+    let mut reference: Option<Afgs1FilmGrainSet> = None;
+    for n in 0..num_film_grain_sets {
+        let start = r.position();
+        let payload_less_than_4byte_flag = r.read_bit()?;
+        let payload_size = r.read_bits(if payload_less_than_4byte_flag { 2 } else { 8 })? as usize;
+        let set_index = r.read_bits(3)? as u8;
+
+        if let Some((set, updated)) = read_set(r, set_index, previous, reference.as_ref())? {
+            if n == 0 && updated {
+                reference = Some(set.clone());
+            }
+            sets.retain(|other| other.set_index != set_index);
+            sets.push(set);
+        }
+
+        let end = start + payload_size * 8;
+        ensure!(
+            r.position() <= end,
+            "Film grain set {set_index} is larger than its signalled size"
+        );
+        while r.position() < end {
+            r.read_bit()?;
+        }
+    }
+
+    Ok(sets)
+}
+
+fn write_set(w: &mut BitWriter, set: &Afgs1FilmGrainSet) -> Result<()> {
+    let segment = &set.segment;
+    if !segment.apply_grain {
+        w.write_bits(u32::from(set.set_index), 3);
+        // apply_grain
+        w.write_bit(false);
+        return Ok(());
+    }
+    validate_for_writing(segment)?;
+    let color_config = set.color_config;
+    let luma_only = color_config.mono_chrome;
+    ensure!(
+        luma_only || color_config.subsampling_x || !color_config.subsampling_y,
+        "4:4:0 subsampling cannot be signalled"
+    );
+    ensure!(
+        !luma_only
+            || (segment.scaling_points_cb.is_empty()
+                && segment.scaling_points_cr.is_empty()
+                && !segment.chroma_scaling_from_luma),
+        "Luma only film grain sets cannot scale chroma"
+    );
+    let apply_units_log2 = (0..16)
+        .find(|&log2| {
+            let fits = |size: u32| size.is_multiple_of(1 << log2) && size >> log2 < 4096;
+            fits(set.width) && fits(set.height)
+        })
+        .with_context(|| format!("Cannot signal a resolution of {}x{}", set.width, set.height))?;
+
+    w.write_bits(u32::from(set.set_index), 3);
+    // apply_grain
+    w.write_bit(true);
+    w.write_bits(u32::from(segment.random_seed), 16);
+    // update_grain
+    w.write_bit(true);
+    w.write_bits(apply_units_log2, 4);
+    w.write_bits(set.width >> apply_units_log2, 12);
+    w.write_bits(set.height >> apply_units_log2, 12);
+    w.write_bit(luma_only);
+    if !luma_only {
+        w.write_bit(color_config.subsampling_x);
+        w.write_bit(color_config.subsampling_y);
+    }
+    w.write_bit(set.video_signal.is_some());
+    if let Some(video_signal) = set.video_signal {
+        ensure!(
+            (8..=15).contains(&video_signal.bit_depth),
+            "Bit depth must be between 8 and 15, got {}",
+            video_signal.bit_depth
+        );
+        w.write_bits(u32::from(video_signal.bit_depth - 8), 3);
+        w.write_bit(video_signal.color_description.is_some());
+        if let Some(color_description) = video_signal.color_description {
+            w.write_bits(u32::from(color_description.color_primaries), 8);
+            w.write_bits(u32::from(color_description.transfer_characteristics), 8);
+            w.write_bits(u32::from(color_description.matrix_coefficients), 8);
+            w.write_bit(color_description.full_range);
+        }
+    }
+    // predict_scaling
+    w.write_bit(false);
+
+    w.write_bits(segment.scaling_points_y.len() as u32, 4);
+    if !segment.scaling_points_y.is_empty() {
+        write_points(w, &segment.scaling_points_y, None, "luma")?;
+    }
+    let chroma_scaling_from_luma = !luma_only && segment.chroma_scaling_from_luma;
+    let (cb_points, cr_points): (&[[u8; 2]], &[[u8; 2]]) = if luma_only {
+        (&[], &[])
+    } else {
+        w.write_bit(chroma_scaling_from_luma);
+        if chroma_scaling_from_luma {
+            (&[], &[])
+        } else {
+            for (points, label) in [
+                (&segment.scaling_points_cb, "Cb"),
+                (&segment.scaling_points_cr, "Cr"),
+            ] {
+                w.write_bits(points.len() as u32, 4);
+                let uv_offset = points.iter().map(|&[_, scaling]| scaling).min();
+                write_points(w, points, Some(uv_offset.unwrap_or(0)), label)?;
+            }
+            (&segment.scaling_points_cb, &segment.scaling_points_cr)
+        }
+    };
+
+    w.write_bits(u32::from(segment.scaling_shift - 8), 2);
+    w.write_bits(u32::from(segment.ar_coeff_lag), 2);
+    let num_pos_luma =
+        2 * usize::from(segment.ar_coeff_lag) * usize::from(segment.ar_coeff_lag + 1);
+    let has_luma = !segment.scaling_points_y.is_empty();
+    if has_luma {
+        write_coeffs(w, &segment.ar_coeffs_y, num_pos_luma);
+    }
+    let num_pos_chroma = num_pos_luma + usize::from(has_luma);
+    if chroma_scaling_from_luma || !cb_points.is_empty() {
+        write_coeffs(w, &segment.ar_coeffs_cb, num_pos_chroma);
+    }
+    if chroma_scaling_from_luma || !cr_points.is_empty() {
+        write_coeffs(w, &segment.ar_coeffs_cr, num_pos_chroma);
+    }
+    w.write_bits(u32::from(segment.ar_coeff_shift - 6), 2);
+    w.write_bits(u32::from(segment.grain_scale_shift), 2);
+    if !cb_points.is_empty() {
+        w.write_bits(u32::from(segment.cb_mult), 8);
+        w.write_bits(u32::from(segment.cb_luma_mult), 8);
+        w.write_bits(u32::from(segment.cb_offset), 9);
+    }
+    if !cr_points.is_empty() {
+        w.write_bits(u32::from(segment.cr_mult), 8);
+        w.write_bits(u32::from(segment.cr_luma_mult), 8);
+        w.write_bits(u32::from(segment.cr_offset), 9);
+    }
+    w.write_bit(segment.overlap_flag);
+    // limit_output_range
+    w.write_bit(segment.clip_to_restricted_range);
+
+    Ok(())
+}
+
+/// Writes the bit sizes and values of scaling points, whose values are
+/// coded as increments. Chroma planes code their scaling relative to
+/// `uv_offset`, which is signalled after the bit sizes.
+fn write_points(
+    w: &mut BitWriter,
+    points: &[[u8; 2]],
+    uv_offset: Option<u8>,
+    label: &str,
+) -> Result<()> {
+    let offset = uv_offset.unwrap_or(0);
+    let mut increments = ArrayVec::<u8, NUM_Y_POINTS>::new();
+    let mut previous = 0;
+    for &[value, _] in points {
+        ensure!(
+            increments.is_empty() || value > previous,
+            "{label} scaling points must be in increasing order"
+        );
+        increments.push(value - previous);
+        previous = value;
+    }
+    let bits_increment = bits_needed(increments.iter().copied().max().unwrap_or(0)).max(1);
+    let max_scaling = points.iter().map(|&[_, scaling]| scaling - offset).max();
+    let bits_scaling = bits_needed(max_scaling.unwrap_or(0)).max(5);
+
+    w.write_bits(bits_increment - 1, 3);
+    w.write_bits(bits_scaling - 5, 2);
+    if let Some(uv_offset) = uv_offset {
+        w.write_bits(u32::from(uv_offset), 8);
+    }
+    for (&increment, &[_, scaling]) in increments.iter().zip(points) {
+        w.write_bits(u32::from(increment), bits_increment);
+        w.write_bits(u32::from(scaling - offset), bits_scaling);
+    }
+    Ok(())
+}
+
+fn bits_needed(value: u8) -> u32 {
+    u8::BITS - value.leading_zeros()
+}
+
+/// Writes the first `count` coefficients with the smallest supported
+/// number of bits.
+fn write_coeffs(w: &mut BitWriter, coeffs: &[i8], count: usize) {
+    let coeffs = coeffs
+        .iter()
+        .copied()
+        .chain(std::iter::repeat(0))
+        .take(count);
+    let ar_coeff_bits = (5..=8)
+        .find(|&bits| {
+            let max = (1i32 << (bits - 1)) - 1;
+            coeffs
+                .clone()
+                .all(|coeff| (-max - 1..=max).contains(&i32::from(coeff)))
+        })
+        .unwrap_or(8);
+    w.write_bits(ar_coeff_bits - 5, 2);
+    for coeff in coeffs {
+        w.write_bits(
+            (i32::from(coeff) + (1 << (ar_coeff_bits - 1))) as u32,
+            ar_coeff_bits,
+        );
+    }
+}
+
+/// Reads a film grain set after its `set_index`, returning `None` if it
+/// disables film grain, and whether its parameters were signalled in full
+/// rather than reused from a previous payload.
+fn read_set(
+    r: &mut BitReader,
+    set_index: u8,
+    previous: &[Afgs1FilmGrainSet],
+    reference: Option<&Afgs1FilmGrainSet>,
+) -> Result<Option<(Afgs1FilmGrainSet, bool)>> {
+    let apply_grain = r.read_bit()?;
+    if !apply_grain {
+        return Ok(None);
+    }
+    let random_seed = r.read_bits(16)? as u16;
+    let update_grain = r.read_bit()?;
+    if !update_grain {
+        let previous = previous
+            .iter()
+            .find(|set| set.set_index == set_index)
+            .with_context(|| format!("Film grain set {set_index} reuses unknown parameters"))?;
+        let mut set = previous.clone();
+        set.segment.random_seed = random_seed;
+        return Ok(Some((set, false)));
+    }
+
+    let apply_units_log2 = r.read_bits(4)?;
+    let width = r.read_bits(12)? << apply_units_log2;
+    let height = r.read_bits(12)? << apply_units_log2;
+    let luma_only = r.read_bit()?;
+    let color_config = if luma_only {
+        ColorConfig {
+            mono_chrome: true,
+            subsampling_x: true,
+            subsampling_y: true,
+        }
+    } else {
+        let subsampling_x = r.read_bit()?;
+        let subsampling_y = r.read_bit()?;
+        ensure!(
+            subsampling_x || !subsampling_y,
+            "4:4:0 subsampling is not supported"
+        );
+        ColorConfig {
+            mono_chrome: false,
+            subsampling_x,
+            subsampling_y,
+        }
+    };
+    let video_signal_characteristics_flag = r.read_bit()?;
+    let video_signal = if video_signal_characteristics_flag {
+        let bit_depth = r.read_bits(3)? as u8 + 8;
+        let cicp_info_present_flag = r.read_bit()?;
+        let color_description = if cicp_info_present_flag {
+            Some(Afgs1ColorDescription {
+                color_primaries: r.read_bits(8)? as u8,
+                transfer_characteristics: r.read_bits(8)? as u8,
+                matrix_coefficients: r.read_bits(8)? as u8,
+                full_range: r.read_bit()?,
+            })
+        } else {
+            None
+        };
+        Some(Afgs1VideoSignal {
+            bit_depth,
+            color_description,
+        })
+    } else {
+        None
+    };
+
+    let predict_scaling = r.read_bit()?;
+    let reference = if predict_scaling {
+        Some(reference.context(
+            "Scaling functions can only be predicted from the first set, \
+             when it is signalled in full",
+        )?)
+    } else {
+        None
+    };
+    let scaling_points_y = match reference {
+        Some(reference) if r.read_bit()? => predict_points(r, &reference.segment.scaling_points_y)?,
+        _ => read_points::<NUM_Y_POINTS>(r, false, "luma")?,
+    };
+
+    let mut chroma_scaling_from_luma = false;
+    let mut chroma = [(ArrayVec::new(), None), (ArrayVec::new(), None)];
+    if !luma_only {
+        chroma_scaling_from_luma = r.read_bit()?;
+        if !chroma_scaling_from_luma {
+            let reference_chroma = reference.map(|reference| {
+                let segment = &reference.segment;
+                [
+                    (
+                        &segment.scaling_points_cb,
+                        (segment.cb_mult, segment.cb_luma_mult, segment.cb_offset),
+                    ),
+                    (
+                        &segment.scaling_points_cr,
+                        (segment.cr_mult, segment.cr_luma_mult, segment.cr_offset),
+                    ),
+                ]
+            });
+            for (i, (points, multipliers)) in chroma.iter_mut().enumerate() {
+                let label = if i == 0 { "Cb" } else { "Cr" };
+                let reference = reference_chroma
+                    .as_ref()
+                    .and_then(|reference| reference.get(i));
+                *points = match reference {
+                    Some(&(reference_points, reference_multipliers)) if r.read_bit()? => {
+                        // The multipliers are taken from the reference set,
+                        // and not signalled.
+                        *multipliers = Some(reference_multipliers);
+                        predict_points(r, reference_points)?
+                    }
+                    _ => read_points::<NUM_UV_POINTS>(r, true, label)?,
+                };
+            }
+        }
+    }
+    let [
+        (scaling_points_cb, cb_multipliers),
+        (scaling_points_cr, cr_multipliers),
+    ] = chroma;
+
+    let scaling_shift = r.read_bits(2)? as u8 + 8;
+    let ar_coeff_lag = r.read_bits(2)? as u8;
+    let num_pos_luma = 2 * usize::from(ar_coeff_lag) * usize::from(ar_coeff_lag + 1);
+    let has_luma = !scaling_points_y.is_empty();
+    let ar_coeffs_y = read_coeffs(r, has_luma, num_pos_luma, num_pos_luma)?;
+    let num_pos_chroma = num_pos_luma + usize::from(has_luma);
+    let ar_coeffs_cb = read_coeffs(
+        r,
+        chroma_scaling_from_luma || !scaling_points_cb.is_empty(),
+        num_pos_chroma,
+        num_pos_luma + 1,
+    )?;
+    let ar_coeffs_cr = read_coeffs(
+        r,
+        chroma_scaling_from_luma || !scaling_points_cr.is_empty(),
+        num_pos_chroma,
+        num_pos_luma + 1,
+    )?;
+    let ar_coeff_shift = r.read_bits(2)? as u8 + 6;
+    let grain_scale_shift = r.read_bits(2)? as u8;
+    let mut read_multipliers = |points: &ArrayVec<[u8; 2], NUM_UV_POINTS>,
+                                predicted: Option<(u8, u8, u16)>|
+     -> Result<(u8, u8, u16)> {
+        if let Some(predicted) = predicted {
+            return Ok(predicted);
+        }
+        if points.is_empty() {
+            return Ok((0, 0, 0));
+        }
+        Ok((
+            r.read_bits(8)? as u8,
+            r.read_bits(8)? as u8,
+            r.read_bits(9)? as u16,
+        ))
+    };
+    let (cb_mult, cb_luma_mult, cb_offset) = read_multipliers(&scaling_points_cb, cb_multipliers)?;
+    let (cr_mult, cr_luma_mult, cr_offset) = read_multipliers(&scaling_points_cr, cr_multipliers)?;
+    let overlap_flag = r.read_bit()?;
+    // limit_output_range
+    let clip_to_restricted_range = r.read_bit()?;
+
+    let set = Afgs1FilmGrainSet {
+        set_index,
+        segment: GrainTableSegment {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y,
+            scaling_points_cb,
+            scaling_points_cr,
+            scaling_shift,
+            ar_coeff_lag,
+            ar_coeffs_y,
+            ar_coeffs_cb,
+            ar_coeffs_cr,
+            ar_coeff_shift,
+            cb_mult,
+            cb_luma_mult,
+            cb_offset,
+            cr_mult,
+            cr_luma_mult,
+            cr_offset,
+            overlap_flag,
+            chroma_scaling_from_luma,
+            grain_scale_shift,
+            random_seed,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range,
+            comments: Vec::new(),
+        },
+        width,
+        height,
+        color_config,
+        video_signal,
+    };
+    Ok(Some((set, true)))
+}
+
+/// Reads scaling points coded as increments. Chroma planes signal
+/// the bit sizes and an offset even without scaling points.
+fn read_points<const N: usize>(
+    r: &mut BitReader,
+    chroma: bool,
+    label: &str,
+) -> Result<ArrayVec<[u8; 2], N>> {
+    let count = r.read_bits(4)? as usize;
+    ensure!(
+        count <= N,
+        "{label} scaling point count must be at most {N}, got {count}"
+    );
+    let mut points = ArrayVec::new();
+    if count == 0 && !chroma {
+        return Ok(points);
+    }
+    let bits_increment = r.read_bits(3)? + 1;
+    let bits_scaling = r.read_bits(2)? + 5;
+    let offset = if chroma { r.read_bits(8)? } else { 0 };
+    let mut value = 0;
+    for _ in 0..count {
+        value += r.read_bits(bits_increment)?;
+        ensure!(
+            value <= 255,
+            "{label} scaling point values must be at most 255"
+        );
+        let scaling = r.read_bits(bits_scaling)? + offset;
+        ensure!(scaling <= 255, "{label} scaling values must be at most 255");
+        points.push([value as u8, scaling as u8]);
+    }
+    Ok(points)
+}
+
+/// Reads scaling points predicted from those of the reference set, which
+/// keep their values and scale, offset and refine the reference scaling.
+fn predict_points<const N: usize>(
+    r: &mut BitReader,
+    reference: &ArrayVec<[u8; 2], N>,
+) -> Result<ArrayVec<[u8; 2], N>> {
+    let scale = r.read_bits(9)? as i32 - 256;
+    let offset = r.read_bits(9)? as i32 - 256;
+    let bits_residual = r.read_bits(3)?;
+    let mut residuals = ArrayVec::<i32, N>::new();
+    let mut granularity = 0;
+    if bits_residual > 0 {
+        for _ in reference {
+            residuals.push(r.read_bits(bits_residual)? as i32 - (1 << (bits_residual - 1)));
+        }
+        granularity = r.read_bits(3)? as i32;
+    }
+    let residuals = residuals.into_iter().chain(std::iter::repeat(0));
+    Ok(reference
+        .iter()
+        .zip(residuals)
+        .map(|(&[value, scaling], residual)| {
+            let predicted = ((i32::from(scaling) * scale + 8) >> 4) + offset;
+            [
+                value,
+                (predicted + residual * granularity).clamp(0, 255) as u8,
+            ]
+        })
+        .collect())
+}
+
+/// Reads `count` coefficients if `present` is set, padding the result with
+/// zeros to `len` coefficients.
+fn read_coeffs<const N: usize>(
+    r: &mut BitReader,
+    present: bool,
+    count: usize,
+    len: usize,
+) -> Result<ArrayVec<i8, N>> {
+    let mut coeffs = ArrayVec::new();
+    if present {
+        let ar_coeff_bits = r.read_bits(2)? + 5;
+        for _ in 0..count {
+            let coeff = r.read_bits(ar_coeff_bits)? as i32 - (1 << (ar_coeff_bits - 1));
+            coeffs.push(coeff as i8);
+        }
+    }
+    while coeffs.len() < len {
+        coeffs.push(0);
+    }
+    Ok(coeffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [64, 60], [255, 40]]),
+            scaling_points_cb: ArrayVec::from_iter([[0, 30], [255, 35]]),
+            scaling_points_cr: ArrayVec::from_iter([[128, 12]]),
+            scaling_shift: 10,
+            ar_coeff_lag: 1,
+            ar_coeffs_y: ArrayVec::from_iter([4, -3, 2, -1]),
+            ar_coeffs_cb: ArrayVec::from_iter([1, 0, -2, 3, 100]),
+            ar_coeffs_cr: ArrayVec::from_iter([0, 0, 0, 0, -128]),
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 100,
+            cr_luma_mult: 64,
+            cr_offset: 300,
+            overlap_flag: true,
+            grain_scale_shift: 1,
+            random_seed: 1234,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    fn sets() -> [Afgs1FilmGrainSet; 2] {
+        [
+            Afgs1FilmGrainSet {
+                set_index: 3,
+                segment: GrainTableSegment {
+                    clip_to_restricted_range: true,
+                    ..segment()
+                },
+                width: 1920,
+                height: 1080,
+                color_config: ColorConfig {
+                    mono_chrome: false,
+                    subsampling_x: true,
+                    subsampling_y: true,
+                },
+                video_signal: Some(Afgs1VideoSignal {
+                    bit_depth: 10,
+                    color_description: Some(Afgs1ColorDescription {
+                        color_primaries: 9,
+                        transfer_characteristics: 16,
+                        matrix_coefficients: 9,
+                        full_range: false,
+                    }),
+                }),
+            },
+            Afgs1FilmGrainSet {
+                set_index: 0,
+                segment: GrainTableSegment {
+                    scaling_points_cb: ArrayVec::new(),
+                    scaling_points_cr: ArrayVec::new(),
+                    ar_coeffs_cb: ArrayVec::from_iter([0; 5]),
+                    ar_coeffs_cr: ArrayVec::from_iter([0; 5]),
+                    cb_mult: 0,
+                    cb_luma_mult: 0,
+                    cb_offset: 0,
+                    cr_mult: 0,
+                    cr_luma_mult: 0,
+                    cr_offset: 0,
+                    ..segment()
+                },
+                width: 3840,
+                height: 2160,
+                color_config: ColorConfig {
+                    mono_chrome: true,
+                    subsampling_x: true,
+                    subsampling_y: true,
+                },
+                video_signal: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let sets = sets();
+        let payload = write_afgs1_payload(&sets).expect("valid sets");
+        assert!(payload.starts_with(&[0xb5, 0x58, 0x90, 0x01]));
+        let read = read_afgs1_payload(&payload, &[]).expect("valid payload");
+        assert_eq!(read, sets);
+    }
+
+    #[test]
+    fn disabled_payload() {
+        let payload = write_afgs1_payload(&[]).expect("no sets");
+        assert_eq!(payload, [0xb5, 0x58, 0x90, 0x01, 0x00]);
+        let read = read_afgs1_payload(&payload, &sets()).expect("valid payload");
+        assert!(read.is_empty());
+    }
+
+    /// A payload with a single set which only updates the random seed.
+    fn seed_update(set_index: u32, random_seed: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        for byte in T35_HEADER {
+            w.write_bits(u32::from(byte), 8);
+        }
+        // afgs1_enable_flag, reserved bits and num_film_grain_sets_minus_1
+        w.write_bits(0x80, 8);
+        // A 4 byte set, with apply_grain set and update_grain unset
+        w.write_bit(false);
+        w.write_bits(4, 8);
+        w.write_bits(set_index, 3);
+        w.write_bit(true);
+        w.write_bits(random_seed, 16);
+        w.write_bit(false);
+        w.into_bytes()
+    }
+
+    #[test]
+    fn reuses_previous_parameters() {
+        let previous = sets();
+        let read = read_afgs1_payload(&seed_update(3, 777), &previous).expect("valid payload");
+        let [set] = read.as_slice() else {
+            panic!("expected one set, got {read:?}");
+        };
+        let [expected, _] = previous;
+        assert_eq!(
+            *set,
+            Afgs1FilmGrainSet {
+                segment: GrainTableSegment {
+                    random_seed: 777,
+                    ..expected.segment.clone()
+                },
+                ..expected
+            }
+        );
+
+        assert!(read_afgs1_payload(&seed_update(5, 777), &sets()).is_err());
+    }
+
+    #[test]
+    fn predicts_scaling_from_first_set() {
+        let [first, _] = sets();
+        let mut payload = write_afgs1_payload(std::slice::from_ref(&first)).expect("valid set");
+        // Increase num_film_grain_sets_minus_1 to 1
+        let header = payload.get_mut(4).expect("enable flag");
+        *header |= 1;
+
+        // A luma only set with index 1, predicting luma from the first set
+        let mut w = BitWriter::new();
+        w.write_bits(1, 3);
+        w.write_bit(true);
+        w.write_bits(99, 16);
+        w.write_bit(true);
+        // apply_units_log2, width and height
+        w.write_bits(4, 4);
+        w.write_bits(40, 12);
+        w.write_bits(30, 12);
+        // luma_only, video_signal_characteristics_flag, predict_scaling
+        // and predict_y_scaling
+        w.write_bits(0b1011, 4);
+        // A scale of 2, an offset of -10, 2 bit residuals and a granularity
+        // of 3
+        w.write_bits(256 + 32, 9);
+        w.write_bits(256 - 10, 9);
+        w.write_bits(2, 3);
+        for residual in [2, 3, 0] {
+            w.write_bits(residual, 2);
+        }
+        w.write_bits(3, 3);
+        // scaling_shift, lag, ar_coeff_shift, grain_scale_shift
+        w.write_bits(0, 2);
+        w.write_bits(0, 2);
+        w.write_bits(0, 2);
+        w.write_bits(0, 2);
+        // overlap_flag and limit_output_range
+        w.write_bits(0, 2);
+        let mut set = BitWriter::new();
+        set.write_bit(false);
+        set.write_bits((9 + w.bit_len()).div_ceil(8) as u32, 8);
+        for byte in w.into_bytes() {
+            set.write_bits(u32::from(byte), 8);
+        }
+        payload.extend(set.into_bytes());
+
+        let read = read_afgs1_payload(&payload, &[]).expect("valid payload");
+        let [_, predicted] = read.as_slice() else {
+            panic!("expected two sets, got {read:?}");
+        };
+        assert_eq!((predicted.width, predicted.height), (640, 480));
+        // ((scaling * 32 + 8) >> 4) - 10 + (residual - 2) * 3
+        assert_eq!(
+            predicted.segment.scaling_points_y.as_slice(),
+            [[0, 30], [64, 113], [255, 64]]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_sets() {
+        assert!(read_afgs1_payload(&[0xb5, 0x00, 0x3c, 0x00, 0x00], &[]).is_err());
+
+        let [first, second] = sets();
+        let duplicate = Afgs1FilmGrainSet {
+            set_index: 3,
+            ..second
+        };
+        assert!(write_afgs1_payload(&[first.clone(), duplicate]).is_err());
+        let odd_size = Afgs1FilmGrainSet {
+            width: 4097,
+            ..first.clone()
+        };
+        assert!(write_afgs1_payload(&[odd_size]).is_err());
+        let unsorted = Afgs1FilmGrainSet {
+            segment: GrainTableSegment {
+                scaling_points_y: ArrayVec::from_iter([[64, 20], [0, 40]]),
+                ..first.segment.clone()
+            },
+            ..first
+        };
+        assert!(write_afgs1_payload(&[unsorted]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{Result, bail};
+
+/// Writes values most significant bit first, as used by the
+/// `f(n)` descriptor of the AV1 specification.
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    /// Creates an empty writer.
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            bit_len: 0,
+        }
+    }
+
+    /// Writes a single bit.
+    #[inline]
+    pub fn write_bit(&mut self, bit: bool) {
+        let bit_offset = self.bit_len % 8;
+        if bit_offset == 0 {
+            self.data.push(0);
+        }
+        if bit && let Some(byte) = self.data.last_mut() {
+            *byte |= 0x80 >> bit_offset;
+        }
+        self.bit_len += 1;
+    }
+
+    /// Writes the low `bits` bits of `value`, most significant bit first.
+    ///
+    /// # Panics
+    ///
+    /// - If `bits` is greater than 32
+    /// - If `value` does not fit in `bits` bits
+    #[inline]
+    pub fn write_bits(&mut self, value: u32, bits: u32) {
+        assert!(bits <= 32, "cannot write more than 32 bits at once");
+        assert!(
+            bits == 32 || value >> bits == 0,
+            "{value} does not fit in {bits} bits"
+        );
+        for i in (0..bits).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Pads the output with zero bits up to the next byte boundary.
+    #[inline]
+    pub fn byte_align(&mut self) {
+        self.bit_len = self.data.len() * 8;
+    }
+
+    /// The number of bits written so far.
+    #[inline]
+    #[must_use]
+    pub const fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// Returns the written bits, padded with zero bits
+    /// up to the next byte boundary.
+    #[inline]
+    #[must_use]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads values most significant bit first, as used by the
+/// `f(n)` descriptor of the AV1 specification.
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Creates a reader positioned at the first bit of `data`.
+    #[inline]
+    #[must_use]
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Reads a single bit.
+    ///
+    /// # Errors
+    ///
+    /// - If there are no bits left to read
+    #[inline]
+    pub fn read_bit(&mut self) -> Result<bool> {
+        let Some(byte) = self.data.get(self.position / 8) else {
+            bail!("Unexpected end of data at bit {}", self.position);
+        };
+        let bit = (byte >> (7 - self.position % 8)) & 1 == 1;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    /// Reads a `bits`-bit unsigned value, most significant bit first.
+    ///
+    /// # Errors
+    ///
+    /// - If there are fewer than `bits` bits left to read
+    ///
+    /// # Panics
+    ///
+    /// - If `bits` is greater than 32
+    #[inline]
+    pub fn read_bits(&mut self, bits: u32) -> Result<u32> {
+        assert!(bits <= 32, "cannot read more than 32 bits at once");
+        let mut value = 0u64;
+        for _ in 0..bits {
+            value = (value << 1) | u64::from(self.read_bit()?);
+        }
+        Ok(value as u32)
+    }
+
+    /// Reads a `bits`-bit signed value, as described by the `su(n)`
+    /// descriptor.
+    ///
+    /// # Errors
+    ///
+    /// - If there are fewer than `bits` bits left to read
+    ///
+    /// # Panics
+    ///
+    /// - If `bits` is 0 or greater than 32
+    #[inline]
+    pub fn read_su(&mut self, bits: u32) -> Result<i32> {
+        assert!(bits > 0, "signed values need at least one bit");
+        let value = i64::from(self.read_bits(bits)?);
+        let sign_mask = 1 << (bits - 1);
+        Ok(if value & sign_mask == 0 {
+            value as i32
+        } else {
+            (value - 2 * sign_mask) as i32
+        })
+    }
+
+    /// Reads a value in the range `0..n`, as described by the `ns(n)`
+    /// descriptor.
+    ///
+    /// # Errors
+    ///
+    /// - If the data ends before the value
+    ///
+    /// # Panics
+    ///
+    /// - If `n` is 0
+    #[inline]
+    pub fn read_ns(&mut self, n: u32) -> Result<u32> {
+        assert!(n > 0, "ns(n) requires a non-empty range");
+        let w = n.ilog2() + 1;
+        let m = (1 << w) - n;
+        let v = self.read_bits(w - 1)?;
+        if v < m {
+            return Ok(v);
+        }
+        let extra_bit = u32::from(self.read_bit()?);
+        Ok((v << 1) - m + extra_bit)
+    }
+
+    /// Reads a variable length unsigned value, as described by the `uvlc()`
+    /// descriptor.
+    ///
+    /// # Errors
+    ///
+    /// - If the data ends before the value
+    #[inline]
+    pub fn read_uvlc(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+        }
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+        let value = self.read_bits(leading_zeros)?;
+        Ok(value + ((1 << leading_zeros) - 1))
+    }
+
+    /// Skips to the next byte boundary.
+    #[inline]
+    pub const fn byte_align(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+
+    /// The number of bits read so far.
+    #[inline]
+    #[must_use]
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_most_significant_bit_first() {
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_bits(0b010, 3);
+        writer.write_bits(0x1ff, 9);
+
+        assert_eq!(writer.bit_len(), 13);
+        assert_eq!(writer.into_bytes(), [0b1010_1111, 0b1111_1000]);
+    }
+
+    #[test]
+    fn byte_align_pads_with_zeros() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b11, 2);
+        writer.byte_align();
+        writer.write_bits(0xff, 8);
+
+        assert_eq!(writer.bit_len(), 16);
+        assert_eq!(writer.into_bytes(), [0b1100_0000, 0xff]);
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let mut writer = BitWriter::new();
+        writer.write_bit(false);
+        writer.write_bits(0x1234, 16);
+        writer.write_bits(5, 3);
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data);
+        assert!(!reader.read_bit().expect("enough data"));
+        assert_eq!(reader.read_bits(16).expect("enough data"), 0x1234);
+        assert_eq!(reader.read_bits(3).expect("enough data"), 5);
+        assert_eq!(reader.position(), 20);
+        reader.byte_align();
+        assert_eq!(reader.position(), 24);
+        assert!(reader.read_bit().is_err());
+    }
+
+    #[test]
+    fn reads_spec_descriptors() {
+        let mut writer = BitWriter::new();
+        // su(7) of -3
+        writer.write_bits(0b111_1101, 7);
+        // ns(5) of 1, which needs 2 bits, and of 4, which needs 3
+        writer.write_bits(0b01, 2);
+        writer.write_bits(0b111, 3);
+        // uvlc() of 4
+        writer.write_bits(0b00101, 5);
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data);
+        assert_eq!(reader.read_su(7).expect("enough data"), -3);
+        assert_eq!(reader.read_ns(5).expect("enough data"), 1);
+        assert_eq!(reader.read_ns(5).expect("enough data"), 4);
+        assert_eq!(reader.read_uvlc().expect("enough data"), 4);
+        assert_eq!(reader.position(), 17);
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Result, ensure};
+use num_rational::Rational64;
+
+use super::{
+    ObuStreamFormat,
+    headers::HeaderParser,
+    ivf::read_ivf,
+    obu::{Obu, annexb_temporal_units, section5_obus},
+};
+use crate::{GrainTableSegment, OPEN_END_TIME};
+
+/// Extracts the film grain parameters of every shown frame in an AV1 IVF
+/// file, returning them as grain table segments.
+///
+/// Timestamps are derived from the presentation timestamps and timebase of
+/// the IVF file. Consecutive frames whose parameters only differ in
+/// `random_seed`, which libaom changes on every frame, are merged into
+/// a single segment using the seed of the first frame. Frames without
+/// film grain are covered by segments with `apply_grain` unset, so that
+/// writing the table and rewriting the stream with it keeps them.
+///
+/// # Errors
+///
+/// - If the data is not a valid IVF file containing AV1
+/// - If an OBU or frame header cannot be parsed, such as when the stream
+///   uses features which are not supported by this parser
+#[inline]
+pub fn extract_grain_table_ivf(data: &[u8]) -> Result<Vec<GrainTableSegment>> {
+    let (header, frames) = read_ivf(data)?;
+    let mut extractor = Extractor::default();
+    for frame in frames {
+        let time = header.timestamp(frame.pts);
+        for obu in section5_obus(frame.data) {
+            extractor.add_obu(&obu?, time)?;
+        }
+    }
+    Ok(extractor.finish())
+}
+
+/// Extracts the film grain parameters of every shown frame in a raw AV1
+/// OBU stream, returning them as grain table segments.
+///
+/// Raw streams do not carry timestamps, so frames are assumed to be
+/// shown at a constant `fps`. Segments are merged as described in
+/// [`extract_grain_table_ivf`].
+///
+/// # Errors
+///
+/// - If `fps` is not positive
+/// - If an OBU or frame header cannot be parsed, such as when the stream
+///   uses features which are not supported by this parser
+#[inline]
+pub fn extract_grain_table_obu(
+    data: &[u8],
+    format: ObuStreamFormat,
+    fps: Rational64,
+) -> Result<Vec<GrainTableSegment>> {
+    ensure!(
+        *fps.numer() > 0 && *fps.denom() > 0,
+        "Frame rate must be positive"
+    );
+    let mut extractor = Extractor::default();
+    let mut frame_count = 0u64;
+    let mut add_obu = |obu: &Obu| -> Result<()> {
+        let time =
+            u128::from(frame_count) * 10_000_000 * (*fps.denom() as u128) / (*fps.numer() as u128);
+        if extractor.add_obu(obu, u64::try_from(time).unwrap_or(u64::MAX))? {
+            frame_count += 1;
+        }
+        Ok(())
+    };
+    match format {
+        ObuStreamFormat::Section5 => {
+            for obu in section5_obus(data) {
+                add_obu(&obu?)?;
+            }
+        }
+        ObuStreamFormat::AnnexB => {
+            for obu in annexb_temporal_units(data)?.iter().flatten() {
+                add_obu(obu)?;
+            }
+        }
+    }
+    Ok(extractor.finish())
+}
+
+/// Collects the film grain parameters of shown frames into segments.
+#[derive(Debug, Default)]
+struct Extractor {
+    parser: HeaderParser,
+    segments: Vec<GrainTableSegment>,
+    current: Option<GrainTableSegment>,
+}
+
+impl Extractor {
+    /// Parses `obu`, returning whether it caused a frame to be shown.
+    fn add_obu(&mut self, obu: &Obu, time: u64) -> Result<bool> {
+        let Some(header) = self.parser.parse_obu(obu)? else {
+            return Ok(false);
+        };
+        if !header.shown {
+            return Ok(false);
+        }
+
+        // Grain tables cannot refer to reference frames, so parameters
+        // loaded from one are stored as if they were signalled again.
+        let film_grain = header.film_grain.map_or_else(
+            || GrainTableSegment {
+                apply_grain: false,
+                ..GrainTableSegment::default()
+            },
+            |film_grain| GrainTableSegment {
+                update_grain: true,
+                film_grain_params_ref_idx: None,
+                ..film_grain
+            },
+        );
+        if let Some(current) = &self.current
+            && same_params(current, &film_grain)
+        {
+            return Ok(true);
+        }
+        self.end_segment(time);
+        self.current = Some(GrainTableSegment {
+            start_time: time,
+            ..film_grain
+        });
+        Ok(true)
+    }
+
+    fn end_segment(&mut self, time: u64) {
+        if let Some(segment) = self.current.take()
+            && time > segment.start_time
+        {
+            self.segments.push(GrainTableSegment {
+                end_time: time,
+                ..segment
+            });
+        }
+    }
+
+    fn finish(mut self) -> Vec<GrainTableSegment> {
+        self.end_segment(OPEN_END_TIME);
+        self.segments
+    }
+}
+
+/// Whether two segments have the same parameters, ignoring their times
+/// and random seeds.
+fn same_params(a: &GrainTableSegment, b: &GrainTableSegment) -> bool {
+    *a == GrainTableSegment {
+        start_time: a.start_time,
+        end_time: a.end_time,
+        random_seed: a.random_seed,
+        ..b.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+    use crate::bitstream::test_stream::{
+        Grain, inter_frame, ivf, key_frame, sequence_header, temporal_delimiter,
+    };
+
+    fn segment(scaling_shift: u8) -> GrainTableSegment {
+        GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+            scaling_shift,
+            ar_coeff_shift: 7,
+            overlap_flag: true,
+            random_seed: 100,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    fn temporal_unit(obus: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = temporal_delimiter();
+        for obu in obus {
+            data.extend_from_slice(obu);
+        }
+        data
+    }
+
+    #[test]
+    fn merges_frames_which_only_differ_in_seed() {
+        let first = segment(8);
+        let second = GrainTableSegment {
+            random_seed: 200,
+            ..segment(8)
+        };
+        let third = segment(10);
+        let data = ivf(&[
+            temporal_unit(&[sequence_header(), key_frame(Grain::Update(&first))]),
+            temporal_unit(&[inter_frame(1, true, 0x01, Grain::Update(&second))]),
+            temporal_unit(&[inter_frame(2, true, 0x01, Grain::Update(&third))]),
+            temporal_unit(&[inter_frame(3, true, 0x01, Grain::None)]),
+            temporal_unit(&[inter_frame(
+                4,
+                true,
+                0x00,
+                Grain::Reference {
+                    random_seed: 300,
+                    ref_idx: 1,
+                },
+            )]),
+        ]);
+
+        let table = extract_grain_table_ivf(&data).expect("valid stream");
+        assert_eq!(
+            table,
+            [
+                GrainTableSegment {
+                    start_time: 0,
+                    end_time: 800_000,
+                    ..first.clone()
+                },
+                GrainTableSegment {
+                    start_time: 800_000,
+                    end_time: 1_200_000,
+                    ..third
+                },
+                GrainTableSegment {
+                    start_time: 1_200_000,
+                    end_time: 1_600_000,
+                    apply_grain: false,
+                    ..GrainTableSegment::default()
+                },
+                GrainTableSegment {
+                    start_time: 1_600_000,
+                    end_time: i64::MAX as u64,
+                    random_seed: 300,
+                    ..first
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn extracted_tables_survive_rewriting() {
+        let grain = segment(8);
+        let data = ivf(&[
+            temporal_unit(&[sequence_header(), key_frame(Grain::None)]),
+            temporal_unit(&[inter_frame(1, true, 0x01, Grain::Update(&grain))]),
+            temporal_unit(&[inter_frame(2, true, 0x01, Grain::None)]),
+        ]);
+        let table = extract_grain_table_ivf(&data).expect("valid stream");
+        assert_eq!(
+            table
+                .iter()
+                .map(|segment| segment.apply_grain)
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        #[cfg(all(feature = "create", feature = "parse"))]
+        assert_eq!(
+            crate::parse_grain_table(&crate::grain_table_to_string(&table))
+                .expect("written table is valid"),
+            table
+        );
+
+        let rewritten = crate::rewrite_film_grain_ivf(&data, Some(&table)).expect("valid stream");
+        assert_eq!(
+            extract_grain_table_ivf(&rewritten).expect("valid stream"),
+            table
+        );
+    }
+
+    #[test]
+    fn hidden_frames_are_not_output() {
+        let key = segment(8);
+        let hidden = segment(10);
+        let data = [
+            temporal_unit(&[sequence_header(), key_frame(Grain::Update(&key))]),
+            temporal_unit(&[
+                inter_frame(2, false, 0x02, Grain::Update(&hidden)),
+                inter_frame(1, true, 0x01, Grain::None),
+            ]),
+        ]
+        .concat();
+
+        let table =
+            extract_grain_table_obu(&data, ObuStreamFormat::Section5, Rational64::new(25, 1))
+                .expect("valid stream");
+        assert_eq!(
+            table,
+            [
+                GrainTableSegment {
+                    start_time: 0,
+                    end_time: 400_000,
+                    ..key
+                },
+                GrainTableSegment {
+                    start_time: 400_000,
+                    end_time: OPEN_END_TIME,
+                    apply_grain: false,
+                    ..GrainTableSegment::default()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_frames_before_sequence_header() {
+        let data = temporal_unit(&[key_frame(Grain::None)]);
+        assert!(
+            extract_grain_table_obu(&data, ObuStreamFormat::Section5, Rational64::new(25, 1))
+                .is_err()
+        );
+        assert!(extract_grain_table_ivf(&data).is_err());
+    }
+}
@@ -0,0 +1,683 @@
+use anyhow::{Context, Result, ensure};
+use arrayvec::ArrayVec;
+
+use super::{BitReader, BitWriter, ColorConfig, FrameType};
+use crate::{GrainTableSegment, NUM_UV_POINTS, NUM_Y_POINTS};
+
+/// The number of reference frame slots of an AV1 decoder, each of which
+/// stores the film grain parameters of the frame last saved to it.
+pub const NUM_REF_FRAMES: usize = 8;
+
+/// Writes `segment` to `writer` using the `film_grain_params()` syntax of
+/// the AV1 frame header, and returns the number of bits written.
+///
+/// This assumes `film_grain_params_present` is set in the sequence header
+/// and the frame is shown or showable, as otherwise `film_grain_params()`
+/// is not signalled. Segments with `apply_grain` unset are written as that
+/// single bit. Segments of inter frames with `update_grain` unset and a
+/// `film_grain_params_ref_idx` are written as a reference to the parameters
+/// of that frame, and every other segment is written with `update_grain`
+/// set.
+///
+/// Fields which are not signalled for the given stream are not written:
+/// chroma parameters are dropped for monochrome streams, and the luma
+/// correlation coefficient of each chroma plane is dropped when there are
+/// no luma scaling points. Missing auto-regressive coefficients are written
+/// as zero.
+///
+/// # Errors
+///
+/// - If the segment contains parameters outside of the ranges allowed by the
+///   AV1 specification
+/// - If the segment has chroma scaling points, but no luma scaling points,
+///   for 4:2:0 video. This cannot be signalled in AV1.
+/// - If the segment references a frame slot which does not exist
+#[inline]
+pub fn write_film_grain_params(
+    segment: &GrainTableSegment,
+    color_config: &ColorConfig,
+    frame_type: FrameType,
+    writer: &mut BitWriter,
+) -> Result<usize> {
+    if !segment.apply_grain {
+        writer.write_bit(false);
+        return Ok(1);
+    }
+    let ref_idx = segment
+        .film_grain_params_ref_idx
+        .filter(|_| frame_type == FrameType::Inter && !segment.update_grain);
+    if let Some(ref_idx) = ref_idx {
+        ensure!(
+            usize::from(ref_idx) < NUM_REF_FRAMES,
+            "film_grain_params_ref_idx must be less than {NUM_REF_FRAMES}, got {ref_idx}"
+        );
+    } else {
+        validate_for_writing(segment)?;
+    }
+
+    let start = writer.bit_len();
+    // apply_grain
+    writer.write_bit(true);
+    writer.write_bits(u32::from(segment.random_seed), 16);
+    if frame_type == FrameType::Inter {
+        // update_grain
+        writer.write_bit(ref_idx.is_none());
+    }
+    if let Some(ref_idx) = ref_idx {
+        writer.write_bits(u32::from(ref_idx), 3);
+        return Ok(writer.bit_len() - start);
+    }
+
+    write_points(writer, &segment.scaling_points_y);
+    let chroma_scaling_from_luma = !color_config.mono_chrome && segment.chroma_scaling_from_luma;
+    if !color_config.mono_chrome {
+        writer.write_bit(chroma_scaling_from_luma);
+    }
+    let signal_chroma_points = !color_config.mono_chrome
+        && !chroma_scaling_from_luma
+        && (!color_config.subsampling_x
+            || !color_config.subsampling_y
+            || !segment.scaling_points_y.is_empty());
+    let (cb_points, cr_points): (&[[u8; 2]], &[[u8; 2]]) = if signal_chroma_points {
+        write_points(writer, &segment.scaling_points_cb);
+        write_points(writer, &segment.scaling_points_cr);
+        (&segment.scaling_points_cb, &segment.scaling_points_cr)
+    } else {
+        ensure!(
+            color_config.mono_chrome
+                || chroma_scaling_from_luma
+                || (segment.scaling_points_cb.is_empty() && segment.scaling_points_cr.is_empty()),
+            "Chroma scaling points require luma scaling points for 4:2:0 video"
+        );
+        (&[], &[])
+    };
+
+    writer.write_bits(u32::from(segment.scaling_shift - 8), 2);
+    writer.write_bits(u32::from(segment.ar_coeff_lag), 2);
+    let num_pos_luma =
+        2 * usize::from(segment.ar_coeff_lag) * usize::from(segment.ar_coeff_lag + 1);
+    let num_pos_chroma = if segment.scaling_points_y.is_empty() {
+        num_pos_luma
+    } else {
+        write_coeffs(writer, &segment.ar_coeffs_y, num_pos_luma);
+        num_pos_luma + 1
+    };
+    if chroma_scaling_from_luma || !cb_points.is_empty() {
+        write_coeffs(writer, &segment.ar_coeffs_cb, num_pos_chroma);
+    }
+    if chroma_scaling_from_luma || !cr_points.is_empty() {
+        write_coeffs(writer, &segment.ar_coeffs_cr, num_pos_chroma);
+    }
+    writer.write_bits(u32::from(segment.ar_coeff_shift - 6), 2);
+    writer.write_bits(u32::from(segment.grain_scale_shift), 2);
+    if !cb_points.is_empty() {
+        writer.write_bits(u32::from(segment.cb_mult), 8);
+        writer.write_bits(u32::from(segment.cb_luma_mult), 8);
+        writer.write_bits(u32::from(segment.cb_offset), 9);
+    }
+    if !cr_points.is_empty() {
+        writer.write_bits(u32::from(segment.cr_mult), 8);
+        writer.write_bits(u32::from(segment.cr_luma_mult), 8);
+        writer.write_bits(u32::from(segment.cr_offset), 9);
+    }
+    writer.write_bit(segment.overlap_flag);
+    writer.write_bit(segment.clip_to_restricted_range);
+
+    Ok(writer.bit_len() - start)
+}
+
+/// Reads the `film_grain_params()` syntax of the AV1 frame header from
+/// `reader`, returning the grain which applies to the frame, or `None` if
+/// no grain is applied.
+///
+/// `reference_grain` holds the film grain parameters stored in each
+/// reference frame slot, which are used when the frame reuses the
+/// parameters of a reference frame by setting `update_grain` to 0. In that
+/// case, only the random seed is taken from the frame header, and the
+/// returned segment has `update_grain` unset and `film_grain_params_ref_idx`
+/// set to the slot it was loaded from. Slots of frames without grain hold a
+/// segment with `apply_grain` unset, the parameters stored by
+/// `reset_grain_params()`, and frames loading them have no grain. Slots
+/// which no frame has been stored in hold `None`.
+///
+/// If `film_grain_params_present` is not set, nothing is read. Callers
+/// should also not call this for frames which are neither shown nor
+/// showable, as `film_grain_params()` is not signalled for them.
+///
+/// The returned segment has `start_time` and `end_time` set to 0, and
+/// follows the conventions of grain table files: chroma coefficients
+/// always include the luma correlation coefficient, and coefficients
+/// which are not signalled are set to 0.
+///
+/// # Errors
+///
+/// - If the data ends before the end of `film_grain_params()`
+/// - If the parameters are not valid, such as scaling points
+///   exceeding the maximum count
+/// - If the frame reuses the parameters of a reference frame slot which no
+///   frame has been stored in
+#[inline]
+pub fn read_film_grain_params(
+    reader: &mut BitReader,
+    color_config: &ColorConfig,
+    frame_type: FrameType,
+    film_grain_params_present: bool,
+    reference_grain: &[Option<GrainTableSegment>; NUM_REF_FRAMES],
+) -> Result<Option<GrainTableSegment>> {
+    if !film_grain_params_present {
+        return Ok(None);
+    }
+
+    let apply_grain = reader.read_bit()?;
+    if !apply_grain {
+        return Ok(None);
+    }
+    let random_seed = reader.read_bits(16)? as u16;
+    let update_grain = frame_type != FrameType::Inter || reader.read_bit()?;
+    if !update_grain {
+        let ref_idx = reader.read_bits(3)? as u8;
+        let reference = reference_grain
+            .get(usize::from(ref_idx))
+            .and_then(Option::as_ref)
+            .with_context(|| {
+                format!("film_grain_params_ref_idx {ref_idx} refers to an empty reference slot")
+            })?;
+        if !reference.apply_grain {
+            return Ok(None);
+        }
+        return Ok(Some(GrainTableSegment {
+            random_seed,
+            update_grain: false,
+            film_grain_params_ref_idx: Some(ref_idx),
+            ..reference.clone()
+        }));
+    }
+
+    let scaling_points_y = read_points::<NUM_Y_POINTS>(reader, "luma")?;
+    let chroma_scaling_from_luma = !color_config.mono_chrome && reader.read_bit()?;
+    let signal_chroma_points = !color_config.mono_chrome
+        && !chroma_scaling_from_luma
+        && (!color_config.subsampling_x
+            || !color_config.subsampling_y
+            || !scaling_points_y.is_empty());
+    let (scaling_points_cb, scaling_points_cr) = if signal_chroma_points {
+        (
+            read_points::<NUM_UV_POINTS>(reader, "Cb")?,
+            read_points::<NUM_UV_POINTS>(reader, "Cr")?,
+        )
+    } else {
+        (ArrayVec::new(), ArrayVec::new())
+    };
+
+    let scaling_shift = reader.read_bits(2)? as u8 + 8;
+    let ar_coeff_lag = reader.read_bits(2)? as u8;
+    let num_pos_luma = 2 * usize::from(ar_coeff_lag) * usize::from(ar_coeff_lag + 1);
+    let has_luma = !scaling_points_y.is_empty();
+    let ar_coeffs_y = read_coeffs(reader, has_luma, num_pos_luma, num_pos_luma)?;
+    // Without luma grain, the luma correlation coefficient is not signalled.
+    let num_pos_chroma = num_pos_luma + usize::from(has_luma);
+    let ar_coeffs_cb = read_coeffs(
+        reader,
+        chroma_scaling_from_luma || !scaling_points_cb.is_empty(),
+        num_pos_chroma,
+        num_pos_luma + 1,
+    )?;
+    let ar_coeffs_cr = read_coeffs(
+        reader,
+        chroma_scaling_from_luma || !scaling_points_cr.is_empty(),
+        num_pos_chroma,
+        num_pos_luma + 1,
+    )?;
+    let ar_coeff_shift = reader.read_bits(2)? as u8 + 6;
+    let grain_scale_shift = reader.read_bits(2)? as u8;
+    let (cb_mult, cb_luma_mult, cb_offset) = if scaling_points_cb.is_empty() {
+        (0, 0, 0)
+    } else {
+        read_multipliers(reader)?
+    };
+    let (cr_mult, cr_luma_mult, cr_offset) = if scaling_points_cr.is_empty() {
+        (0, 0, 0)
+    } else {
+        read_multipliers(reader)?
+    };
+    let overlap_flag = reader.read_bit()?;
+    let clip_to_restricted_range = reader.read_bit()?;
+
+    Ok(Some(GrainTableSegment {
+        start_time: 0,
+        end_time: 0,
+        scaling_points_y,
+        scaling_points_cb,
+        scaling_points_cr,
+        scaling_shift,
+        ar_coeff_lag,
+        ar_coeffs_y,
+        ar_coeffs_cb,
+        ar_coeffs_cr,
+        ar_coeff_shift,
+        cb_mult,
+        cb_luma_mult,
+        cb_offset,
+        cr_mult,
+        cr_luma_mult,
+        cr_offset,
+        overlap_flag,
+        chroma_scaling_from_luma,
+        grain_scale_shift,
+        random_seed,
+        apply_grain: true,
+        update_grain: true,
+        film_grain_params_ref_idx: None,
+        clip_to_restricted_range,
+        comments: Vec::new(),
+    }))
+}
+
+pub(super) fn validate_for_writing(segment: &GrainTableSegment) -> Result<()> {
+    ensure!(
+        (8..=11).contains(&segment.scaling_shift),
+        "scaling_shift must be between 8 and 11"
+    );
+    ensure!(
+        segment.ar_coeff_lag <= 3,
+        "ar_coeff_lag must be between 0 and 3"
+    );
+    ensure!(
+        (6..=9).contains(&segment.ar_coeff_shift),
+        "ar_coeff_shift must be between 6 and 9"
+    );
+    ensure!(
+        segment.grain_scale_shift <= 3,
+        "grain_scale_shift must be between 0 and 3"
+    );
+    ensure!(
+        segment.cb_offset < 512 && segment.cr_offset < 512,
+        "cb_offset and cr_offset must be between 0 and 511"
+    );
+
+    Ok(())
+}
+
+fn write_points(writer: &mut BitWriter, points: &[[u8; 2]]) {
+    writer.write_bits(points.len() as u32, 4);
+    for &[value, scaling] in points {
+        writer.write_bits(u32::from(value), 8);
+        writer.write_bits(u32::from(scaling), 8);
+    }
+}
+
+/// Writes the first `count` coefficients, offset by 128.
+fn write_coeffs(writer: &mut BitWriter, coeffs: &[i8], count: usize) {
+    let coeffs = coeffs.iter().copied().chain(std::iter::repeat(0));
+    for coeff in coeffs.take(count) {
+        writer.write_bits((i32::from(coeff) + 128) as u32, 8);
+    }
+}
+
+fn read_points<const N: usize>(
+    reader: &mut BitReader,
+    label: &str,
+) -> Result<ArrayVec<[u8; 2], N>> {
+    let count = reader.read_bits(4)? as usize;
+    ensure!(
+        count <= N,
+        "{label} scaling point count must be at most {N}, got {count}"
+    );
+    let mut points = ArrayVec::new();
+    for _ in 0..count {
+        let value = reader.read_bits(8)? as u8;
+        let scaling = reader.read_bits(8)? as u8;
+        points.push([value, scaling]);
+    }
+    Ok(points)
+}
+
+/// Reads `count` coefficients offset by 128 if `present` is set, padding
+/// the result with zeros to `len` coefficients.
+fn read_coeffs<const N: usize>(
+    reader: &mut BitReader,
+    present: bool,
+    count: usize,
+    len: usize,
+) -> Result<ArrayVec<i8, N>> {
+    let mut coeffs = ArrayVec::new();
+    if present {
+        for _ in 0..count {
+            coeffs.push((reader.read_bits(8)? as i32 - 128) as i8);
+        }
+    }
+    while coeffs.len() < len {
+        coeffs.push(0);
+    }
+    Ok(coeffs)
+}
+
+fn read_multipliers(reader: &mut BitReader) -> Result<(u8, u8, u16)> {
+    let mult = reader.read_bits(8)? as u8;
+    let luma_mult = reader.read_bits(8)? as u8;
+    let offset = reader.read_bits(9)? as u16;
+    Ok((mult, luma_mult, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    const YUV420: ColorConfig = ColorConfig {
+        mono_chrome: false,
+        subsampling_x: true,
+        subsampling_y: true,
+    };
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            end_time: 100,
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+            scaling_points_cb: ArrayVec::from_iter([[128, 10]]),
+            scaling_shift: 11,
+            ar_coeff_lag: 1,
+            ar_coeffs_y: ArrayVec::from_iter([1, -1, 2, -2]),
+            ar_coeffs_cb: ArrayVec::from_iter([0, 0, 0, 4, 127]),
+            ar_coeffs_cr: ArrayVec::from_iter([0, 0, 0, 0, 0]),
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            overlap_flag: true,
+            random_seed: 0x1234,
+            clip_to_restricted_range: true,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    #[test]
+    fn writes_fields_in_frame_header_order() {
+        let mut writer = BitWriter::new();
+        let bits = write_film_grain_params(&segment(), &YUV420, FrameType::Inter, &mut writer)
+            .expect("valid segment");
+
+        let mut expected = BitWriter::new();
+        for (value, bits) in [
+            (1, 1),
+            (0x1234, 16),
+            (1, 1),
+            (2, 4),
+            (0, 8),
+            (20, 8),
+            (255, 8),
+            (40, 8),
+            (0, 1),
+            (1, 4),
+            (128, 8),
+            (10, 8),
+            (0, 4),
+            (3, 2),
+            (1, 2),
+            (129, 8),
+            (127, 8),
+            (130, 8),
+            (126, 8),
+            (128, 8),
+            (128, 8),
+            (128, 8),
+            (132, 8),
+            (255, 8),
+            (1, 2),
+            (0, 2),
+            (128, 8),
+            (192, 8),
+            (256, 9),
+            (1, 1),
+            (1, 1),
+        ] {
+            expected.write_bits(value, bits);
+        }
+        assert_eq!(bits, expected.bit_len());
+        assert_eq!(writer.into_bytes(), expected.into_bytes());
+    }
+
+    #[test]
+    fn update_grain_is_only_signalled_for_inter_frames() {
+        let mut key = BitWriter::new();
+        let mut inter = BitWriter::new();
+        let key_bits = write_film_grain_params(&segment(), &YUV420, FrameType::Key, &mut key)
+            .expect("valid segment");
+        let inter_bits = write_film_grain_params(&segment(), &YUV420, FrameType::Inter, &mut inter)
+            .expect("valid segment");
+
+        assert_eq!(inter_bits, key_bits + 1);
+    }
+
+    #[test]
+    fn monochrome_drops_chroma_fields() {
+        let mono = ColorConfig {
+            mono_chrome: true,
+            ..YUV420
+        };
+        let mut writer = BitWriter::new();
+        let bits = write_film_grain_params(&segment(), &mono, FrameType::Key, &mut writer)
+            .expect("valid segment");
+
+        // apply_grain, grain_seed, the luma points, the parameters and the
+        // luma coefficients, and the final flags.
+        assert_eq!(bits, 1 + 16 + (4 + 2 * 16) + 8 + 4 * 8 + 2);
+    }
+
+    #[test]
+    fn rejects_chroma_points_without_luma_points_for_420() {
+        let segment = GrainTableSegment {
+            scaling_points_y: ArrayVec::new(),
+            ..segment()
+        };
+        let mut writer = BitWriter::new();
+        assert!(write_film_grain_params(&segment, &YUV420, FrameType::Key, &mut writer).is_err());
+
+        let yuv444 = ColorConfig {
+            mono_chrome: false,
+            subsampling_x: false,
+            subsampling_y: false,
+        };
+        assert!(write_film_grain_params(&segment, &yuv444, FrameType::Key, &mut writer).is_ok());
+    }
+
+    fn read(
+        data: &[u8],
+        color_config: ColorConfig,
+        frame_type: FrameType,
+    ) -> Result<Option<GrainTableSegment>> {
+        let reference_grain = std::array::from_fn(|_| None);
+        read_film_grain_params(
+            &mut BitReader::new(data),
+            &color_config,
+            frame_type,
+            true,
+            &reference_grain,
+        )
+    }
+
+    #[test]
+    fn reads_written_params() {
+        let segment = GrainTableSegment {
+            start_time: 0,
+            end_time: 0,
+            ..segment()
+        };
+        for frame_type in [FrameType::Key, FrameType::Inter] {
+            let mut writer = BitWriter::new();
+            write_film_grain_params(&segment, &YUV420, frame_type, &mut writer)
+                .expect("valid segment");
+
+            let parsed = read(&writer.into_bytes(), YUV420, frame_type).expect("valid data");
+            assert_eq!(parsed, Some(segment.clone()));
+        }
+    }
+
+    #[test]
+    fn pads_chroma_coeffs_without_luma_points() {
+        let yuv444 = ColorConfig {
+            mono_chrome: false,
+            subsampling_x: false,
+            subsampling_y: false,
+        };
+        let segment = GrainTableSegment {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y: ArrayVec::new(),
+            ar_coeffs_y: ArrayVec::from_iter([0; 4]),
+            ar_coeffs_cb: ArrayVec::from_iter([1, 2, 3, 4, 0]),
+            ..segment()
+        };
+        let mut writer = BitWriter::new();
+        write_film_grain_params(&segment, &yuv444, FrameType::Key, &mut writer)
+            .expect("valid segment");
+
+        let parsed = read(&writer.into_bytes(), yuv444, FrameType::Key)
+            .expect("valid data")
+            .expect("grain is applied");
+        assert_eq!(parsed.ar_coeffs_y.as_slice(), [0; 4]);
+        assert_eq!(parsed.ar_coeffs_cb.as_slice(), [1, 2, 3, 4, 0]);
+        assert_eq!(parsed, segment);
+    }
+
+    #[test]
+    fn update_grain_loads_reference_with_new_seed() {
+        let mut reference_grain = std::array::from_fn(|_| None);
+        reference_grain[5] = Some(segment());
+
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_bits(0x4321, 16);
+        writer.write_bit(false);
+        writer.write_bits(5, 3);
+        let data = writer.into_bytes();
+
+        let parsed = read_film_grain_params(
+            &mut BitReader::new(&data),
+            &YUV420,
+            FrameType::Inter,
+            true,
+            &reference_grain,
+        )
+        .expect("valid data");
+        assert_eq!(
+            parsed,
+            Some(GrainTableSegment {
+                random_seed: 0x4321,
+                update_grain: false,
+                film_grain_params_ref_idx: Some(5),
+                ..segment()
+            })
+        );
+    }
+
+    #[test]
+    fn update_grain_rejects_empty_reference() {
+        let mut reference_grain = std::array::from_fn(|_| None);
+        reference_grain[5] = Some(segment());
+
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_bits(0x4321, 16);
+        writer.write_bit(false);
+        writer.write_bits(3, 3);
+        let data = writer.into_bytes();
+
+        let error = read_film_grain_params(
+            &mut BitReader::new(&data),
+            &YUV420,
+            FrameType::Inter,
+            true,
+            &reference_grain,
+        )
+        .expect_err("slot 3 is empty");
+        assert!(error.to_string().contains("film_grain_params_ref_idx 3"));
+    }
+
+    #[test]
+    fn writes_reference_to_reused_params() {
+        let segment = GrainTableSegment {
+            update_grain: false,
+            film_grain_params_ref_idx: Some(5),
+            ..segment()
+        };
+        let mut writer = BitWriter::new();
+        let len = write_film_grain_params(&segment, &YUV420, FrameType::Inter, &mut writer)
+            .expect("valid segment");
+        // apply_grain, grain_seed, update_grain and film_grain_params_ref_idx
+        assert_eq!(len, 1 + 16 + 1 + 3);
+
+        let mut reference_grain = std::array::from_fn(|_| None);
+        reference_grain[5] = Some(self::segment());
+        let parsed = read_film_grain_params(
+            &mut BitReader::new(&writer.into_bytes()),
+            &YUV420,
+            FrameType::Inter,
+            true,
+            &reference_grain,
+        )
+        .expect("valid data");
+        assert_eq!(parsed, Some(segment.clone()));
+
+        // Key frames cannot reference other frames, so the parameters are
+        // written in full.
+        let mut writer = BitWriter::new();
+        write_film_grain_params(&segment, &YUV420, FrameType::Key, &mut writer)
+            .expect("valid segment");
+        assert!(writer.bit_len() > 1 + 16 + 3);
+
+        let segment = GrainTableSegment {
+            film_grain_params_ref_idx: Some(8),
+            ..segment
+        };
+        assert!(
+            write_film_grain_params(&segment, &YUV420, FrameType::Inter, &mut BitWriter::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn no_grain_without_apply_grain_or_params_present() {
+        assert_eq!(
+            read(&[0], YUV420, FrameType::Key).expect("valid data"),
+            None
+        );
+
+        let reference_grain = std::array::from_fn(|_| None);
+        let mut reader = BitReader::new(&[]);
+        let parsed = read_film_grain_params(
+            &mut reader,
+            &YUV420,
+            FrameType::Key,
+            false,
+            &reference_grain,
+        )
+        .expect("nothing is read");
+        assert_eq!(parsed, None);
+
+        let disabled = GrainTableSegment {
+            apply_grain: false,
+            ..segment()
+        };
+        let mut writer = BitWriter::new();
+        let bits = write_film_grain_params(&disabled, &YUV420, FrameType::Inter, &mut writer)
+            .expect("valid segment");
+        assert_eq!(bits, 1);
+        assert_eq!(
+            read(&writer.into_bytes(), YUV420, FrameType::Inter).expect("valid data"),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut writer = BitWriter::new();
+        write_film_grain_params(&segment(), &YUV420, FrameType::Key, &mut writer)
+            .expect("valid segment");
+        let mut data = writer.into_bytes();
+        data.pop();
+
+        assert!(read(&data, YUV420, FrameType::Key).is_err());
+    }
+}
@@ -0,0 +1,1515 @@
+use std::ops::Range;
+
+use anyhow::{Context, Result, bail, ensure};
+
+use super::{
+    BitReader, ColorConfig, FrameType, NUM_REF_FRAMES,
+    obu::{
+        OBU_FRAME, OBU_FRAME_HEADER, OBU_REDUNDANT_FRAME_HEADER, OBU_SEQUENCE_HEADER,
+        OBU_TEMPORAL_DELIMITER, OBU_TILE_GROUP, Obu,
+    },
+    read_film_grain_params,
+};
+use crate::GrainTableSegment;
+
+/// The number of references used by an inter frame.
+const REFS_PER_FRAME: usize = 7;
+const PRIMARY_REF_NONE: usize = 7;
+const ALL_FRAMES: u32 = (1 << NUM_REF_FRAMES) - 1;
+/// The value of `seq_force_screen_content_tools` and `seq_force_integer_mv`
+/// which leaves the choice to each frame.
+const SELECT: u8 = 2;
+const WARPEDMODEL_PREC_BITS: u32 = 16;
+const MAX_TILE_WIDTH: u32 = 4096;
+const MAX_TILE_AREA: u32 = 4096 * 2304;
+const MAX_TILE_COLS: u32 = 64;
+const MAX_TILE_ROWS: u32 = 64;
+/// The number of bits, whether the value is signed, and the maximum
+/// value of each segmentation feature.
+const SEGMENTATION_FEATURES: [(u32, bool, i32); 8] = [
+    (8, true, 255),
+    (6, true, 63),
+    (6, true, 63),
+    (6, true, 63),
+    (6, true, 63),
+    (3, false, 7),
+    (0, false, 0),
+    (0, false, 0),
+];
+
+/// The global motion parameters of each reference frame, when no motion
+/// has been signalled.
+const DEFAULT_GM_PARAMS: [[i32; 6]; REFS_PER_FRAME + 1] = [[
+    0,
+    0,
+    1 << WARPEDMODEL_PREC_BITS,
+    0,
+    0,
+    1 << WARPEDMODEL_PREC_BITS,
+]; REFS_PER_FRAME + 1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GmType {
+    Identity,
+    Translation,
+    RotZoom,
+    Affine,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DecoderModelInfo {
+    buffer_delay_length: u32,
+    buffer_removal_time_length: u32,
+    frame_presentation_time_length: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OperatingPoint {
+    idc: u32,
+    decoder_model_present: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameIdLengths {
+    delta_frame_id_length: u32,
+    frame_id_length: u32,
+}
+
+/// The fields of the sequence header which are needed
+/// to parse frame headers.
+#[derive(Debug, Clone)]
+pub(super) struct SequenceHeader {
+    reduced_still_picture_header: bool,
+    equal_picture_interval: bool,
+    decoder_model_info: Option<DecoderModelInfo>,
+    operating_points: Vec<OperatingPoint>,
+    frame_width_bits: u32,
+    frame_height_bits: u32,
+    max_frame_width: u32,
+    max_frame_height: u32,
+    frame_id_lengths: Option<FrameIdLengths>,
+    use_128x128_superblock: bool,
+    enable_warped_motion: bool,
+    enable_ref_frame_mvs: bool,
+    /// 0 if order hints are disabled.
+    order_hint_bits: u32,
+    force_screen_content_tools: u8,
+    force_integer_mv: u8,
+    enable_superres: bool,
+    enable_cdef: bool,
+    enable_restoration: bool,
+    pub color_config: ColorConfig,
+    separate_uv_delta_q: bool,
+    pub film_grain_params_present: bool,
+    /// The position of `film_grain_params_present`, which is always the last
+    /// field of the sequence header.
+    pub film_grain_params_present_bit: usize,
+}
+
+impl SequenceHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+        let r = &mut reader;
+        let seq_profile = r.read_bits(3)?;
+        ensure!(seq_profile <= 2, "Unsupported seq_profile {seq_profile}");
+        // still_picture
+        r.read_bit()?;
+        let reduced_still_picture_header = r.read_bit()?;
+
+        let mut equal_picture_interval = false;
+        let mut decoder_model_info = None;
+        let mut operating_points = Vec::new();
+        if reduced_still_picture_header {
+            // seq_level_idx
+            r.read_bits(5)?;
+            operating_points.push(OperatingPoint {
+                idc: 0,
+                decoder_model_present: false,
+            });
+        } else {
+            let timing_info_present_flag = r.read_bit()?;
+            if timing_info_present_flag {
+                // num_units_in_display_tick and time_scale
+                r.read_bits(32)?;
+                r.read_bits(32)?;
+                equal_picture_interval = r.read_bit()?;
+                if equal_picture_interval {
+                    // num_ticks_per_picture_minus_1
+                    r.read_uvlc()?;
+                }
+                let decoder_model_info_present_flag = r.read_bit()?;
+                if decoder_model_info_present_flag {
+                    let buffer_delay_length = r.read_bits(5)? + 1;
+                    // num_units_in_decoding_tick
+                    r.read_bits(32)?;
+                    decoder_model_info = Some(DecoderModelInfo {
+                        buffer_delay_length,
+                        buffer_removal_time_length: r.read_bits(5)? + 1,
+                        frame_presentation_time_length: r.read_bits(5)? + 1,
+                    });
+                }
+            }
+            let initial_display_delay_present_flag = r.read_bit()?;
+            let operating_points_cnt = r.read_bits(5)? + 1;
+            for _ in 0..operating_points_cnt {
+                let idc = r.read_bits(12)?;
+                let seq_level_idx = r.read_bits(5)?;
+                if seq_level_idx > 7 {
+                    // seq_tier
+                    r.read_bit()?;
+                }
+                let mut decoder_model_present = false;
+                if let Some(info) = decoder_model_info {
+                    decoder_model_present = r.read_bit()?;
+                    if decoder_model_present {
+                        // decoder_buffer_delay, encoder_buffer_delay
+                        // and low_delay_mode_flag
+                        r.read_bits(info.buffer_delay_length)?;
+                        r.read_bits(info.buffer_delay_length)?;
+                        r.read_bit()?;
+                    }
+                }
+                if initial_display_delay_present_flag && r.read_bit()? {
+                    // initial_display_delay_minus_1
+                    r.read_bits(4)?;
+                }
+                operating_points.push(OperatingPoint {
+                    idc,
+                    decoder_model_present,
+                });
+            }
+        }
+
+        let frame_width_bits = r.read_bits(4)? + 1;
+        let frame_height_bits = r.read_bits(4)? + 1;
+        let max_frame_width = r.read_bits(frame_width_bits)? + 1;
+        let max_frame_height = r.read_bits(frame_height_bits)? + 1;
+        let frame_id_numbers_present_flag = !reduced_still_picture_header && r.read_bit()?;
+        let frame_id_lengths = if frame_id_numbers_present_flag {
+            let delta_frame_id_length = r.read_bits(4)? + 2;
+            let additional_frame_id_length = r.read_bits(3)? + 1;
+            Some(FrameIdLengths {
+                delta_frame_id_length,
+                frame_id_length: delta_frame_id_length + additional_frame_id_length,
+            })
+        } else {
+            None
+        };
+        let use_128x128_superblock = r.read_bit()?;
+        // enable_filter_intra and enable_intra_edge_filter
+        r.read_bit()?;
+        r.read_bit()?;
+
+        let mut enable_warped_motion = false;
+        let mut enable_ref_frame_mvs = false;
+        let mut order_hint_bits = 0;
+        let mut force_screen_content_tools = SELECT;
+        let mut force_integer_mv = SELECT;
+        if !reduced_still_picture_header {
+            // enable_interintra_compound and enable_masked_compound
+            r.read_bit()?;
+            r.read_bit()?;
+            enable_warped_motion = r.read_bit()?;
+            // enable_dual_filter
+            r.read_bit()?;
+            let enable_order_hint = r.read_bit()?;
+            if enable_order_hint {
+                // enable_jnt_comp
+                r.read_bit()?;
+                enable_ref_frame_mvs = r.read_bit()?;
+            }
+            let seq_choose_screen_content_tools = r.read_bit()?;
+            if !seq_choose_screen_content_tools {
+                force_screen_content_tools = u8::from(r.read_bit()?);
+            }
+            if force_screen_content_tools > 0 {
+                let seq_choose_integer_mv = r.read_bit()?;
+                if !seq_choose_integer_mv {
+                    force_integer_mv = u8::from(r.read_bit()?);
+                }
+            }
+            if enable_order_hint {
+                order_hint_bits = r.read_bits(3)? + 1;
+            }
+        }
+        let enable_superres = r.read_bit()?;
+        let enable_cdef = r.read_bit()?;
+        let enable_restoration = r.read_bit()?;
+        let (color_config, separate_uv_delta_q) = read_color_config(r, seq_profile)?;
+        let film_grain_params_present_bit = r.position();
+        let film_grain_params_present = r.read_bit()?;
+
+        Ok(Self {
+            reduced_still_picture_header,
+            equal_picture_interval,
+            decoder_model_info,
+            operating_points,
+            frame_width_bits,
+            frame_height_bits,
+            max_frame_width,
+            max_frame_height,
+            frame_id_lengths,
+            use_128x128_superblock,
+            enable_warped_motion,
+            enable_ref_frame_mvs,
+            order_hint_bits,
+            force_screen_content_tools,
+            force_integer_mv,
+            enable_superres,
+            enable_cdef,
+            enable_restoration,
+            color_config,
+            separate_uv_delta_q,
+            film_grain_params_present,
+            film_grain_params_present_bit,
+        })
+    }
+
+    const fn num_planes(&self) -> usize {
+        if self.color_config.mono_chrome { 1 } else { 3 }
+    }
+
+    /// Whether an OBU belongs to the operating point chosen by the decoder,
+    /// which is always the first.
+    fn in_operating_point(&self, obu: &Obu) -> bool {
+        let idc = self.operating_points.first().map_or(0, |op| op.idc);
+        if idc == 0 || !obu.has_extension {
+            return true;
+        }
+        let in_temporal_layer = (idc >> obu.temporal_id) & 1 == 1;
+        let in_spatial_layer = (idc >> (obu.spatial_id + 8)) & 1 == 1;
+        in_temporal_layer && in_spatial_layer
+    }
+
+    /// The signed distance between two order hints.
+    const fn relative_dist(&self, a: u32, b: u32) -> i32 {
+        if self.order_hint_bits == 0 {
+            return 0;
+        }
+        let diff = a as i32 - b as i32;
+        let m = 1 << (self.order_hint_bits - 1);
+        (diff & (m - 1)) - (diff & m)
+    }
+}
+
+fn read_color_config(r: &mut BitReader, seq_profile: u32) -> Result<(ColorConfig, bool)> {
+    let high_bitdepth = r.read_bit()?;
+    let twelve_bit = seq_profile == 2 && high_bitdepth && r.read_bit()?;
+    let mono_chrome = seq_profile != 1 && r.read_bit()?;
+    let color_description_present_flag = r.read_bit()?;
+    let (color_primaries, transfer_characteristics, matrix_coefficients) =
+        if color_description_present_flag {
+            (r.read_bits(8)?, r.read_bits(8)?, r.read_bits(8)?)
+        } else {
+            (2, 2, 2)
+        };
+
+    if mono_chrome {
+        // color_range
+        r.read_bit()?;
+        let color_config = ColorConfig {
+            mono_chrome,
+            subsampling_x: true,
+            subsampling_y: true,
+        };
+        return Ok((color_config, false));
+    }
+
+    let (subsampling_x, subsampling_y) = if (
+        color_primaries,
+        transfer_characteristics,
+        matrix_coefficients,
+    ) == (1, 13, 0)
+    {
+        // sRGB is always full range 4:4:4
+        (false, false)
+    } else {
+        // color_range
+        r.read_bit()?;
+        let subsampling = match seq_profile {
+            0 => (true, true),
+            1 => (false, false),
+            _ if twelve_bit => {
+                let subsampling_x = r.read_bit()?;
+                (subsampling_x, subsampling_x && r.read_bit()?)
+            }
+            _ => (true, false),
+        };
+        if subsampling == (true, true) {
+            // chroma_sample_position
+            r.read_bits(2)?;
+        }
+        subsampling
+    };
+    let separate_uv_delta_q = r.read_bit()?;
+
+    Ok((
+        ColorConfig {
+            mono_chrome,
+            subsampling_x,
+            subsampling_y,
+        },
+        separate_uv_delta_q,
+    ))
+}
+
+/// The state saved in each reference frame slot which affects
+/// how later frame headers are parsed.
+#[derive(Debug, Clone)]
+struct RefSlot {
+    frame_type: FrameType,
+    order_hint: u32,
+    size: FrameSize,
+    gm_params: [[i32; 6]; REFS_PER_FRAME + 1],
+    /// The `SEG_LVL_ALT_Q` feature data of each segment, if enabled.
+    alt_q: [Option<i32>; 8],
+}
+
+impl Default for RefSlot {
+    fn default() -> Self {
+        Self {
+            frame_type: FrameType::Key,
+            order_hint: 0,
+            size: FrameSize::default(),
+            gm_params: DEFAULT_GM_PARAMS,
+            alt_q: [None; 8],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameSize {
+    upscaled_width: u32,
+    frame_width: u32,
+    frame_height: u32,
+    render_width: u32,
+    render_height: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TileLayout {
+    cols_log2: u32,
+    rows_log2: u32,
+    num_tiles: u32,
+}
+
+/// The film grain related information of a parsed frame header.
+#[derive(Debug, Clone)]
+pub(super) struct FrameHeader {
+    pub frame_type: FrameType,
+    /// Whether the frame is output, either directly or by
+    /// `show_existing_frame`.
+    pub shown: bool,
+    /// The reference slot which is output, if `show_existing_frame` is 1.
+    pub show_existing_frame: Option<usize>,
+    pub refresh_frame_flags: u32,
+    /// The film grain parameters of the frame, which are applied
+    /// when it is shown.
+    pub film_grain: Option<GrainTableSegment>,
+    /// The bit range of `film_grain_params()`, which is always the last
+    /// field of the uncompressed header. The range is empty if
+    /// `film_grain_params_present` is 0, and `None` if the frame does not
+    /// signal film grain parameters.
+    pub film_grain_bits: Option<Range<usize>>,
+    /// The number of bits in the uncompressed header.
+    pub header_bits: usize,
+}
+
+/// Tracks the decoder state needed to parse a sequence of frame headers.
+#[derive(Debug, Clone, Default)]
+pub(super) struct HeaderParser {
+    sequence: Option<SequenceHeader>,
+    refs: [RefSlot; NUM_REF_FRAMES],
+    /// The film grain parameters stored in each reference slot, which are
+    /// disabled for frames without grain and `None` before the first frame
+    /// is stored in the slot.
+    ref_grain: [Option<GrainTableSegment>; NUM_REF_FRAMES],
+    /// The tile layout of the frame whose tile groups are being read,
+    /// which is set from when its frame header is seen until its last
+    /// tile group.
+    current_frame: Option<TileLayout>,
+}
+
+impl HeaderParser {
+    pub const fn sequence(&self) -> Option<&SequenceHeader> {
+        self.sequence.as_ref()
+    }
+
+    /// Updates the state of the parser with the contents of `obu`,
+    /// returning the frame header if the OBU starts a new frame.
+    pub fn parse_obu(&mut self, obu: &Obu) -> Result<Option<FrameHeader>> {
+        if let Some(sequence) = &self.sequence
+            && !sequence.in_operating_point(obu)
+        {
+            return Ok(None);
+        }
+
+        match obu.obu_type {
+            OBU_SEQUENCE_HEADER => {
+                self.sequence = Some(SequenceHeader::parse(obu.payload)?);
+            }
+            OBU_TEMPORAL_DELIMITER => {
+                self.current_frame = None;
+            }
+            OBU_FRAME_HEADER | OBU_FRAME if self.current_frame.is_none() => {
+                let mut reader = BitReader::new(obu.payload);
+                let header = self.frame_header(&mut reader, obu)?;
+                if obu.obu_type == OBU_FRAME {
+                    ensure!(
+                        header.show_existing_frame.is_none(),
+                        "Frame OBUs cannot show existing frames"
+                    );
+                    reader.byte_align();
+                    self.tile_group(&mut reader)?;
+                }
+                return Ok(Some(header));
+            }
+            OBU_FRAME_HEADER | OBU_REDUNDANT_FRAME_HEADER => {
+                // A copy of the current frame header
+            }
+            OBU_FRAME => bail!("Frame OBU received before the end of the previous frame"),
+            OBU_TILE_GROUP => {
+                self.tile_group(&mut BitReader::new(obu.payload))?;
+            }
+            _ => {}
+        }
+
+        Ok(None)
+    }
+
+    fn tile_group(&mut self, r: &mut BitReader) -> Result<()> {
+        let layout = self
+            .current_frame
+            .context("Tile group received without a frame header")?;
+        let tile_start_and_end_present_flag = layout.num_tiles > 1 && r.read_bit()?;
+        let tg_end = if tile_start_and_end_present_flag {
+            let tile_bits = layout.cols_log2 + layout.rows_log2;
+            // tg_start
+            r.read_bits(tile_bits)?;
+            r.read_bits(tile_bits)?
+        } else {
+            layout.num_tiles - 1
+        };
+        if tg_end + 1 >= layout.num_tiles {
+            self.current_frame = None;
+        }
+
+        Ok(())
+    }
+
+    fn frame_header(&mut self, r: &mut BitReader, obu: &Obu) -> Result<FrameHeader> {
+        let seq = self
+            .sequence
+            .as_ref()
+            .context("Frame header received before a sequence header")?;
+        let temporal_point_info = |r: &mut BitReader| -> Result<()> {
+            if let Some(info) = seq.decoder_model_info
+                && !seq.equal_picture_interval
+            {
+                // frame_presentation_time
+                r.read_bits(info.frame_presentation_time_length)?;
+            }
+            Ok(())
+        };
+
+        let (frame_type, show_frame, showable_frame, error_resilient_mode);
+        if seq.reduced_still_picture_header {
+            frame_type = FrameType::Key;
+            show_frame = true;
+            showable_frame = false;
+            error_resilient_mode = true;
+        } else {
+            let show_existing_frame = r.read_bit()?;
+            if show_existing_frame {
+                let frame_to_show_map_idx = r.read_bits(3)? as usize;
+                temporal_point_info(r)?;
+                if let Some(lengths) = seq.frame_id_lengths {
+                    // display_frame_id
+                    r.read_bits(lengths.frame_id_length)?;
+                }
+                let slot = self
+                    .refs
+                    .get(frame_to_show_map_idx)
+                    .expect("frame_to_show_map_idx is a 3-bit value")
+                    .clone();
+                let slot_grain = self
+                    .ref_grain
+                    .get(frame_to_show_map_idx)
+                    .expect("frame_to_show_map_idx is a 3-bit value")
+                    .clone();
+                let film_grain = slot_grain.clone().filter(|grain| grain.apply_grain);
+                let frame_type = slot.frame_type;
+                // Showing a key frame refreshes every reference slot.
+                let refresh_frame_flags = if frame_type == FrameType::Key {
+                    self.refs.fill(slot);
+                    self.ref_grain.fill(slot_grain);
+                    ALL_FRAMES
+                } else {
+                    0
+                };
+                return Ok(FrameHeader {
+                    frame_type,
+                    shown: true,
+                    show_existing_frame: Some(frame_to_show_map_idx),
+                    refresh_frame_flags,
+                    film_grain,
+                    film_grain_bits: None,
+                    header_bits: r.position(),
+                });
+            }
+
+            frame_type = match r.read_bits(2)? {
+                0 => FrameType::Key,
+                1 => FrameType::Inter,
+                2 => FrameType::IntraOnly,
+                _ => FrameType::Switch,
+            };
+            show_frame = r.read_bit()?;
+            if show_frame {
+                temporal_point_info(r)?;
+            }
+            showable_frame = if show_frame {
+                frame_type != FrameType::Key
+            } else {
+                r.read_bit()?
+            };
+            error_resilient_mode = frame_type == FrameType::Switch
+                || (frame_type == FrameType::Key && show_frame)
+                || r.read_bit()?;
+        }
+        let frame_is_intra = matches!(frame_type, FrameType::Key | FrameType::IntraOnly);
+
+        if frame_type == FrameType::Key && show_frame {
+            for slot in &mut self.refs {
+                slot.order_hint = 0;
+            }
+        }
+        let disable_cdf_update = r.read_bit()?;
+        let allow_screen_content_tools = if seq.force_screen_content_tools == SELECT {
+            r.read_bit()?
+        } else {
+            seq.force_screen_content_tools == 1
+        };
+        let force_integer_mv = if !allow_screen_content_tools {
+            false
+        } else if seq.force_integer_mv == SELECT {
+            r.read_bit()?
+        } else {
+            seq.force_integer_mv == 1
+        };
+        // Intra frames always use integer motion vectors, even if
+        // `force_integer_mv` is signalled as 0.
+        let force_integer_mv = force_integer_mv || frame_is_intra;
+        if let Some(lengths) = seq.frame_id_lengths {
+            // current_frame_id
+            r.read_bits(lengths.frame_id_length)?;
+        }
+        let frame_size_override_flag = if frame_type == FrameType::Switch {
+            true
+        } else {
+            !seq.reduced_still_picture_header && r.read_bit()?
+        };
+        let order_hint = r.read_bits(seq.order_hint_bits)?;
+        let primary_ref_frame = if frame_is_intra || error_resilient_mode {
+            PRIMARY_REF_NONE
+        } else {
+            r.read_bits(3)? as usize
+        };
+        if let Some(info) = seq.decoder_model_info {
+            let buffer_removal_time_present_flag = r.read_bit()?;
+            if buffer_removal_time_present_flag {
+                for op in &seq.operating_points {
+                    if !op.decoder_model_present {
+                        continue;
+                    }
+                    let in_temporal_layer = (op.idc >> obu.temporal_id) & 1 == 1;
+                    let in_spatial_layer = (op.idc >> (obu.spatial_id + 8)) & 1 == 1;
+                    if op.idc == 0 || (in_temporal_layer && in_spatial_layer) {
+                        // buffer_removal_time
+                        r.read_bits(info.buffer_removal_time_length)?;
+                    }
+                }
+            }
+        }
+
+        let refresh_frame_flags =
+            if frame_type == FrameType::Switch || (frame_type == FrameType::Key && show_frame) {
+                ALL_FRAMES
+            } else {
+                r.read_bits(8)?
+            };
+        if (!frame_is_intra || refresh_frame_flags != ALL_FRAMES)
+            && error_resilient_mode
+            && seq.order_hint_bits > 0
+        {
+            for slot in &mut self.refs {
+                let ref_order_hint = r.read_bits(seq.order_hint_bits)?;
+                if ref_order_hint != slot.order_hint {
+                    *slot = RefSlot {
+                        order_hint: ref_order_hint,
+                        ..RefSlot::default()
+                    };
+                }
+            }
+        }
+
+        let mut ref_frame_idx = [0; REFS_PER_FRAME];
+        let mut allow_high_precision_mv = false;
+        let mut allow_intrabc = false;
+        let size = if frame_is_intra {
+            let size = read_frame_size(r, seq, frame_size_override_flag)?;
+            let size = read_render_size(r, size)?;
+            if allow_screen_content_tools && size.upscaled_width == size.frame_width {
+                allow_intrabc = r.read_bit()?;
+            }
+            size
+        } else {
+            let frame_refs_short_signaling = seq.order_hint_bits > 0 && r.read_bit()?;
+            if frame_refs_short_signaling {
+                let last_frame_idx = r.read_bits(3)? as usize;
+                let gold_frame_idx = r.read_bits(3)? as usize;
+                ref_frame_idx =
+                    set_frame_refs(seq, &self.refs, last_frame_idx, gold_frame_idx, order_hint);
+            }
+            for idx in &mut ref_frame_idx {
+                if !frame_refs_short_signaling {
+                    *idx = r.read_bits(3)? as usize;
+                }
+                if let Some(lengths) = seq.frame_id_lengths {
+                    // delta_frame_id_minus_1
+                    r.read_bits(lengths.delta_frame_id_length)?;
+                }
+            }
+            let size = if frame_size_override_flag && !error_resilient_mode {
+                read_frame_size_with_refs(r, seq, &self.refs, &ref_frame_idx)?
+            } else {
+                let size = read_frame_size(r, seq, frame_size_override_flag)?;
+                read_render_size(r, size)?
+            };
+            allow_high_precision_mv = !force_integer_mv && r.read_bit()?;
+            let is_filter_switchable = r.read_bit()?;
+            if !is_filter_switchable {
+                // interpolation_filter
+                r.read_bits(2)?;
+            }
+            // is_motion_mode_switchable
+            r.read_bit()?;
+            if !error_resilient_mode && seq.enable_ref_frame_mvs {
+                // use_ref_frame_mvs
+                r.read_bit()?;
+            }
+            size
+        };
+
+        if !seq.reduced_still_picture_header && !disable_cdf_update {
+            // disable_frame_end_update_cdf
+            r.read_bit()?;
+        }
+        let previous = (primary_ref_frame != PRIMARY_REF_NONE)
+            .then(|| {
+                ref_frame_idx
+                    .get(primary_ref_frame)
+                    .and_then(|&idx| self.refs.get(idx))
+            })
+            .flatten();
+
+        let tile_layout = read_tile_info(r, seq, &size)?;
+        let (base_q_idx, deltas_are_zero) = read_quantization_params(r, seq)?;
+        let alt_q = read_segmentation_params(r, previous)?;
+        let delta_q_present = base_q_idx > 0 && r.read_bit()?;
+        if delta_q_present {
+            // delta_q_res
+            r.read_bits(2)?;
+            let delta_lf_present = !allow_intrabc && r.read_bit()?;
+            if delta_lf_present {
+                // delta_lf_res and delta_lf_multi
+                r.read_bits(2)?;
+                r.read_bit()?;
+            }
+        }
+        let coded_lossless = deltas_are_zero
+            && alt_q.iter().all(|alt_q| {
+                alt_q.map_or(base_q_idx, |data| (base_q_idx + data).clamp(0, 255)) == 0
+            });
+        let all_lossless = coded_lossless && size.frame_width == size.upscaled_width;
+
+        if !coded_lossless && !allow_intrabc {
+            read_loop_filter_params(r, seq)?;
+        }
+        if !coded_lossless && !allow_intrabc && seq.enable_cdef {
+            read_cdef_params(r, seq)?;
+        }
+        if !all_lossless && !allow_intrabc && seq.enable_restoration {
+            read_lr_params(r, seq)?;
+        }
+        if !coded_lossless {
+            // tx_mode_select
+            r.read_bit()?;
+        }
+        let reference_select = !frame_is_intra && r.read_bit()?;
+        if reference_select
+            && seq.order_hint_bits > 0
+            && skip_mode_allowed(seq, &self.refs, &ref_frame_idx, order_hint)
+        {
+            // skip_mode_present
+            r.read_bit()?;
+        }
+        if !frame_is_intra && !error_resilient_mode && seq.enable_warped_motion {
+            // allow_warped_motion
+            r.read_bit()?;
+        }
+        // reduced_tx_set
+        r.read_bit()?;
+        let gm_params = if frame_is_intra {
+            DEFAULT_GM_PARAMS
+        } else {
+            let previous_gm_params = previous.map_or(&DEFAULT_GM_PARAMS, |slot| &slot.gm_params);
+            read_global_motion_params(r, previous_gm_params, allow_high_precision_mv)?
+        };
+
+        let film_grain_start = r.position();
+        let film_grain = if show_frame || showable_frame {
+            read_film_grain_params(
+                r,
+                &seq.color_config,
+                frame_type,
+                seq.film_grain_params_present,
+                &self.ref_grain,
+            )?
+        } else {
+            None
+        };
+        let film_grain_bits =
+            (show_frame || showable_frame).then(|| film_grain_start..r.position());
+
+        let slot = RefSlot {
+            frame_type,
+            order_hint,
+            size,
+            gm_params,
+            alt_q,
+        };
+        // Frames without grain store the parameters of `reset_grain_params()`,
+        // which later frames can load to have no grain either.
+        let slot_grain = film_grain.clone().unwrap_or_else(|| GrainTableSegment {
+            apply_grain: false,
+            ..GrainTableSegment::default()
+        });
+        for (i, (ref_slot, ref_grain)) in self.refs.iter_mut().zip(&mut self.ref_grain).enumerate()
+        {
+            if (refresh_frame_flags >> i) & 1 == 1 {
+                ref_slot.clone_from(&slot);
+                *ref_grain = Some(slot_grain.clone());
+            }
+        }
+        self.current_frame = Some(tile_layout);
+
+        Ok(FrameHeader {
+            frame_type,
+            shown: show_frame,
+            show_existing_frame: None,
+            refresh_frame_flags,
+            film_grain,
+            film_grain_bits,
+            header_bits: r.position(),
+        })
+    }
+}
+
+fn read_frame_size(
+    r: &mut BitReader,
+    seq: &SequenceHeader,
+    frame_size_override_flag: bool,
+) -> Result<FrameSize> {
+    let (width, height) = if frame_size_override_flag {
+        (
+            r.read_bits(seq.frame_width_bits)? + 1,
+            r.read_bits(seq.frame_height_bits)? + 1,
+        )
+    } else {
+        (seq.max_frame_width, seq.max_frame_height)
+    };
+    read_superres_params(r, seq, width, height)
+}
+
+fn read_superres_params(
+    r: &mut BitReader,
+    seq: &SequenceHeader,
+    upscaled_width: u32,
+    frame_height: u32,
+) -> Result<FrameSize> {
+    let use_superres = seq.enable_superres && r.read_bit()?;
+    let superres_denom = if use_superres { r.read_bits(3)? + 9 } else { 8 };
+    Ok(FrameSize {
+        upscaled_width,
+        frame_width: (upscaled_width * 8 + superres_denom / 2) / superres_denom,
+        frame_height,
+        render_width: upscaled_width,
+        render_height: frame_height,
+    })
+}
+
+fn read_render_size(r: &mut BitReader, size: FrameSize) -> Result<FrameSize> {
+    let render_and_frame_size_different = r.read_bit()?;
+    if !render_and_frame_size_different {
+        return Ok(size);
+    }
+    Ok(FrameSize {
+        render_width: r.read_bits(16)? + 1,
+        render_height: r.read_bits(16)? + 1,
+        ..size
+    })
+}
+
+fn read_frame_size_with_refs(
+    r: &mut BitReader,
+    seq: &SequenceHeader,
+    refs: &[RefSlot; NUM_REF_FRAMES],
+    ref_frame_idx: &[usize; REFS_PER_FRAME],
+) -> Result<FrameSize> {
+    for &idx in ref_frame_idx {
+        let found_ref = r.read_bit()?;
+        if found_ref {
+            let reference = refs.get(idx).expect("ref_frame_idx is a 3-bit value").size;
+            let size =
+                read_superres_params(r, seq, reference.upscaled_width, reference.frame_height)?;
+            return Ok(FrameSize {
+                render_width: reference.render_width,
+                render_height: reference.render_height,
+                ..size
+            });
+        }
+    }
+
+    let size = read_frame_size(r, seq, true)?;
+    read_render_size(r, size)
+}
+
+/// Derives the references of a frame from the order hints of the
+/// reference slots, following the set frame refs process.
+fn set_frame_refs(
+    seq: &SequenceHeader,
+    refs: &[RefSlot; NUM_REF_FRAMES],
+    last_frame_idx: usize,
+    gold_frame_idx: usize,
+    order_hint: u32,
+) -> [usize; REFS_PER_FRAME] {
+    const LAST2_FRAME: usize = 1;
+    const LAST3_FRAME: usize = 2;
+    const GOLDEN_FRAME: usize = 3;
+    const BWDREF_FRAME: usize = 4;
+    const ALTREF2_FRAME: usize = 5;
+    const ALTREF_FRAME: usize = 6;
+
+    let mut ref_frame_idx: [Option<usize>; REFS_PER_FRAME] = [None; REFS_PER_FRAME];
+    ref_frame_idx[0] = Some(last_frame_idx);
+    ref_frame_idx[GOLDEN_FRAME] = Some(gold_frame_idx);
+    let cur_frame_hint = 1i32 << (seq.order_hint_bits - 1);
+    let shifted_order_hints = refs
+        .each_ref()
+        .map(|slot| cur_frame_hint + seq.relative_dist(slot.order_hint, order_hint));
+    let mut used_frame = [false; NUM_REF_FRAMES];
+    for (i, used) in used_frame.iter_mut().enumerate() {
+        *used = i == last_frame_idx || i == gold_frame_idx;
+    }
+
+    // Assigns the unused reference with the latest or earliest order hint
+    // before or after the current frame, unless already assigned.
+    let mut set_ref = |ref_frame: usize, backward: bool, latest: bool| {
+        let Some(slot @ None) = ref_frame_idx.get_mut(ref_frame) else {
+            return;
+        };
+        let mut best: Option<(usize, i32)> = None;
+        for (i, (&hint, &used)) in shifted_order_hints.iter().zip(&used_frame).enumerate() {
+            if used || (hint >= cur_frame_hint) != backward {
+                continue;
+            }
+            let better = best.is_none_or(|(_, best_hint)| {
+                if latest {
+                    hint >= best_hint
+                } else {
+                    hint < best_hint
+                }
+            });
+            if better {
+                best = Some((i, hint));
+            }
+        }
+        if let Some((i, _)) = best
+            && let Some(used) = used_frame.get_mut(i)
+        {
+            *slot = Some(i);
+            *used = true;
+        }
+    };
+
+    set_ref(ALTREF_FRAME, true, true);
+    set_ref(BWDREF_FRAME, true, false);
+    set_ref(ALTREF2_FRAME, true, false);
+    for ref_frame in [
+        LAST2_FRAME,
+        LAST3_FRAME,
+        BWDREF_FRAME,
+        ALTREF2_FRAME,
+        ALTREF_FRAME,
+    ] {
+        set_ref(ref_frame, false, true);
+    }
+
+    // Any remaining references use the earliest frame.
+    let mut earliest: Option<(usize, i32)> = None;
+    for (i, &hint) in shifted_order_hints.iter().enumerate() {
+        if earliest.is_none_or(|(_, earliest_hint)| hint < earliest_hint) {
+            earliest = Some((i, hint));
+        }
+    }
+    let earliest = earliest.map_or(0, |(i, _)| i);
+    ref_frame_idx.map(|idx| idx.unwrap_or(earliest))
+}
+
+fn read_tile_info(r: &mut BitReader, seq: &SequenceHeader, size: &FrameSize) -> Result<TileLayout> {
+    let mi_cols = 2 * size.frame_width.div_ceil(8);
+    let mi_rows = 2 * size.frame_height.div_ceil(8);
+    let (sb_cols, sb_rows, sb_shift) = if seq.use_128x128_superblock {
+        (mi_cols.div_ceil(32), mi_rows.div_ceil(32), 5)
+    } else {
+        (mi_cols.div_ceil(16), mi_rows.div_ceil(16), 4)
+    };
+    let sb_size = sb_shift + 2;
+    let max_tile_width_sb = MAX_TILE_WIDTH >> sb_size;
+    let mut max_tile_area_sb = MAX_TILE_AREA >> (2 * sb_size);
+    let min_log2_tile_cols = tile_log2(max_tile_width_sb, sb_cols);
+    let max_log2_tile_cols = tile_log2(1, sb_cols.min(MAX_TILE_COLS));
+    let max_log2_tile_rows = tile_log2(1, sb_rows.min(MAX_TILE_ROWS));
+    let min_log2_tiles = min_log2_tile_cols.max(tile_log2(max_tile_area_sb, sb_rows * sb_cols));
+
+    let uniform_tile_spacing_flag = r.read_bit()?;
+    let (tile_cols, tile_rows, cols_log2, rows_log2);
+    if uniform_tile_spacing_flag {
+        let mut log2 = min_log2_tile_cols;
+        while log2 < max_log2_tile_cols && r.read_bit()? {
+            log2 += 1;
+        }
+        cols_log2 = log2;
+        let tile_width_sb = (sb_cols + (1 << cols_log2) - 1) >> cols_log2;
+        tile_cols = sb_cols.div_ceil(tile_width_sb);
+
+        let mut log2 = min_log2_tiles.saturating_sub(cols_log2);
+        while log2 < max_log2_tile_rows && r.read_bit()? {
+            log2 += 1;
+        }
+        rows_log2 = log2;
+        let tile_height_sb = (sb_rows + (1 << rows_log2) - 1) >> rows_log2;
+        tile_rows = sb_rows.div_ceil(tile_height_sb);
+    } else {
+        let mut widest_tile_sb = 0;
+        let mut start_sb = 0;
+        let mut count = 0;
+        while start_sb < sb_cols {
+            let max_width = (sb_cols - start_sb).min(max_tile_width_sb);
+            let size_sb = r.read_ns(max_width)? + 1;
+            widest_tile_sb = widest_tile_sb.max(size_sb);
+            start_sb += size_sb;
+            count += 1;
+        }
+        tile_cols = count;
+        cols_log2 = tile_log2(1, tile_cols);
+
+        max_tile_area_sb = if min_log2_tiles > 0 {
+            (sb_rows * sb_cols) >> (min_log2_tiles + 1)
+        } else {
+            sb_rows * sb_cols
+        };
+        let max_tile_height_sb = (max_tile_area_sb / widest_tile_sb).max(1);
+        let mut start_sb = 0;
+        let mut count = 0;
+        while start_sb < sb_rows {
+            let max_height = (sb_rows - start_sb).min(max_tile_height_sb);
+            start_sb += r.read_ns(max_height)? + 1;
+            count += 1;
+        }
+        tile_rows = count;
+        rows_log2 = tile_log2(1, tile_rows);
+    }
+    if cols_log2 > 0 || rows_log2 > 0 {
+        // context_update_tile_id and tile_size_bytes_minus_1
+        r.read_bits(cols_log2 + rows_log2)?;
+        r.read_bits(2)?;
+    }
+
+    Ok(TileLayout {
+        cols_log2,
+        rows_log2,
+        num_tiles: tile_cols * tile_rows,
+    })
+}
+
+/// Returns the smallest `k` such that `block_size << k` is at least `target`.
+fn tile_log2(block_size: u32, target: u32) -> u32 {
+    let mut k = 0;
+    while (block_size << k) < target {
+        k += 1;
+    }
+    k
+}
+
+/// Returns `base_q_idx`, and whether all of the delta quantizers are zero.
+fn read_quantization_params(r: &mut BitReader, seq: &SequenceHeader) -> Result<(i32, bool)> {
+    let read_delta_q = |r: &mut BitReader| -> Result<i32> {
+        let delta_coded = r.read_bit()?;
+        if delta_coded { r.read_su(7) } else { Ok(0) }
+    };
+
+    let base_q_idx = r.read_bits(8)? as i32;
+    let mut deltas_are_zero = read_delta_q(r)? == 0;
+    if seq.num_planes() > 1 {
+        let diff_uv_delta = seq.separate_uv_delta_q && r.read_bit()?;
+        let planes = if diff_uv_delta { 2 } else { 1 };
+        for _ in 0..planes * 2 {
+            deltas_are_zero &= read_delta_q(r)? == 0;
+        }
+    }
+    let using_qmatrix = r.read_bit()?;
+    if using_qmatrix {
+        // qm_y, qm_u and qm_v
+        r.read_bits(4)?;
+        r.read_bits(4)?;
+        if seq.separate_uv_delta_q {
+            r.read_bits(4)?;
+        }
+    }
+
+    Ok((base_q_idx, deltas_are_zero))
+}
+
+/// Returns the `SEG_LVL_ALT_Q` feature data of each segment, if enabled.
+fn read_segmentation_params(
+    r: &mut BitReader,
+    previous: Option<&RefSlot>,
+) -> Result<[Option<i32>; 8]> {
+    let segmentation_enabled = r.read_bit()?;
+    if !segmentation_enabled {
+        return Ok([None; 8]);
+    }
+    if let Some(previous) = previous {
+        let segmentation_update_map = r.read_bit()?;
+        if segmentation_update_map {
+            // segmentation_temporal_update
+            r.read_bit()?;
+        }
+        let segmentation_update_data = r.read_bit()?;
+        if !segmentation_update_data {
+            return Ok(previous.alt_q);
+        }
+    }
+
+    let mut alt_q = [None; 8];
+    for segment in &mut alt_q {
+        for (feature, &(bits, signed, max)) in SEGMENTATION_FEATURES.iter().enumerate() {
+            let feature_enabled = r.read_bit()?;
+            if !feature_enabled {
+                continue;
+            }
+            let value = if signed {
+                r.read_su(1 + bits)?.clamp(-max, max)
+            } else {
+                (r.read_bits(bits)? as i32).clamp(0, max)
+            };
+            if feature == 0 {
+                *segment = Some(value);
+            }
+        }
+    }
+
+    Ok(alt_q)
+}
+
+fn read_loop_filter_params(r: &mut BitReader, seq: &SequenceHeader) -> Result<()> {
+    let loop_filter_level = [r.read_bits(6)?, r.read_bits(6)?];
+    if seq.num_planes() > 1 && loop_filter_level != [0, 0] {
+        r.read_bits(6)?;
+        r.read_bits(6)?;
+    }
+    // loop_filter_sharpness
+    r.read_bits(3)?;
+    let loop_filter_delta_enabled = r.read_bit()?;
+    if loop_filter_delta_enabled {
+        let loop_filter_delta_update = r.read_bit()?;
+        if loop_filter_delta_update {
+            // loop_filter_ref_deltas and loop_filter_mode_deltas
+            for _ in 0..(NUM_REF_FRAMES + 2) {
+                if r.read_bit()? {
+                    r.read_su(7)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_cdef_params(r: &mut BitReader, seq: &SequenceHeader) -> Result<()> {
+    // cdef_damping_minus_3
+    r.read_bits(2)?;
+    let cdef_bits = r.read_bits(2)?;
+    for _ in 0..(1 << cdef_bits) {
+        // Primary and secondary strengths for luma and chroma
+        r.read_bits(4)?;
+        r.read_bits(2)?;
+        if seq.num_planes() > 1 {
+            r.read_bits(4)?;
+            r.read_bits(2)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_lr_params(r: &mut BitReader, seq: &SequenceHeader) -> Result<()> {
+    let mut uses_lr = false;
+    let mut uses_chroma_lr = false;
+    for plane in 0..seq.num_planes() {
+        let lr_type = r.read_bits(2)?;
+        if lr_type != 0 {
+            uses_lr = true;
+            uses_chroma_lr |= plane > 0;
+        }
+    }
+    if uses_lr {
+        let lr_unit_shift = r.read_bit()?;
+        if !seq.use_128x128_superblock && lr_unit_shift {
+            // lr_unit_extra_shift
+            r.read_bit()?;
+        }
+        let color_config = seq.color_config;
+        if color_config.subsampling_x && color_config.subsampling_y && uses_chroma_lr {
+            // lr_uv_shift
+            r.read_bit()?;
+        }
+    }
+    Ok(())
+}
+
+fn skip_mode_allowed(
+    seq: &SequenceHeader,
+    refs: &[RefSlot; NUM_REF_FRAMES],
+    ref_frame_idx: &[usize; REFS_PER_FRAME],
+    order_hint: u32,
+) -> bool {
+    let hints = ref_frame_idx.map(|idx| refs.get(idx).map_or(0, |slot| slot.order_hint));
+    let mut forward_hint = None;
+    let mut backward_hint = None;
+    for &hint in &hints {
+        let dist = seq.relative_dist(hint, order_hint);
+        if dist < 0 {
+            if forward_hint.is_none_or(|forward| seq.relative_dist(hint, forward) > 0) {
+                forward_hint = Some(hint);
+            }
+        } else if dist > 0
+            && backward_hint.is_none_or(|backward| seq.relative_dist(hint, backward) < 0)
+        {
+            backward_hint = Some(hint);
+        }
+    }
+
+    forward_hint.is_some_and(|forward| {
+        backward_hint.is_some()
+            || hints
+                .iter()
+                .any(|&hint| seq.relative_dist(hint, forward) < 0)
+    })
+}
+
+fn read_global_motion_params(
+    r: &mut BitReader,
+    previous: &[[i32; 6]; REFS_PER_FRAME + 1],
+    allow_high_precision_mv: bool,
+) -> Result<[[i32; 6]; REFS_PER_FRAME + 1]> {
+    let mut gm_params = DEFAULT_GM_PARAMS;
+    for (params, previous) in gm_params.iter_mut().zip(previous).skip(1) {
+        let is_global = r.read_bit()?;
+        let gm_type = if !is_global {
+            GmType::Identity
+        } else if r.read_bit()? {
+            GmType::RotZoom
+        } else if r.read_bit()? {
+            GmType::Translation
+        } else {
+            GmType::Affine
+        };
+
+        let read_param = |r: &mut BitReader, params: &mut [i32; 6], idx: usize| {
+            read_global_param(r, gm_type, idx, params, previous, allow_high_precision_mv)
+        };
+        if matches!(gm_type, GmType::RotZoom | GmType::Affine) {
+            read_param(r, params, 2)?;
+            read_param(r, params, 3)?;
+            if gm_type == GmType::Affine {
+                read_param(r, params, 4)?;
+                read_param(r, params, 5)?;
+            } else {
+                params[4] = -params[3];
+                params[5] = params[2];
+            }
+        }
+        if gm_type != GmType::Identity {
+            read_param(r, params, 0)?;
+            read_param(r, params, 1)?;
+        }
+    }
+
+    Ok(gm_params)
+}
+
+fn read_global_param(
+    r: &mut BitReader,
+    gm_type: GmType,
+    idx: usize,
+    params: &mut [i32; 6],
+    previous: &[i32; 6],
+    allow_high_precision_mv: bool,
+) -> Result<()> {
+    let (abs_bits, prec_bits) = if idx >= 2 {
+        (12, 15)
+    } else if gm_type == GmType::Translation {
+        let reduced = u32::from(!allow_high_precision_mv);
+        (9 - reduced, 3 - reduced)
+    } else {
+        (12, 6)
+    };
+    let prec_diff = WARPEDMODEL_PREC_BITS - prec_bits;
+    let (round, sub) = if idx % 3 == 2 {
+        (1 << WARPEDMODEL_PREC_BITS, 1 << prec_bits)
+    } else {
+        (0, 0)
+    };
+    let mx = 1 << abs_bits;
+    let previous = previous
+        .get(idx)
+        .expect("global motion params have 6 values");
+    let reference = (previous >> prec_diff) - sub;
+    let value = decode_signed_subexp_with_ref(r, -mx, mx + 1, reference)?;
+    *params
+        .get_mut(idx)
+        .expect("global motion params have 6 values") = (value << prec_diff) + round;
+    Ok(())
+}
+
+fn decode_signed_subexp_with_ref(
+    r: &mut BitReader,
+    low: i32,
+    high: i32,
+    reference: i32,
+) -> Result<i32> {
+    let mx = (high - low) as u32;
+    let reference = (reference - low) as u32;
+    let value = decode_subexp(r, mx)?;
+    let value = if (reference << 1) <= mx {
+        inverse_recenter(reference, value)
+    } else {
+        mx - 1 - inverse_recenter(mx - 1 - reference, value)
+    };
+    Ok(value as i32 + low)
+}
+
+fn decode_subexp(r: &mut BitReader, num_syms: u32) -> Result<u32> {
+    let mut i = 0;
+    let mut mk = 0;
+    let k = 3;
+    loop {
+        let b2 = if i > 0 { k + i - 1 } else { k };
+        let a = 1 << b2;
+        if num_syms <= mk + 3 * a {
+            return Ok(r.read_ns(num_syms - mk)? + mk);
+        }
+        let subexp_more_bits = r.read_bit()?;
+        if !subexp_more_bits {
+            return Ok(r.read_bits(b2)? + mk);
+        }
+        i += 1;
+        mk += a;
+    }
+}
+
+const fn inverse_recenter(r: u32, v: u32) -> u32 {
+    if v > 2 * r {
+        v
+    } else if v & 1 == 1 {
+        r - ((v + 1) >> 1)
+    } else {
+        r + (v >> 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+    use crate::bitstream::{
+        obu::{read_obu, section5_obus},
+        test_stream::{
+            COLOR_CONFIG, Grain, inter_frame, key_frame, sequence_header, show_existing_frame,
+        },
+    };
+
+    fn sequence() -> SequenceHeader {
+        let data = sequence_header();
+        let (obu, _) = read_obu(&data).expect("valid OBU");
+        SequenceHeader::parse(obu.payload).expect("valid sequence header")
+    }
+
+    fn parse(parser: &mut HeaderParser, data: &[u8]) -> Vec<FrameHeader> {
+        let mut headers = Vec::new();
+        for obu in section5_obus(data) {
+            let obu = obu.expect("valid OBU");
+            if let Some(header) = parser.parse_obu(&obu).expect("valid header") {
+                headers.push(header);
+            }
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_sequence_header() {
+        let seq = sequence();
+
+        assert_eq!(seq.color_config, COLOR_CONFIG);
+        assert_eq!((seq.max_frame_width, seq.max_frame_height), (64, 64));
+        assert_eq!(seq.order_hint_bits, 7);
+        assert_eq!(seq.force_screen_content_tools, SELECT);
+        assert!(seq.film_grain_params_present);
+    }
+
+    #[test]
+    fn shows_existing_frames_with_their_grain() {
+        let grain = GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 40]]),
+            random_seed: 7,
+            ..GrainTableSegment::default()
+        };
+        let data = [
+            sequence_header(),
+            key_frame(Grain::None),
+            inter_frame(2, false, 0x04, Grain::Update(&grain)),
+            show_existing_frame(2),
+            show_existing_frame(0),
+        ]
+        .concat();
+
+        let headers = parse(&mut HeaderParser::default(), &data);
+        let shown = headers
+            .iter()
+            .map(|header| (header.shown, header.show_existing_frame))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            [
+                (true, None),
+                (false, None),
+                (true, Some(2)),
+                (true, Some(0))
+            ]
+        );
+        let film_grain = headers
+            .iter()
+            .map(|header| header.film_grain.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(film_grain, [None, Some(&grain), Some(&grain), None]);
+    }
+
+    #[test]
+    fn loads_reset_grain_of_frames_without_grain() {
+        let grain = GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 40]]),
+            random_seed: 7,
+            ..GrainTableSegment::default()
+        };
+        let data = [
+            sequence_header(),
+            key_frame(Grain::Update(&grain)),
+            inter_frame(1, true, 0x02, Grain::None),
+            inter_frame(
+                2,
+                true,
+                0,
+                Grain::Reference {
+                    random_seed: 9,
+                    ref_idx: 1,
+                },
+            ),
+            inter_frame(
+                3,
+                true,
+                0,
+                Grain::Reference {
+                    random_seed: 11,
+                    ref_idx: 0,
+                },
+            ),
+        ]
+        .concat();
+
+        let headers = parse(&mut HeaderParser::default(), &data);
+        let film_grain = headers
+            .iter()
+            .map(|header| header.film_grain.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            film_grain,
+            [
+                Some(grain.clone()),
+                None,
+                None,
+                Some(GrainTableSegment {
+                    random_seed: 11,
+                    update_grain: false,
+                    film_grain_params_ref_idx: Some(0),
+                    ..grain
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_layers_outside_the_operating_point() {
+        let seq = SequenceHeader {
+            operating_points: vec![OperatingPoint {
+                // Temporal layer 0 and spatial layer 0
+                idc: 0x101,
+                decoder_model_present: false,
+            }],
+            ..sequence()
+        };
+        let obu = |temporal_id, has_extension| Obu {
+            obu_type: OBU_FRAME,
+            temporal_id,
+            spatial_id: 0,
+            has_extension,
+            has_size_field: false,
+            header: &[],
+            payload: &[],
+        };
+
+        assert!(seq.in_operating_point(&obu(0, true)));
+        assert!(!seq.in_operating_point(&obu(1, true)));
+        assert!(seq.in_operating_point(&obu(1, false)));
+    }
+
+    #[test]
+    fn relative_dist_wraps_around() {
+        let seq = sequence();
+
+        assert_eq!(seq.relative_dist(5, 3), 2);
+        assert_eq!(seq.relative_dist(1, 127), 2);
+        assert_eq!(seq.relative_dist(127, 1), -2);
+    }
+
+    #[test]
+    fn decodes_recentered_values() {
+        assert_eq!(tile_log2(1, 1), 0);
+        assert_eq!(tile_log2(1, 5), 3);
+        assert_eq!(tile_log2(64, 64), 0);
+        assert_eq!(inverse_recenter(4, 9), 9);
+        assert_eq!(inverse_recenter(4, 3), 2);
+        assert_eq!(inverse_recenter(4, 4), 6);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result, ensure};
+
+/// The length of the IVF file header.
+const IVF_HEADER_LEN: usize = 32;
+/// The length of the header preceding each IVF frame.
+const IVF_FRAME_HEADER_LEN: usize = 12;
+
+/// The fields of the IVF file header which are needed to read a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct IvfHeader {
+    /// The length of the file header, including any unknown fields.
+    pub header_len: usize,
+    /// The number of time units per second.
+    pub timebase_rate: u32,
+    /// The duration of one time unit, in `1 / timebase_rate` seconds.
+    pub timebase_scale: u32,
+}
+
+impl IvfHeader {
+    /// Converts a presentation timestamp to 10,000,000ths of a second,
+    /// as used by grain tables.
+    pub fn timestamp(self, pts: u64) -> u64 {
+        let time = u128::from(pts) * u128::from(self.timebase_scale) * 10_000_000
+            / u128::from(self.timebase_rate);
+        u64::try_from(time).unwrap_or(u64::MAX)
+    }
+}
+
+/// A single frame of an IVF file, which contains one temporal unit.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct IvfFrame<'a> {
+    pub pts: u64,
+    pub data: &'a [u8],
+}
+
+/// Reads the header of an AV1 IVF file and splits the rest of
+/// the file into frames.
+pub(super) fn read_ivf(data: &[u8]) -> Result<(IvfHeader, Vec<IvfFrame<'_>>)> {
+    let header = data
+        .get(..IVF_HEADER_LEN)
+        .context("IVF file is too short for its header")?;
+    ensure!(header.starts_with(b"DKIF"), "Missing IVF signature");
+    ensure!(
+        header.get(8..12) == Some(b"AV01".as_slice()),
+        "IVF file does not contain AV1"
+    );
+    let header_len = usize::from(read_u16(header, 6)?).max(IVF_HEADER_LEN);
+    let timebase_rate = read_u32(header, 16)?;
+    let timebase_scale = read_u32(header, 20)?;
+    ensure!(timebase_rate > 0, "IVF timebase rate must be non-zero");
+    // A scale of 0 would give every frame the same timestamp.
+    ensure!(timebase_scale > 0, "IVF timebase scale must be non-zero");
+
+    let mut rest = data
+        .get(header_len..)
+        .context("IVF file is too short for its header")?;
+    let mut frames = Vec::new();
+    while !rest.is_empty() {
+        let frame_size = read_u32(rest, 0).context("Truncated IVF frame header")? as usize;
+        let pts = read_u64(rest, 4).context("Truncated IVF frame header")?;
+        let end = IVF_FRAME_HEADER_LEN + frame_size;
+        let frame_data = rest
+            .get(IVF_FRAME_HEADER_LEN..end)
+            .context("IVF frame extends past the end of the file")?;
+        frames.push(IvfFrame {
+            pts,
+            data: frame_data,
+        });
+        rest = rest.get(end..).unwrap_or_default();
+    }
+
+    Ok((
+        IvfHeader {
+            header_len,
+            timebase_rate,
+            timebase_scale,
+        },
+        frames,
+    ))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Unexpected end of IVF data")?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Unexpected end of IVF data")?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Unexpected end of IVF data")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitstream::test_stream::{ivf, sequence_header, temporal_delimiter};
+
+    #[test]
+    fn rejects_zero_timebase() {
+        let data = ivf(&[[temporal_delimiter(), sequence_header()].concat()]);
+        let (header, frames) = read_ivf(&data).expect("valid IVF file");
+        assert_eq!((header.timebase_rate, header.timebase_scale), (25, 1));
+        assert_eq!(header.timestamp(frames.len() as u64), 400_000);
+
+        for offset in [16, 20] {
+            let mut data = data.clone();
+            data.splice(offset..offset + 4, 0u32.to_le_bytes());
+            let error = read_ivf(&data).expect_err("zero timebase should fail");
+            assert!(error.to_string().contains("must be non-zero"), "{error}");
+        }
+    }
+}
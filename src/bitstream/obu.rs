@@ -0,0 +1,215 @@
+use anyhow::{Context, Result, bail, ensure};
+
+pub(super) const OBU_SEQUENCE_HEADER: u8 = 1;
+pub(super) const OBU_TEMPORAL_DELIMITER: u8 = 2;
+pub(super) const OBU_FRAME_HEADER: u8 = 3;
+pub(super) const OBU_TILE_GROUP: u8 = 4;
+pub(super) const OBU_FRAME: u8 = 6;
+pub(super) const OBU_REDUNDANT_FRAME_HEADER: u8 = 7;
+
+/// How the OBUs of a raw AV1 stream are delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObuStreamFormat {
+    /// The low overhead bitstream format described in section 5 of the
+    /// AV1 specification, where every OBU has a size field. This is the
+    /// format used within IVF, MP4 and Matroska files.
+    Section5,
+    /// The length-delimited bitstream format described in Annex B
+    /// of the AV1 specification.
+    AnnexB,
+}
+
+/// A single OBU, borrowed from the stream it was read from.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Obu<'a> {
+    pub obu_type: u8,
+    pub temporal_id: u32,
+    pub spatial_id: u32,
+    pub has_extension: bool,
+    pub has_size_field: bool,
+    /// The OBU header, including the extension header if present.
+    pub header: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+/// Reads a `leb128()` value from the start of `data`, returning the value
+/// and the number of bytes it occupied.
+pub(super) fn read_leb128(data: &[u8]) -> Result<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().take(8).enumerate() {
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    bail!("Invalid or truncated leb128 value")
+}
+
+/// Appends `value` to `output` as a `leb128()` value.
+pub(super) fn write_leb128(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+/// Reads the OBU at the start of `data`, returning it and the number of
+/// bytes it occupied. OBUs without a size field extend to the end of `data`.
+pub(super) fn read_obu(data: &[u8]) -> Result<(Obu<'_>, usize)> {
+    let &byte = data.first().context("Expected an OBU header")?;
+    ensure!(byte & 0x80 == 0, "OBU forbidden bit is set");
+    let obu_type = (byte >> 3) & 0xf;
+    let has_extension = byte & 0x04 != 0;
+    let has_size_field = byte & 0x02 != 0;
+
+    let (temporal_id, spatial_id, header_len) = if has_extension {
+        let &extension = data.get(1).context("Truncated OBU extension header")?;
+        (
+            u32::from(extension >> 5),
+            u32::from((extension >> 3) & 0x3),
+            2,
+        )
+    } else {
+        (0, 0, 1)
+    };
+    let rest = data.get(header_len..).unwrap_or_default();
+    let (payload_start, payload_len) = if has_size_field {
+        let (size, size_len) = read_leb128(rest)?;
+        (header_len + size_len, usize::try_from(size)?)
+    } else {
+        (header_len, rest.len())
+    };
+    let end = payload_start
+        .checked_add(payload_len)
+        .context("OBU size overflows")?;
+    let payload = data
+        .get(payload_start..end)
+        .context("OBU extends past the end of the data")?;
+
+    Ok((
+        Obu {
+            obu_type,
+            temporal_id,
+            spatial_id,
+            has_extension,
+            has_size_field,
+            header: data.get(..header_len).unwrap_or_default(),
+            payload,
+        },
+        end,
+    ))
+}
+
+/// Splits data in the low overhead bitstream format into OBUs.
+pub(super) fn section5_obus(mut data: &[u8]) -> impl Iterator<Item = Result<Obu<'_>>> {
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        Some(read_obu(data).map(|(obu, len)| {
+            data = data.get(len..).unwrap_or_default();
+            obu
+        }))
+    })
+}
+
+/// Splits data in the Annex B bitstream format into temporal units,
+/// each of which is a list of OBUs.
+pub(super) fn annexb_temporal_units(data: &[u8]) -> Result<Vec<Vec<Obu<'_>>>> {
+    let mut temporal_units = Vec::new();
+    for temporal_unit in length_delimited(data) {
+        let mut obus = Vec::new();
+        for frame_unit in length_delimited(temporal_unit?) {
+            for obu_data in length_delimited(frame_unit?) {
+                let (obu, _) = read_obu(obu_data?)?;
+                obus.push(obu);
+            }
+        }
+        temporal_units.push(obus);
+    }
+    Ok(temporal_units)
+}
+
+/// Splits `data` into units which are each preceded by their `leb128()`
+/// size.
+fn length_delimited(mut data: &[u8]) -> impl Iterator<Item = Result<&[u8]>> {
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        let unit = read_leb128(data).and_then(|(size, size_len)| {
+            let end = usize::try_from(size)?
+                .checked_add(size_len)
+                .context("Unit size overflows")?;
+            let unit = data
+                .get(size_len..end)
+                .context("Unit extends past the end of the data")?;
+            data = data.get(end..).unwrap_or_default();
+            Ok(unit)
+        });
+        if unit.is_err() {
+            data = &[];
+        }
+        Some(unit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leb128_round_trips() {
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX)] {
+            let mut data = Vec::new();
+            write_leb128(&mut data, value);
+            assert_eq!(
+                read_leb128(&data).expect("valid leb128"),
+                (value, data.len())
+            );
+        }
+        assert!(read_leb128(&[0x80]).is_err());
+    }
+
+    #[test]
+    fn splits_section5_obus() {
+        // A temporal delimiter, followed by a padding OBU with an extension
+        // header and a two byte payload.
+        let data = [0x12, 0x00, 0x7e, 0x48, 0x02, 0xaa, 0xbb];
+        let obus = section5_obus(&data)
+            .collect::<Result<Vec<_>>>()
+            .expect("valid OBUs");
+
+        let [delimiter, padding] = obus.as_slice() else {
+            panic!("expected 2 OBUs, got {}", obus.len());
+        };
+        assert_eq!(delimiter.obu_type, OBU_TEMPORAL_DELIMITER);
+        assert!(delimiter.payload.is_empty());
+        assert_eq!(padding.obu_type, 15);
+        assert_eq!((padding.temporal_id, padding.spatial_id), (2, 1));
+        assert_eq!(padding.payload, [0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn splits_annexb_temporal_units() {
+        // Two temporal units, each with one frame unit containing
+        // a temporal delimiter without a size field.
+        let data = [0x03, 0x02, 0x01, 0x10, 0x03, 0x02, 0x01, 0x10];
+        let temporal_units = annexb_temporal_units(&data).expect("valid temporal units");
+
+        let obu_types = temporal_units
+            .iter()
+            .map(|obus| obus.iter().map(|obu| obu.obu_type).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            obu_types,
+            [[OBU_TEMPORAL_DELIMITER], [OBU_TEMPORAL_DELIMITER]]
+        );
+        assert!(annexb_temporal_units(&[0x05, 0x01]).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{Context, Result, ensure};
+
+use super::{
+    BitReader, BitWriter, NUM_REF_FRAMES,
+    headers::{FrameHeader, HeaderParser},
+    ivf::{IvfFrame, IvfHeader, read_ivf},
+    obu::{
+        OBU_FRAME, OBU_FRAME_HEADER, OBU_REDUNDANT_FRAME_HEADER, OBU_SEQUENCE_HEADER,
+        OBU_TEMPORAL_DELIMITER, Obu, section5_obus, write_leb128,
+    },
+    write_film_grain_params,
+};
+use crate::GrainTableSegment;
+
+/// Rewrites the film grain parameters of an AV1 IVF file, leaving
+/// everything else, including the tile data, unchanged.
+///
+/// With `Some(grain_table)`, `film_grain_params_present` is set in every
+/// sequence header, and each frame which signals film grain parameters
+/// uses the segment covering the time at which it is first shown, or no
+/// film grain if no segment covers that time. The random seed of each
+/// segment is used for its first frame, and changes on every following
+/// frame in the same way as libaom. With `None`, film grain is removed
+/// from the stream.
+///
+/// # Errors
+///
+/// - If the data is not a valid IVF file containing AV1
+/// - If an OBU or frame header cannot be parsed, such as when the stream
+///   uses features which are not supported by this parser
+/// - If the stream uses scalability, which is signalled by OBU
+///   extension headers
+/// - If a segment cannot be signalled, as described in
+///   [`write_film_grain_params`]
+#[inline]
+pub fn rewrite_film_grain_ivf(
+    data: &[u8],
+    grain_table: Option<&[GrainTableSegment]>,
+) -> Result<Vec<u8>> {
+    let (header, frames) = read_ivf(data)?;
+    let mut rewriter = Rewriter {
+        parser: HeaderParser::default(),
+        grain_table,
+        display_times: display_times(header, &frames)?,
+        frame_count: 0,
+        random_seed: None,
+        frame_header: None,
+    };
+
+    let mut output = data.get(..header.header_len).unwrap_or_default().to_vec();
+    for frame in &frames {
+        let mut frame_data = Vec::new();
+        for obu in ivf_frame_obus(frame.data) {
+            rewriter.rewrite_obu(&obu?, &mut frame_data)?;
+        }
+        let frame_size = u32::try_from(frame_data.len()).context("IVF frame is too large")?;
+        output.extend_from_slice(&frame_size.to_le_bytes());
+        output.extend_from_slice(&frame.pts.to_le_bytes());
+        output.extend_from_slice(&frame_data);
+    }
+    Ok(output)
+}
+
+/// Splits an IVF frame into OBUs, rejecting the OBUs of scalable streams,
+/// since frames outside of the selected operating point are not parsed.
+fn ivf_frame_obus(data: &[u8]) -> impl Iterator<Item = Result<Obu<'_>>> {
+    section5_obus(data).map(|obu| {
+        let obu = obu?;
+        ensure!(
+            !obu.has_extension,
+            "Streams with OBU extension headers are not supported"
+        );
+        Ok(obu)
+    })
+}
+
+/// Finds the time at which each frame with a frame header is first shown,
+/// either directly or by `show_existing_frame`. Frames which are never
+/// shown use the time of their temporal unit.
+fn display_times(header: IvfHeader, frames: &[IvfFrame]) -> Result<Vec<u64>> {
+    let mut parser = HeaderParser::default();
+    // The time at which each frame is decoded and shown
+    let mut times: Vec<(u64, Option<u64>)> = Vec::new();
+    let mut ref_frames = [None; NUM_REF_FRAMES];
+    for frame in frames {
+        let time = header.timestamp(frame.pts);
+        for obu in ivf_frame_obus(frame.data) {
+            let Some(frame_header) = parser.parse_obu(&obu?)? else {
+                continue;
+            };
+            let frame_index = if let Some(slot) = frame_header.show_existing_frame {
+                let Some(&Some(frame_index)) = ref_frames.get(slot) else {
+                    continue;
+                };
+                if let Some((_, shown @ None)) = times.get_mut(frame_index) {
+                    *shown = Some(time);
+                }
+                frame_index
+            } else {
+                times.push((time, frame_header.shown.then_some(time)));
+                times.len() - 1
+            };
+            for (i, ref_frame) in ref_frames.iter_mut().enumerate() {
+                if (frame_header.refresh_frame_flags >> i) & 1 == 1 {
+                    *ref_frame = Some(frame_index);
+                }
+            }
+        }
+    }
+
+    Ok(times
+        .into_iter()
+        .map(|(decoded, shown)| shown.unwrap_or(decoded))
+        .collect())
+}
+
+struct Rewriter<'a> {
+    parser: HeaderParser,
+    grain_table: Option<&'a [GrainTableSegment]>,
+    display_times: Vec<u64>,
+    /// The number of frame headers rewritten so far, not counting
+    /// `show_existing_frame`.
+    frame_count: usize,
+    /// The index of the segment used by the previous frame, and the random
+    /// seed of the next frame using it.
+    random_seed: Option<(usize, u16)>,
+    /// The rewritten uncompressed header of the current frame, which is
+    /// repeated by redundant frame headers.
+    frame_header: Option<BitWriter>,
+}
+
+impl Rewriter<'_> {
+    fn rewrite_obu(&mut self, obu: &Obu, output: &mut Vec<u8>) -> Result<()> {
+        let frame_header = self.parser.parse_obu(obu)?;
+        let payload = match (obu.obu_type, frame_header) {
+            (OBU_SEQUENCE_HEADER, _) => {
+                let seq = self.parser.sequence().context("Missing sequence header")?;
+                let mut writer = BitWriter::new();
+                copy_bits(&mut writer, obu.payload, seq.film_grain_params_present_bit)?;
+                writer.write_bit(self.grain_table.is_some());
+                Some(trailing_bits(writer))
+            }
+            (OBU_TEMPORAL_DELIMITER, _) => {
+                self.frame_header = None;
+                None
+            }
+            (_, Some(frame_header)) => self.rewrite_frame_header(obu, &frame_header)?,
+            (OBU_FRAME_HEADER | OBU_REDUNDANT_FRAME_HEADER, None) => {
+                self.frame_header.clone().map(trailing_bits)
+            }
+            _ => None,
+        };
+
+        let payload = payload.as_deref().unwrap_or(obu.payload);
+        output.extend_from_slice(obu.header);
+        if obu.has_size_field {
+            write_leb128(output, payload.len() as u64);
+        }
+        output.extend_from_slice(payload);
+        Ok(())
+    }
+
+    /// Returns the rewritten payload of an OBU containing a frame header,
+    /// or `None` if it is unchanged.
+    fn rewrite_frame_header(
+        &mut self,
+        obu: &Obu,
+        frame_header: &FrameHeader,
+    ) -> Result<Option<Vec<u8>>> {
+        if frame_header.show_existing_frame.is_some() {
+            return Ok(None);
+        }
+        let time = self
+            .display_times
+            .get(self.frame_count)
+            .copied()
+            .context("Stream changed between passes")?;
+        self.frame_count += 1;
+        self.frame_header = None;
+        let Some(film_grain_bits) = &frame_header.film_grain_bits else {
+            return Ok(None);
+        };
+
+        let color_config = self
+            .parser
+            .sequence()
+            .context("Missing sequence header")?
+            .color_config;
+        let mut writer = BitWriter::new();
+        copy_bits(&mut writer, obu.payload, film_grain_bits.start)?;
+        if let Some(grain_table) = self.grain_table {
+            let segment = grain_table
+                .iter()
+                .enumerate()
+                .find(|(_, segment)| (segment.start_time..segment.end_time).contains(&time));
+            if let Some((index, segment)) = segment {
+                let random_seed = match self.random_seed {
+                    Some((previous, random_seed)) if previous == index => random_seed,
+                    _ => segment.random_seed,
+                };
+                let segment = GrainTableSegment {
+                    random_seed,
+                    ..segment.clone()
+                };
+                write_film_grain_params(
+                    &segment,
+                    &color_config,
+                    frame_header.frame_type,
+                    &mut writer,
+                )
+                .with_context(|| format!("Cannot signal the segment at time {time}"))?;
+                self.random_seed = Some((index, next_random_seed(random_seed)));
+            } else {
+                // apply_grain
+                writer.write_bit(false);
+            }
+        }
+
+        let payload = if obu.obu_type == OBU_FRAME {
+            let tile_group = obu
+                .payload
+                .get(frame_header.header_bits.div_ceil(8)..)
+                .unwrap_or_default();
+            let mut payload = writer.clone().into_bytes();
+            payload.extend_from_slice(tile_group);
+            payload
+        } else {
+            trailing_bits(writer.clone())
+        };
+        self.frame_header = Some(writer);
+        Ok(Some(payload))
+    }
+}
+
+/// Advances the random seed in the same way as libaom does between frames.
+const fn next_random_seed(random_seed: u16) -> u16 {
+    match random_seed.wrapping_add(3381) {
+        0 => 7391,
+        random_seed => random_seed,
+    }
+}
+
+/// Copies the first `bits` bits of `data` to `writer`.
+fn copy_bits(writer: &mut BitWriter, data: &[u8], bits: usize) -> Result<()> {
+    let mut reader = BitReader::new(data);
+    let mut remaining = bits;
+    while remaining > 0 {
+        let chunk = remaining.min(32);
+        writer.write_bits(reader.read_bits(chunk as u32)?, chunk as u32);
+        remaining -= chunk;
+    }
+    Ok(())
+}
+
+/// Finishes an OBU payload with `trailing_bits()`.
+fn trailing_bits(mut writer: BitWriter) -> Vec<u8> {
+    writer.write_bit(true);
+    writer.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+    use crate::{
+        bitstream::test_stream::{
+            Grain, TILE_DATA, inter_frame, ivf, key_frame, sequence_header, show_existing_frame,
+            temporal_delimiter,
+        },
+        extract_grain_table_ivf,
+    };
+
+    fn segment(start_time: u64, end_time: u64, scaling_shift: u8) -> GrainTableSegment {
+        GrainTableSegment {
+            start_time,
+            end_time,
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+            scaling_shift,
+            ar_coeff_shift: 7,
+            overlap_flag: true,
+            random_seed: 100,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    fn temporal_unit(obus: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = temporal_delimiter();
+        for obu in obus {
+            data.extend_from_slice(obu);
+        }
+        data
+    }
+
+    /// A key frame, followed by a hidden frame which is shown by
+    /// `show_existing_frame` after the next frame.
+    fn stream(grain: [Grain; 3]) -> Vec<u8> {
+        let [key, hidden, inter] = grain;
+        ivf(&[
+            temporal_unit(&[sequence_header(), key_frame(key)]),
+            temporal_unit(&[
+                inter_frame(2, false, 0x02, hidden),
+                inter_frame(1, true, 0x01, inter),
+            ]),
+            temporal_unit(&[show_existing_frame(1)]),
+        ])
+    }
+
+    #[test]
+    fn rewriting_with_the_same_params_is_lossless() {
+        let table = [segment(0, u64::MAX, 8)];
+        let with_seed = |random_seed| GrainTableSegment {
+            random_seed,
+            ..table[0].clone()
+        };
+        // The hidden frame is shown last, but decoded before the inter frame.
+        let data = stream([
+            Grain::Update(&with_seed(100)),
+            Grain::Update(&with_seed(100 + 3381)),
+            Grain::Update(&with_seed(100 + 2 * 3381)),
+        ]);
+
+        let rewritten = rewrite_film_grain_ivf(&data, Some(&table)).expect("valid stream");
+        assert_eq!(rewritten, data);
+    }
+
+    #[test]
+    fn strips_film_grain() {
+        let table = [segment(0, u64::MAX, 8)];
+        let data = stream([Grain::Update(&table[0]), Grain::None, Grain::None]);
+
+        let stripped = rewrite_film_grain_ivf(&data, None).expect("valid stream");
+        assert!(stripped.len() < data.len());
+        let (_, frames) = read_ivf(&stripped).expect("valid IVF");
+        for frame in frames {
+            for obu in section5_obus(frame.data) {
+                let obu = obu.expect("valid OBU");
+                if obu.obu_type == OBU_FRAME {
+                    assert_eq!(obu.payload.last(), Some(&TILE_DATA));
+                }
+            }
+        }
+        assert!(
+            extract_grain_table_ivf(&stripped)
+                .expect("valid stream")
+                .iter()
+                .all(|segment| !segment.apply_grain)
+        );
+    }
+
+    #[test]
+    fn replaces_film_grain_by_display_time() {
+        let table = [
+            segment(0, 800_000, 8),
+            segment(800_000, i64::MAX as u64, 10),
+        ];
+        let data = stream([Grain::None, Grain::None, Grain::None]);
+        let stripped = rewrite_film_grain_ivf(&data, None).expect("valid stream");
+
+        // The hidden frame is shown at 800,000, so it uses the second segment.
+        for data in [data, stripped] {
+            let rewritten = rewrite_film_grain_ivf(&data, Some(&table)).expect("valid stream");
+            assert_eq!(
+                extract_grain_table_ivf(&rewritten).expect("valid stream"),
+                table
+            );
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Builds minimal AV1 streams for testing, with a 64x64 4:2:0 sequence
+//! and frames which only signal what the header parser needs.
+
+use super::{
+    BitWriter, ColorConfig, FrameType,
+    obu::{OBU_FRAME, OBU_FRAME_HEADER, OBU_SEQUENCE_HEADER, OBU_TEMPORAL_DELIMITER, write_leb128},
+    write_film_grain_params,
+};
+use crate::GrainTableSegment;
+
+pub const COLOR_CONFIG: ColorConfig = ColorConfig {
+    mono_chrome: false,
+    subsampling_x: true,
+    subsampling_y: true,
+};
+/// The byte which stands in for the tile data of each frame.
+pub const TILE_DATA: u8 = 0x5a;
+const ORDER_HINT_BITS: u32 = 7;
+
+/// The film grain parameters signalled by a frame.
+#[derive(Debug, Clone, Copy)]
+pub enum Grain<'a> {
+    /// `apply_grain` is 0.
+    None,
+    /// The parameters are signalled in full.
+    Update(&'a GrainTableSegment),
+    /// The parameters are loaded from a reference frame.
+    Reference { random_seed: u16, ref_idx: u32 },
+}
+
+fn obu(obu_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![(obu_type << 3) | 0x02];
+    write_leb128(&mut data, payload.len() as u64);
+    data.extend_from_slice(payload);
+    data
+}
+
+fn write_all(writer: &mut BitWriter, fields: &[(u32, u32)]) {
+    for &(value, bits) in fields {
+        writer.write_bits(value, bits);
+    }
+}
+
+fn trailing_bits(mut writer: BitWriter) -> Vec<u8> {
+    writer.write_bit(true);
+    writer.byte_align();
+    writer.into_bytes()
+}
+
+pub fn temporal_delimiter() -> Vec<u8> {
+    obu(OBU_TEMPORAL_DELIMITER, &[])
+}
+
+pub fn sequence_header() -> Vec<u8> {
+    let mut w = BitWriter::new();
+    write_all(
+        &mut w,
+        &[
+            // seq_profile, still_picture, reduced_still_picture_header
+            (0, 3),
+            (0, 1),
+            (0, 1),
+            // timing_info_present_flag, initial_display_delay_present_flag
+            (0, 1),
+            (0, 1),
+            // One operating point with idc 0 and level 0
+            (0, 5),
+            (0, 12),
+            (0, 5),
+            // 64x64 frames, without frame ids
+            (7, 4),
+            (7, 4),
+            (63, 8),
+            (63, 8),
+            (0, 1),
+            // use_128x128_superblock up to enable_dual_filter
+            (0, 7),
+            // enable_order_hint, enable_jnt_comp, enable_ref_frame_mvs
+            (1, 1),
+            (0, 1),
+            (0, 1),
+            // seq_choose_screen_content_tools, seq_choose_integer_mv
+            (1, 1),
+            (1, 1),
+            (ORDER_HINT_BITS - 1, 3),
+            // enable_superres, enable_cdef, enable_restoration
+            (0, 3),
+            // color_config(): 8-bit 4:2:0 without a color description
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 1),
+            (0, 2),
+            (0, 1),
+            // film_grain_params_present
+            (1, 1),
+        ],
+    );
+    obu(OBU_SEQUENCE_HEADER, &trailing_bits(w))
+}
+
+/// Writes the fields shared by key and inter frames, from `tile_info()`
+/// up to `tx_mode_select`.
+fn write_frame_tools(w: &mut BitWriter) {
+    write_all(
+        w,
+        &[
+            // uniform_tile_spacing_flag
+            (1, 1),
+            // base_q_idx, followed by the Y DC, U DC and U AC deltas
+            (100, 8),
+            (0, 3),
+            // using_qmatrix, segmentation_enabled, delta_q_present
+            (0, 3),
+            // loop_filter_level, sharpness and delta_enabled
+            (0, 16),
+            // tx_mode_select
+            (0, 1),
+        ],
+    );
+}
+
+fn write_grain(w: &mut BitWriter, grain: Grain, frame_type: FrameType) {
+    match grain {
+        Grain::None => w.write_bit(false),
+        Grain::Update(segment) => {
+            write_film_grain_params(segment, &COLOR_CONFIG, frame_type, w).expect("valid segment");
+        }
+        Grain::Reference {
+            random_seed,
+            ref_idx,
+        } => write_all(
+            w,
+            &[(1, 1), (u32::from(random_seed), 16), (0, 1), (ref_idx, 3)],
+        ),
+    }
+}
+
+fn frame(mut w: BitWriter) -> Vec<u8> {
+    w.byte_align();
+    let mut payload = w.into_bytes();
+    payload.push(TILE_DATA);
+    obu(OBU_FRAME, &payload)
+}
+
+/// A shown key frame, which refreshes every reference slot.
+pub fn key_frame(grain: Grain) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    write_all(
+        &mut w,
+        &[
+            // show_existing_frame, frame_type, show_frame
+            (0, 1),
+            (0, 2),
+            (1, 1),
+            // disable_cdf_update, allow_screen_content_tools,
+            // frame_size_override_flag
+            (1, 1),
+            (0, 1),
+            (0, 1),
+            (0, ORDER_HINT_BITS),
+            // render_and_frame_size_different
+            (0, 1),
+        ],
+    );
+    write_frame_tools(&mut w);
+    // reduced_tx_set
+    w.write_bit(false);
+    write_grain(&mut w, grain, FrameType::Key);
+    frame(w)
+}
+
+/// An inter frame which refreshes `refresh_frame_flags` and predicts
+/// from reference slots 0 to 6.
+pub fn inter_frame(
+    order_hint: u32,
+    show_frame: bool,
+    refresh_frame_flags: u32,
+    grain: Grain,
+) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    write_all(
+        &mut w,
+        &[
+            // show_existing_frame, frame_type, show_frame
+            (0, 1),
+            (1, 2),
+            (u32::from(show_frame), 1),
+        ],
+    );
+    if !show_frame {
+        // showable_frame
+        w.write_bit(true);
+    }
+    write_all(
+        &mut w,
+        &[
+            // error_resilient_mode, disable_cdf_update,
+            // allow_screen_content_tools, frame_size_override_flag
+            (0, 1),
+            (1, 1),
+            (0, 1),
+            (0, 1),
+            (order_hint, ORDER_HINT_BITS),
+            // primary_ref_frame
+            (0, 3),
+            (refresh_frame_flags, 8),
+            // frame_refs_short_signaling
+            (0, 1),
+        ],
+    );
+    for ref_frame_idx in 0..7 {
+        w.write_bits(ref_frame_idx, 3);
+    }
+    write_all(
+        &mut w,
+        &[
+            // render_and_frame_size_different, allow_high_precision_mv,
+            // is_filter_switchable, is_motion_mode_switchable
+            (0, 1),
+            (0, 1),
+            (1, 1),
+            (0, 1),
+        ],
+    );
+    write_frame_tools(&mut w);
+    // reference_select, reduced_tx_set, and is_global for each reference
+    write_all(&mut w, &[(0, 1), (0, 1), (0, 7)]);
+    write_grain(&mut w, grain, FrameType::Inter);
+    frame(w)
+}
+
+/// A frame header which shows the frame in reference slot
+/// `frame_to_show_map_idx`.
+pub fn show_existing_frame(frame_to_show_map_idx: u32) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    w.write_bit(true);
+    w.write_bits(frame_to_show_map_idx, 3);
+    obu(OBU_FRAME_HEADER, &trailing_bits(w))
+}
+
+/// Wraps each temporal unit in an IVF frame, with a timebase of 1/25.
+pub fn ivf(temporal_units: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"DKIF");
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&32u16.to_le_bytes());
+    data.extend_from_slice(b"AV01");
+    data.extend_from_slice(&64u16.to_le_bytes());
+    data.extend_from_slice(&64u16.to_le_bytes());
+    data.extend_from_slice(&25u32.to_le_bytes());
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&(temporal_units.len() as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    for (pts, temporal_unit) in temporal_units.iter().enumerate() {
+        data.extend_from_slice(&(temporal_unit.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(pts as u64).to_le_bytes());
+        data.extend_from_slice(temporal_unit);
+    }
+    data
+}
@@ -0,0 +1,617 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This module implements the C API declared in `include/av1_grain.h`.
+// Tables are handed out as opaque pointers, segments are copied in and out
+// of a plain struct, and errors are reported through a thread-local message
+// in the style of `dlerror`, so no Rust types cross the boundary. Structs
+// start with their size, so that fields can be added to their end without
+// breaking callers built against an older header.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int},
+    mem::size_of,
+    ptr,
+};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    Error, GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y_POINTS,
+    NoiseGenArgs, TransferFunction, generate_photon_noise_params, parse_grain_table,
+    write_grain_table,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A list of grain table segments, which C callers only see as a pointer.
+#[derive(Debug, Default)]
+pub struct Av1GrainTable {
+    segments: Vec<GrainTableSegment>,
+}
+
+/// The C representation of a [`GrainTableSegment`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Av1GrainSegment {
+    /// The size of the struct as known to the caller, which the library
+    /// checks and leaves unchanged
+    pub struct_size: usize,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub num_y_points: u8,
+    pub scaling_points_y: [[u8; 2]; NUM_Y_POINTS],
+    pub num_cb_points: u8,
+    pub scaling_points_cb: [[u8; 2]; NUM_UV_POINTS],
+    pub num_cr_points: u8,
+    pub scaling_points_cr: [[u8; 2]; NUM_UV_POINTS],
+    pub scaling_shift: u8,
+    pub ar_coeff_lag: u8,
+    pub num_y_coeffs: u8,
+    pub ar_coeffs_y: [i8; NUM_Y_COEFFS],
+    pub num_cb_coeffs: u8,
+    pub ar_coeffs_cb: [i8; NUM_UV_COEFFS],
+    pub num_cr_coeffs: u8,
+    pub ar_coeffs_cr: [i8; NUM_UV_COEFFS],
+    pub ar_coeff_shift: u8,
+    pub cb_mult: u8,
+    pub cb_luma_mult: u8,
+    pub cb_offset: u16,
+    pub cr_mult: u8,
+    pub cr_luma_mult: u8,
+    pub cr_offset: u16,
+    pub overlap_flag: bool,
+    pub chroma_scaling_from_luma: bool,
+    pub grain_scale_shift: u8,
+    pub random_seed: u16,
+    pub apply_grain: bool,
+    pub update_grain: bool,
+    /// The reference frame whose parameters are reused, or -1 if unknown
+    pub film_grain_params_ref_idx: i8,
+    pub clip_to_restricted_range: bool,
+}
+
+/// The C representation of [`NoiseGenArgs`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Av1GrainNoiseArgs {
+    /// The size of the struct as known to the caller, which the library
+    /// checks
+    pub struct_size: usize,
+    pub iso_setting: u32,
+    pub width: u32,
+    pub height: u32,
+    pub transfer_function: c_int,
+    pub full_range: bool,
+    pub chroma_grain: bool,
+    pub has_random_seed: bool,
+    pub random_seed: u16,
+}
+
+impl From<&GrainTableSegment> for Av1GrainSegment {
+    #[inline]
+    fn from(segment: &GrainTableSegment) -> Self {
+        fn array<T: Copy + Default, const N: usize>(values: &[T]) -> [T; N] {
+            let mut array = [T::default(); N];
+            for (dst, &src) in array.iter_mut().zip(values) {
+                *dst = src;
+            }
+            array
+        }
+
+        Self {
+            struct_size: size_of::<Self>(),
+            start_time: segment.start_time,
+            end_time: segment.end_time,
+            num_y_points: segment.scaling_points_y.len() as u8,
+            scaling_points_y: array(&segment.scaling_points_y),
+            num_cb_points: segment.scaling_points_cb.len() as u8,
+            scaling_points_cb: array(&segment.scaling_points_cb),
+            num_cr_points: segment.scaling_points_cr.len() as u8,
+            scaling_points_cr: array(&segment.scaling_points_cr),
+            scaling_shift: segment.scaling_shift,
+            ar_coeff_lag: segment.ar_coeff_lag,
+            num_y_coeffs: segment.ar_coeffs_y.len() as u8,
+            ar_coeffs_y: array(&segment.ar_coeffs_y),
+            num_cb_coeffs: segment.ar_coeffs_cb.len() as u8,
+            ar_coeffs_cb: array(&segment.ar_coeffs_cb),
+            num_cr_coeffs: segment.ar_coeffs_cr.len() as u8,
+            ar_coeffs_cr: array(&segment.ar_coeffs_cr),
+            ar_coeff_shift: segment.ar_coeff_shift,
+            cb_mult: segment.cb_mult,
+            cb_luma_mult: segment.cb_luma_mult,
+            cb_offset: segment.cb_offset,
+            cr_mult: segment.cr_mult,
+            cr_luma_mult: segment.cr_luma_mult,
+            cr_offset: segment.cr_offset,
+            overlap_flag: segment.overlap_flag,
+            chroma_scaling_from_luma: segment.chroma_scaling_from_luma,
+            grain_scale_shift: segment.grain_scale_shift,
+            random_seed: segment.random_seed,
+            apply_grain: segment.apply_grain,
+            update_grain: segment.update_grain,
+            film_grain_params_ref_idx: segment
+                .film_grain_params_ref_idx
+                .and_then(|ref_idx| i8::try_from(ref_idx).ok())
+                .unwrap_or(-1),
+            clip_to_restricted_range: segment.clip_to_restricted_range,
+        }
+    }
+}
+
+impl TryFrom<&Av1GrainSegment> for GrainTableSegment {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(segment: &Av1GrainSegment) -> Result<Self, Error> {
+        fn prefix<T: Copy, const N: usize>(
+            values: &[T; N],
+            count: u8,
+            name: &'static str,
+        ) -> Result<ArrayVec<T, N>, Error> {
+            let values =
+                values
+                    .get(..usize::from(count))
+                    .ok_or_else(|| Error::InvalidArgument {
+                        argument: name,
+                        value: i64::from(count),
+                        range: 0..=N as i64,
+                    })?;
+            Ok(values.iter().copied().collect())
+        }
+
+        Ok(Self {
+            start_time: segment.start_time,
+            end_time: segment.end_time,
+            scaling_points_y: prefix(
+                &segment.scaling_points_y,
+                segment.num_y_points,
+                "num_y_points",
+            )?,
+            scaling_points_cb: prefix(
+                &segment.scaling_points_cb,
+                segment.num_cb_points,
+                "num_cb_points",
+            )?,
+            scaling_points_cr: prefix(
+                &segment.scaling_points_cr,
+                segment.num_cr_points,
+                "num_cr_points",
+            )?,
+            scaling_shift: segment.scaling_shift,
+            ar_coeff_lag: segment.ar_coeff_lag,
+            ar_coeffs_y: prefix(&segment.ar_coeffs_y, segment.num_y_coeffs, "num_y_coeffs")?,
+            ar_coeffs_cb: prefix(
+                &segment.ar_coeffs_cb,
+                segment.num_cb_coeffs,
+                "num_cb_coeffs",
+            )?,
+            ar_coeffs_cr: prefix(
+                &segment.ar_coeffs_cr,
+                segment.num_cr_coeffs,
+                "num_cr_coeffs",
+            )?,
+            ar_coeff_shift: segment.ar_coeff_shift,
+            cb_mult: segment.cb_mult,
+            cb_luma_mult: segment.cb_luma_mult,
+            cb_offset: segment.cb_offset,
+            cr_mult: segment.cr_mult,
+            cr_luma_mult: segment.cr_luma_mult,
+            cr_offset: segment.cr_offset,
+            overlap_flag: segment.overlap_flag,
+            chroma_scaling_from_luma: segment.chroma_scaling_from_luma,
+            grain_scale_shift: segment.grain_scale_shift,
+            random_seed: segment.random_seed,
+            apply_grain: segment.apply_grain,
+            update_grain: segment.update_grain,
+            film_grain_params_ref_idx: match segment.film_grain_params_ref_idx {
+                -1 => None,
+                ref_idx @ 0..=7 => Some(ref_idx as u8),
+                ref_idx => {
+                    return Err(Error::InvalidArgument {
+                        argument: "film_grain_params_ref_idx",
+                        value: i64::from(ref_idx),
+                        range: -1..=7,
+                    });
+                }
+            },
+            clip_to_restricted_range: segment.clip_to_restricted_range,
+            comments: Vec::new(),
+        })
+    }
+}
+
+/// Stores the outcome of a call for [`av1_grain_last_error`], returning
+/// the value of a successful call or `failure` otherwise.
+fn report<T>(result: Result<T, Error>, failure: T) -> T {
+    let error = result.as_ref().err().map(|error| {
+        let message = error.to_string().replace('\0', " ");
+        CString::new(message).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = error);
+    result.unwrap_or(failure)
+}
+
+/// Converts a C string to a `&str`, failing if it is null or not UTF-8.
+///
+/// # Safety
+///
+/// `string` must be null or point to a nul-terminated string which outlives
+/// the returned reference.
+unsafe fn c_str<'a>(string: *const c_char, name: &'static str) -> Result<&'a str, Error> {
+    if string.is_null() {
+        return Err(Error::NullPointer { argument: name });
+    }
+    // SAFETY: The caller guarantees that the string is nul-terminated.
+    let string = unsafe { CStr::from_ptr(string) };
+    string.to_str().map_err(|source| Error::InvalidUtf8 {
+        argument: name,
+        source,
+    })
+}
+
+/// Dereferences a pointer passed by C, failing if it is null.
+///
+/// # Safety
+///
+/// `pointer` must be null or valid for reads for the returned lifetime.
+unsafe fn c_ref<'a, T>(pointer: *const T, name: &'static str) -> Result<&'a T, Error> {
+    // SAFETY: The caller guarantees that the pointer is valid if non-null.
+    unsafe { pointer.as_ref() }.ok_or(Error::NullPointer { argument: name })
+}
+
+/// Mutably dereferences a pointer passed by C, failing if it is null.
+///
+/// # Safety
+///
+/// `pointer` must be null or valid for reads and writes for the returned
+/// lifetime, without other references to it.
+unsafe fn c_mut<'a, T>(pointer: *mut T, name: &'static str) -> Result<&'a mut T, Error> {
+    // SAFETY: The caller guarantees that the pointer is valid if non-null.
+    unsafe { pointer.as_mut() }.ok_or(Error::NullPointer { argument: name })
+}
+
+/// Checks the `struct_size` which starts a struct passed by C, failing if
+/// the pointer is null or the struct is smaller than the one this library
+/// was built with.
+///
+/// A larger size comes from a caller built against a newer header, whose
+/// additional fields at the end of the struct are ignored.
+///
+/// # Safety
+///
+/// `pointer` must be null or point to a struct starting with its size.
+unsafe fn check_struct_size<T>(pointer: *const T, name: &'static str) -> Result<(), Error> {
+    // SAFETY: The caller guarantees that the struct starts with its size.
+    let struct_size = *unsafe { c_ref(pointer.cast::<usize>(), name) }?;
+    if struct_size < size_of::<T>() {
+        return Err(Error::StructSize {
+            argument: name,
+            expected: size_of::<T>(),
+            actual: struct_size,
+        });
+    }
+    Ok(())
+}
+
+/// Dereferences a struct passed by C after checking its `struct_size`.
+///
+/// # Safety
+///
+/// `pointer` must be null or point to a struct starting with its size, and
+/// be valid for reads of that size for the returned lifetime.
+unsafe fn c_struct_ref<'a, T>(pointer: *const T, name: &'static str) -> Result<&'a T, Error> {
+    // SAFETY: The caller guarantees that the pointer is valid if non-null,
+    // and the size check ensures that the whole struct can be read.
+    unsafe {
+        check_struct_size(pointer, name)?;
+        c_ref(pointer, name)
+    }
+}
+
+/// Mutably dereferences a struct passed by C after checking its
+/// `struct_size`.
+///
+/// # Safety
+///
+/// `pointer` must be null or point to a struct starting with its size, and
+/// be valid for reads and writes of that size for the returned lifetime,
+/// without other references to it.
+unsafe fn c_struct_mut<'a, T>(pointer: *mut T, name: &'static str) -> Result<&'a mut T, Error> {
+    // SAFETY: The caller guarantees that the pointer is valid if non-null,
+    // and the size check ensures that the whole struct can be accessed.
+    unsafe {
+        check_struct_size(pointer, name)?;
+        c_mut(pointer, name)
+    }
+}
+
+/// Returns a description of the last failure on the calling thread, or null
+/// if the last call succeeded.
+///
+/// The string is valid until the next call to this library on the same
+/// thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn av1_grain_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |error| error.as_ptr())
+    })
+}
+
+/// Creates an empty table, which must be freed with
+/// [`av1_grain_table_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn av1_grain_table_new() -> *mut Av1GrainTable {
+    Box::into_raw(Box::default())
+}
+
+/// Frees a table. Does nothing if `table` is null.
+///
+/// # Safety
+///
+/// `table` must be null or a table returned by this library which has not
+/// been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_free(table: *mut Av1GrainTable) {
+    if !table.is_null() {
+        // SAFETY: The caller guarantees that the table was allocated by
+        // `Box::into_raw` and is not used after this call.
+        drop(unsafe { Box::from_raw(table) });
+    }
+}
+
+/// Parses a table in the filmgrn1 text format, returning null on failure.
+///
+/// # Safety
+///
+/// `text` must be null or point to a nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_parse(text: *const c_char) -> *mut Av1GrainTable {
+    // SAFETY: The caller guarantees that the string is valid.
+    let result = unsafe { c_str(text, "text") }
+        .and_then(parse_grain_table)
+        .map(|segments| Box::into_raw(Box::new(Av1GrainTable { segments })));
+    report(result, ptr::null_mut())
+}
+
+/// Writes a table in the filmgrn1 text format to the file at `path`,
+/// returning 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table, and `path` must be null or point
+/// to a nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_write(
+    table: *const Av1GrainTable,
+    path: *const c_char,
+) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that both pointers are valid.
+        let (table, path) = unsafe { (c_ref(table, "table")?, c_str(path, "path")?) };
+        write_grain_table(path, &table.segments)
+    })();
+    report(result.map(|()| 0), -1)
+}
+
+/// Returns the number of segments in a table, or 0 if `table` is null.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_len(table: *const Av1GrainTable) -> usize {
+    // SAFETY: The caller guarantees that the table is valid.
+    let result = unsafe { c_ref(table, "table") }.map(|table| table.segments.len());
+    report(result, 0)
+}
+
+/// Copies the segment at `index` into `out`, returning 0 on success and -1
+/// on failure.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table, and `out` must be null or point
+/// to a segment whose `struct_size` is set, valid for reads and writes of
+/// that size.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_get(
+    table: *const Av1GrainTable,
+    index: usize,
+    out: *mut Av1GrainSegment,
+) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that both pointers are valid.
+        let (table, out) = unsafe { (c_ref(table, "table")?, c_struct_mut(out, "out")?) };
+        let segment = table.segments.get(index).ok_or(Error::IndexOutOfBounds {
+            index,
+            len: table.segments.len(),
+        })?;
+        *out = Av1GrainSegment {
+            struct_size: out.struct_size,
+            ..Av1GrainSegment::from(segment)
+        };
+        Ok(0)
+    })();
+    report(result, -1)
+}
+
+/// Replaces the segment at `index`, returning 0 on success and -1 on
+/// failure.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table, and `segment` must be null or
+/// point to a segment whose `struct_size` is set, valid for reads of that
+/// size.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_set(
+    table: *mut Av1GrainTable,
+    index: usize,
+    segment: *const Av1GrainSegment,
+) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that both pointers are valid.
+        let (table, segment) =
+            unsafe { (c_mut(table, "table")?, c_struct_ref(segment, "segment")?) };
+        let len = table.segments.len();
+        let slot = table
+            .segments
+            .get_mut(index)
+            .ok_or(Error::IndexOutOfBounds { index, len })?;
+        *slot = GrainTableSegment::try_from(segment)?;
+        Ok(0)
+    })();
+    report(result, -1)
+}
+
+/// Appends a segment to the end of a table, returning 0 on success and -1
+/// on failure.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table, and `segment` must be null or
+/// point to a segment whose `struct_size` is set, valid for reads of that
+/// size.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_push(
+    table: *mut Av1GrainTable,
+    segment: *const Av1GrainSegment,
+) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that both pointers are valid.
+        let (table, segment) =
+            unsafe { (c_mut(table, "table")?, c_struct_ref(segment, "segment")?) };
+        table.segments.push(GrainTableSegment::try_from(segment)?);
+        Ok(0)
+    })();
+    report(result, -1)
+}
+
+/// Removes the segment at `index`, returning 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `table` must be null or a valid table.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_table_remove(table: *mut Av1GrainTable, index: usize) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that the table is valid.
+        let table = unsafe { c_mut(table, "table")? };
+        if index >= table.segments.len() {
+            return Err(Error::IndexOutOfBounds {
+                index,
+                len: table.segments.len(),
+            });
+        }
+        table.segments.remove(index);
+        Ok(0)
+    })();
+    report(result, -1)
+}
+
+/// Generates photon noise parameters for the segment of video from
+/// `start_time` to `end_time`, writing them to `out`. Returns 0 on success
+/// and -1 on failure.
+///
+/// # Safety
+///
+/// `args` and `out` must be null or point to structs whose `struct_size` is
+/// set, valid for reads of that size, and for writes in the case of `out`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn av1_grain_generate_photon_noise(
+    start_time: u64,
+    end_time: u64,
+    args: *const Av1GrainNoiseArgs,
+    out: *mut Av1GrainSegment,
+) -> c_int {
+    let result = (|| {
+        // SAFETY: The caller guarantees that both pointers are valid.
+        let (args, out) = unsafe { (c_struct_ref(args, "args")?, c_struct_mut(out, "out")?) };
+        let transfer_function = match args.transfer_function {
+            0 => TransferFunction::BT1886,
+            1 => TransferFunction::SMPTE2084,
+            other => {
+                return Err(Error::InvalidArgument {
+                    argument: "transfer_function",
+                    value: i64::from(other),
+                    range: 0..=1,
+                });
+            }
+        };
+        let segment = generate_photon_noise_params(
+            start_time,
+            end_time,
+            NoiseGenArgs {
+                iso_setting: args.iso_setting,
+                width: args.width,
+                height: args.height,
+                transfer_function,
+                full_range: args.full_range,
+                chroma_grain: args.chroma_grain,
+                random_seed: args.has_random_seed.then_some(args.random_seed),
+            },
+        );
+        *out = Av1GrainSegment {
+            struct_size: out.struct_size,
+            ..Av1GrainSegment::from(&segment)
+        };
+        Ok(0)
+    })();
+    report(result, -1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // Built from `tests/capi.c` by the build script
+    #[link(name = "av1_grain_capi_test", kind = "static")]
+    unsafe extern "C" {
+        fn av1_grain_capi_segment_size() -> usize;
+        fn av1_grain_capi_noise_args_size() -> usize;
+        fn av1_grain_capi_test(path: *const c_char) -> c_int;
+    }
+
+    #[test]
+    fn header_matches_layout() {
+        // SAFETY: The functions have no preconditions.
+        let sizes = unsafe {
+            (
+                av1_grain_capi_segment_size(),
+                av1_grain_capi_noise_args_size(),
+            )
+        };
+        assert_eq!(
+            sizes,
+            (size_of::<Av1GrainSegment>(), size_of::<Av1GrainNoiseArgs>())
+        );
+    }
+
+    #[test]
+    fn c_program() {
+        let path = env::temp_dir().join(format!("av1-grain-capi-{}.tbl", std::process::id()));
+        let path_str = CString::new(path.to_str().expect("UTF-8 path")).expect("no nul bytes");
+        // SAFETY: The path is a valid C string.
+        let failed_line = unsafe { av1_grain_capi_test(path_str.as_ptr()) };
+        std::fs::remove_file(&path).ok();
+        assert_eq!(
+            failed_line, 0,
+            "C test failed at tests/capi.c:{failed_line}"
+        );
+    }
+}
@@ -74,7 +74,10 @@ use std::{
 
 use arrayvec::ArrayVec;
 
-use crate::{DEFAULT_GRAIN_SEED, GrainTableSegment, NUM_Y_POINTS, ScalingPoints};
+use crate::{
+    DEFAULT_GRAIN_SEED, Error, GrainTableSegment, NUM_Y_POINTS, ScalingPoints,
+    util::line_annotation,
+};
 
 const PQ_M1: f32 = 2610. / 16384.;
 const PQ_M2: f32 = 128. * 2523. / 4096.;
@@ -119,7 +122,9 @@ pub struct NoiseGenArgs {
     pub width: u32,
     pub height: u32,
     pub transfer_function: TransferFunction,
-    /// Whether the input is full range or limited range
+    /// Whether the input is full range or limited range. The parameters of
+    /// limited range inputs clip the output to the restricted range after
+    /// adding grain.
     pub full_range: bool,
     pub chroma_grain: bool,
     pub random_seed: Option<u16>,
@@ -158,6 +163,11 @@ pub fn generate_photon_noise_params(
         chroma_scaling_from_luma: args.chroma_grain,
         grain_scale_shift: 0,
         random_seed: args.random_seed.unwrap_or(DEFAULT_GRAIN_SEED),
+        apply_grain: true,
+        update_grain: true,
+        film_grain_params_ref_idx: None,
+        clip_to_restricted_range: !args.full_range,
+        comments: Vec::new(),
     }
 }
 
@@ -206,6 +216,11 @@ pub fn generate_photon_noise_params(
 /// using the standard film grain table format supported by
 /// aomenc, rav1e, and svt-av1.
 ///
+/// As in aomenc, the parameters of segments with `update_grain` unset are
+/// not written, since they are reused from the previous segment.
+/// `clip_to_restricted_range` is not written either, as the format has no
+/// place for it; see [`WriteMode::Annotated`] for tables which keep it.
+///
 /// # Errors
 ///
 /// - If the output file cannot be written to
@@ -213,27 +228,130 @@ pub fn generate_photon_noise_params(
 pub fn write_grain_table<P: AsRef<Path>>(
     filename: P,
     params: &[GrainTableSegment],
-) -> anyhow::Result<()> {
-    let mut file = BufWriter::new(File::create(filename)?);
-    writeln!(&mut file, "filmgrn1")?;
+) -> Result<(), Error> {
+    write_grain_table_to(BufWriter::new(File::create(filename)?), params)
+}
+
+/// Write a set of film grain params to any writer, using the same format as
+/// [`write_grain_table`]. The writer is flushed once the table is written.
+///
+/// The table is written in many small pieces, so unbuffered writers such as
+/// files and sockets should be wrapped in a [`BufWriter`].
+///
+/// # Errors
+///
+/// - If the output cannot be written to
+#[inline]
+pub fn write_grain_table_to<W: Write>(
+    output: W,
+    params: &[GrainTableSegment],
+) -> Result<(), Error> {
+    write_grain_table_with_mode(output, params, WriteMode::Plain)
+}
+
+/// Write a set of film grain params to any writer like
+/// [`write_grain_table_to`], formatted as described by `mode`.
+///
+/// # Errors
+///
+/// - If the output cannot be written to
+#[inline]
+pub fn write_grain_table_with_mode<W: Write>(
+    mut output: W,
+    params: &[GrainTableSegment],
+    mode: WriteMode,
+) -> Result<(), Error> {
+    writeln!(output, "filmgrn1")?;
     for segment in params {
-        write_film_grain_segment(segment, &mut file)?;
+        write_film_grain_segment(segment, &mut output, mode)?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+/// How a grain table is formatted by [`write_grain_table_with_mode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// The format written by aomenc and SVT-AV1, which both of them can read.
+    /// The comments of segments and `clip_to_restricted_range` are not
+    /// written.
+    #[default]
+    Plain,
+    /// Writes the comments of each segment before its `E` line, and labels
+    /// each value of the `p` lines and each scaling point with its name, in
+    /// a comment at the end of the line. `clip_to_restricted_range` is
+    /// written as a 13th value on the `p` line when it is set. This is meant
+    /// for editing tables by hand, and can be parsed by this crate but not
+    /// by aomenc or SVT-AV1.
+    Annotated,
+}
+
+/// Formats a set of film grain params as a table, using the same format as
+/// [`write_grain_table`].
+#[must_use]
+#[inline]
+pub fn grain_table_to_string(params: &[GrainTableSegment]) -> String {
+    let mut output = Vec::new();
+    write_grain_table_to(&mut output, params).expect("writing to a Vec cannot fail");
+    String::from_utf8(output).expect("grain tables are ASCII")
+}
+
+fn write_film_grain_segment<W: Write>(
+    params: &GrainTableSegment,
+    output: &mut W,
+    mode: WriteMode,
+) -> Result<(), Error> {
+    if mode == WriteMode::Plain {
+        return write_segment_lines(params, output, mode);
     }
-    file.flush()?;
 
+    for comment in params
+        .comments
+        .iter()
+        .flat_map(|comment| comment.split('\n'))
+    {
+        writeln!(output, "{}", format!("# {comment}").trim_end())?;
+    }
+
+    // The lines are the same as in plain tables, each ending with the
+    // comment labelling its values.
+    let mut lines = Vec::new();
+    write_segment_lines(params, &mut lines, mode)?;
+    let lines = String::from_utf8(lines).expect("grain tables are ASCII");
+    for line in lines.lines() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields
+            .split_first()
+            .and_then(|(tag, values)| line_annotation(tag, values))
+        {
+            Some(annotation) => writeln!(output, "{line} # {annotation}")?,
+            None => writeln!(output, "{line}")?,
+        }
+    }
     Ok(())
 }
 
-fn write_film_grain_segment(
+/// Writes the `E` line of a segment and its parameters, as in plain tables.
+fn write_segment_lines<W: Write>(
     params: &GrainTableSegment,
-    output: &mut BufWriter<File>,
-) -> anyhow::Result<()> {
+    output: &mut W,
+    mode: WriteMode,
+) -> Result<(), Error> {
     writeln!(
         output,
-        "E {} {} 1 {} 1",
-        params.start_time, params.end_time, params.random_seed,
+        "E {} {} {} {} {}",
+        params.start_time,
+        params.end_time,
+        u8::from(params.apply_grain),
+        params.random_seed,
+        u8::from(params.update_grain),
     )?;
-    writeln!(
+    if !params.update_grain {
+        return Ok(());
+    }
+
+    write!(
         output,
         "\tp {} {} {} {} {} {} {} {} {} {} {} {}",
         params.ar_coeff_lag,
@@ -249,6 +367,12 @@ fn write_film_grain_segment(
         params.cr_luma_mult,
         params.cr_offset
     )?;
+    // libaom and SVT-AV1 read exactly 12 values and would fail on the next
+    // line, so this is only written in annotated tables.
+    if mode == WriteMode::Annotated && params.clip_to_restricted_range {
+        write!(output, " 1")?;
+    }
+    writeln!(output)?;
 
     write!(output, "\tsY {} ", params.scaling_points_y.len())?;
     for point in &params.scaling_points_y {
@@ -487,4 +611,143 @@ mod tests {
             assert!((x - res).abs() < TOLERANCE, "x={x} res={res}");
         }
     }
+
+    #[test]
+    fn write_grain_table_to_matches_to_string() {
+        let segment = generate_photon_noise_params(
+            0,
+            10_000_000,
+            NoiseGenArgs {
+                iso_setting: 800,
+                width: 1920,
+                height: 1080,
+                transfer_function: TransferFunction::BT1886,
+                full_range: false,
+                chroma_grain: true,
+                random_seed: Some(7391),
+            },
+        );
+        assert!(segment.clip_to_restricted_range);
+        let table = [
+            segment.clone(),
+            GrainTableSegment {
+                start_time: 10_000_000,
+                end_time: 20_000_000,
+                ..segment
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_grain_table_to(&mut output, &table).expect("writing to a Vec cannot fail");
+        let text = grain_table_to_string(&table);
+        assert_eq!(output, text.as_bytes());
+        assert!(text.starts_with("filmgrn1\nE 0 10000000 1 7391 1\n"));
+
+        // Plain tables do not carry the clipping flag
+        #[cfg(feature = "parse")]
+        assert_eq!(
+            crate::parse_grain_table(&text).expect("written table is valid"),
+            table.map(|segment| GrainTableSegment {
+                clip_to_restricted_range: false,
+                ..segment
+            })
+        );
+    }
+
+    #[test]
+    fn annotated_tables_label_values_and_keep_comments() {
+        let segment = GrainTableSegment {
+            start_time: 0,
+            end_time: 10_000_000,
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+            comments: vec!["reel 3, ISO 1600 push".to_owned(), "opening".to_owned()],
+            ..GrainTableSegment::default()
+        };
+        let table = [segment];
+
+        let plain = grain_table_to_string(&table);
+        assert!(!plain.contains('#'), "{plain}");
+
+        let mut annotated = Vec::new();
+        write_grain_table_with_mode(&mut annotated, &table, WriteMode::Annotated)
+            .expect("writing to a Vec cannot fail");
+        let annotated = String::from_utf8(annotated).expect("grain tables are ASCII");
+        assert!(
+            annotated.starts_with(
+                "filmgrn1\n# reel 3, ISO 1600 push\n# opening\nE 0 10000000 1 10956 1\n"
+            ),
+            "{annotated}"
+        );
+        assert!(
+            annotated.contains(
+                "\tp 0 6 0 8 0 0 0 0 0 0 0 0 # ar_coeff_lag=0 ar_coeff_shift=6 grain_scale_shift=0 scaling_shift=8 chroma_scaling_from_luma=0 overlap_flag=0 cb_mult=0 cb_luma_mult=0 cb_offset=0 cr_mult=0 cr_luma_mult=0 cr_offset=0\n"
+            ),
+            "{annotated}"
+        );
+        assert!(
+            annotated.contains(
+                "\tsY 2  0 20 255 40 # num_y_points=2 point_y[0]=(0,20) point_y[1]=(255,40)\n"
+            ),
+            "{annotated}"
+        );
+        assert!(
+            annotated.contains("\tsCb 0 # num_cb_points=0\n"),
+            "{annotated}"
+        );
+
+        #[cfg(feature = "parse")]
+        {
+            assert_eq!(
+                crate::parse_grain_table(&annotated).expect("annotated table is valid"),
+                table
+            );
+            let plain_table = crate::parse_grain_table(&plain).expect("plain table is valid");
+            assert!(
+                plain_table
+                    .iter()
+                    .all(|segment| segment.comments.is_empty())
+            );
+        }
+    }
+
+    #[test]
+    fn only_annotated_tables_write_clipping() {
+        let table = [GrainTableSegment {
+            end_time: 10_000_000,
+            clip_to_restricted_range: true,
+            ..GrainTableSegment::default()
+        }];
+        let p_values = |table: &str| {
+            let line = table
+                .lines()
+                .find(|line| line.starts_with("\tp "))
+                .expect("table has a p line");
+            let line = line.split('#').next().unwrap_or_default();
+            line.split_whitespace().count() - 1
+        };
+
+        // aomenc and SVT-AV1 read exactly 12 values
+        let plain = grain_table_to_string(&table);
+        assert_eq!(p_values(&plain), 12, "{plain}");
+
+        let mut annotated = Vec::new();
+        write_grain_table_with_mode(&mut annotated, &table, WriteMode::Annotated)
+            .expect("writing to a Vec cannot fail");
+        let annotated = String::from_utf8(annotated).expect("grain tables are ASCII");
+        assert_eq!(p_values(&annotated), 13, "{annotated}");
+
+        #[cfg(feature = "parse")]
+        {
+            assert_eq!(
+                crate::parse_grain_table(&annotated).expect("annotated table is valid"),
+                table
+            );
+            let plain_table = crate::parse_grain_table(&plain).expect("plain table is valid");
+            assert!(
+                plain_table
+                    .iter()
+                    .all(|segment| !segment.clip_to_restricted_range)
+            );
+        }
+    }
 }
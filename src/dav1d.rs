@@ -0,0 +1,285 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This module mirrors the `Dav1dFilmGrainData` struct of dav1d's public
+// headers, which decoders fill from the `film_grain_params()` of each frame
+// header, so that film grain parameters can be exchanged with dav1d without
+// copying fields by hand.
+//
+// dav1d stores some parameters differently from `GrainTableSegment`:
+// the chroma multipliers and offsets are centered on zero instead of being
+// stored as coded, and the coefficient arrays have a fixed size regardless
+// of `ar_coeff_lag`.
+
+use std::ffi::{c_int, c_uint};
+
+use anyhow::{Context, Error, Result};
+
+use crate::{
+    GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y_POINTS,
+    util::{centered_field, checked_field, copy_prefix, scaling_points},
+};
+
+/// The number of entries of each chroma plane in
+/// [`Dav1dFilmGrainData::ar_coeffs_uv`], including padding for alignment.
+pub const DAV1D_AR_COEFFS_UV_STRIDE: usize = NUM_UV_COEFFS + 3;
+
+/// A layout-compatible mirror of dav1d's `Dav1dFilmGrainData`, as found in
+/// the `film_grain.data` field of `Dav1dFrameHeader`.
+///
+/// Convert it from a [`GrainTableSegment`] with `From`, and back with
+/// `TryFrom`, which fails if the data cannot be represented by a segment.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dav1dFilmGrainData {
+    pub seed: c_uint,
+    pub num_y_points: c_int,
+    /// Value and scaling of each luma scaling point
+    pub y_points: [[u8; 2]; NUM_Y_POINTS],
+    pub chroma_scaling_from_luma: c_int,
+    pub num_uv_points: [c_int; 2],
+    /// Value and scaling of each Cb and Cr scaling point
+    pub uv_points: [[[u8; 2]; NUM_UV_POINTS]; 2],
+    /// The scaling shift, between 8 and 11
+    pub scaling_shift: c_int,
+    pub ar_coeff_lag: c_int,
+    pub ar_coeffs_y: [i8; NUM_Y_COEFFS],
+    pub ar_coeffs_uv: [[i8; DAV1D_AR_COEFFS_UV_STRIDE]; 2],
+    /// The AR coefficient shift, between 6 and 9
+    pub ar_coeff_shift: u64,
+    pub grain_scale_shift: c_int,
+    /// The Cb and Cr multipliers, minus 128
+    pub uv_mult: [c_int; 2],
+    /// The Cb and Cr luma multipliers, minus 128
+    pub uv_luma_mult: [c_int; 2],
+    /// The Cb and Cr offsets, minus 256
+    pub uv_offset: [c_int; 2],
+    pub overlap_flag: c_int,
+    pub clip_to_restricted_range: c_int,
+}
+
+impl From<&GrainTableSegment> for Dav1dFilmGrainData {
+    /// Converts a segment to the representation used by dav1d. The start
+    /// and end times of the segment are not carried over. Segments with
+    /// `apply_grain`
+    /// unset convert to parameters without scaling points, which add no
+    /// grain.
+    #[inline]
+    fn from(segment: &GrainTableSegment) -> Self {
+        if !segment.apply_grain {
+            return Self::from(&GrainTableSegment {
+                random_seed: segment.random_seed,
+                ..GrainTableSegment::default()
+            });
+        }
+
+        let mut y_points = [[0; 2]; NUM_Y_POINTS];
+        copy_prefix(&mut y_points, &segment.scaling_points_y);
+        let mut uv_points = [[[0; 2]; NUM_UV_POINTS]; 2];
+        copy_prefix(&mut uv_points[0], &segment.scaling_points_cb);
+        copy_prefix(&mut uv_points[1], &segment.scaling_points_cr);
+        let mut ar_coeffs_y = [0; NUM_Y_COEFFS];
+        copy_prefix(&mut ar_coeffs_y, &segment.ar_coeffs_y);
+        let mut ar_coeffs_uv = [[0; DAV1D_AR_COEFFS_UV_STRIDE]; 2];
+        copy_prefix(&mut ar_coeffs_uv[0], &segment.ar_coeffs_cb);
+        copy_prefix(&mut ar_coeffs_uv[1], &segment.ar_coeffs_cr);
+
+        Self {
+            seed: c_uint::from(segment.random_seed),
+            num_y_points: segment.scaling_points_y.len() as c_int,
+            y_points,
+            chroma_scaling_from_luma: c_int::from(segment.chroma_scaling_from_luma),
+            num_uv_points: [
+                segment.scaling_points_cb.len() as c_int,
+                segment.scaling_points_cr.len() as c_int,
+            ],
+            uv_points,
+            scaling_shift: c_int::from(segment.scaling_shift),
+            ar_coeff_lag: c_int::from(segment.ar_coeff_lag),
+            ar_coeffs_y,
+            ar_coeffs_uv,
+            ar_coeff_shift: u64::from(segment.ar_coeff_shift),
+            grain_scale_shift: c_int::from(segment.grain_scale_shift),
+            uv_mult: [
+                c_int::from(segment.cb_mult) - 128,
+                c_int::from(segment.cr_mult) - 128,
+            ],
+            uv_luma_mult: [
+                c_int::from(segment.cb_luma_mult) - 128,
+                c_int::from(segment.cr_luma_mult) - 128,
+            ],
+            uv_offset: [
+                c_int::from(segment.cb_offset) - 256,
+                c_int::from(segment.cr_offset) - 256,
+            ],
+            overlap_flag: c_int::from(segment.overlap_flag),
+            clip_to_restricted_range: c_int::from(segment.clip_to_restricted_range),
+        }
+    }
+}
+
+impl TryFrom<&Dav1dFilmGrainData> for GrainTableSegment {
+    type Error = Error;
+
+    /// Converts film grain data from dav1d to a segment, with a `start_time`
+    /// and `end_time` of 0.
+    ///
+    /// The coefficients of each plane are truncated to the number used by
+    /// `ar_coeff_lag`, which is how parsed segments store them.
+    ///
+    /// # Errors
+    ///
+    /// - If a field is outside of the range that its bitstream syntax
+    ///   element can represent, such as a negative number of scaling points
+    #[inline]
+    fn try_from(data: &Dav1dFilmGrainData) -> Result<Self> {
+        let ar_coeff_lag = checked_field::<u8>(data.ar_coeff_lag, 0..=3, "ar_coeff_lag")?;
+        let num_pos_luma = 2 * usize::from(ar_coeff_lag) * usize::from(ar_coeff_lag + 1);
+        let [points_cb, points_cr] = &data.uv_points;
+        let [coeffs_cb, coeffs_cr] = &data.ar_coeffs_uv;
+        let [num_cb_points, num_cr_points] = data.num_uv_points;
+        let [cb_mult, cr_mult] = data.uv_mult;
+        let [cb_luma_mult, cr_luma_mult] = data.uv_luma_mult;
+        let [cb_offset, cr_offset] = data.uv_offset;
+
+        Ok(Self {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y: scaling_points(&data.y_points, data.num_y_points, "num_y_points")?,
+            scaling_points_cb: scaling_points(points_cb, num_cb_points, "num_uv_points")?,
+            scaling_points_cr: scaling_points(points_cr, num_cr_points, "num_uv_points")?,
+            scaling_shift: checked_field(data.scaling_shift, 8..=11, "scaling_shift")?,
+            ar_coeff_lag,
+            ar_coeffs_y: data
+                .ar_coeffs_y
+                .iter()
+                .copied()
+                .take(num_pos_luma)
+                .collect(),
+            ar_coeffs_cb: coeffs_cb.iter().copied().take(num_pos_luma + 1).collect(),
+            ar_coeffs_cr: coeffs_cr.iter().copied().take(num_pos_luma + 1).collect(),
+            ar_coeff_shift: u8::try_from(data.ar_coeff_shift)
+                .ok()
+                .filter(|shift| (6..=9).contains(shift))
+                .context("ar_coeff_shift must be between 6 and 9")?,
+            cb_mult: centered_field(cb_mult, 128, "uv_mult")?,
+            cb_luma_mult: centered_field(cb_luma_mult, 128, "uv_luma_mult")?,
+            cb_offset: centered_field(cb_offset, 256, "uv_offset")?,
+            cr_mult: centered_field(cr_mult, 128, "uv_mult")?,
+            cr_luma_mult: centered_field(cr_luma_mult, 128, "uv_luma_mult")?,
+            cr_offset: centered_field(cr_offset, 256, "uv_offset")?,
+            overlap_flag: data.overlap_flag != 0,
+            chroma_scaling_from_luma: data.chroma_scaling_from_luma != 0,
+            grain_scale_shift: checked_field(data.grain_scale_shift, 0..=3, "grain_scale_shift")?,
+            random_seed: u16::try_from(data.seed).context("seed must fit in 16 bits")?,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: data.clip_to_restricted_range != 0,
+            comments: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [128, 40], [255, 30]]),
+            scaling_points_cb: ArrayVec::from_iter([[0, 10], [255, 15]]),
+            scaling_points_cr: ArrayVec::from_iter([[64, 12]]),
+            scaling_shift: 9,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: (1..=12).collect(),
+            ar_coeffs_cb: (-13..0).collect(),
+            ar_coeffs_cr: (20..33).collect(),
+            ar_coeff_shift: 8,
+            cb_luma_mult: 255,
+            cb_offset: 511,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            grain_scale_shift: 2,
+            random_seed: 65535,
+            clip_to_restricted_range: true,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    #[test]
+    fn layout_matches_dav1d() {
+        assert_eq!(offset_of!(Dav1dFilmGrainData, y_points), 8);
+        assert_eq!(offset_of!(Dav1dFilmGrainData, uv_points), 48);
+        assert_eq!(offset_of!(Dav1dFilmGrainData, ar_coeffs_y), 96);
+        assert_eq!(offset_of!(Dav1dFilmGrainData, ar_coeffs_uv), 120);
+        assert_eq!(offset_of!(Dav1dFilmGrainData, ar_coeff_shift), 176);
+        assert_eq!(
+            offset_of!(Dav1dFilmGrainData, clip_to_restricted_range),
+            216
+        );
+        assert_eq!(size_of::<Dav1dFilmGrainData>(), 224);
+    }
+
+    #[test]
+    fn round_trip() {
+        let segment = segment();
+        let data = Dav1dFilmGrainData::from(&segment);
+        assert_eq!(data.num_uv_points, [2, 1]);
+        assert_eq!(data.uv_mult, [-128, 0]);
+        assert_eq!(data.uv_luma_mult, [127, 64]);
+        assert_eq!(data.uv_offset, [255, 0]);
+        assert_eq!(data.clip_to_restricted_range, 1);
+        let [_, cr_coeffs] = data.ar_coeffs_uv;
+        assert_eq!(
+            cr_coeffs.get(12..),
+            Some([32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].as_slice())
+        );
+
+        assert_eq!(
+            GrainTableSegment::try_from(&data).expect("valid data"),
+            segment
+        );
+    }
+
+    #[test]
+    fn rejects_unrepresentable_data() {
+        let data = Dav1dFilmGrainData::from(&segment());
+        for invalid in [
+            Dav1dFilmGrainData {
+                num_y_points: 15,
+                ..data
+            },
+            Dav1dFilmGrainData {
+                num_uv_points: [-1, 0],
+                ..data
+            },
+            Dav1dFilmGrainData {
+                uv_offset: [256, 0],
+                ..data
+            },
+            Dav1dFilmGrainData {
+                ar_coeff_shift: 10,
+                ..data
+            },
+            Dav1dFilmGrainData {
+                seed: 65536,
+                ..data
+            },
+        ] {
+            assert!(GrainTableSegment::try_from(&invalid).is_err());
+        }
+    }
+}
@@ -1,9 +1,8 @@
-use anyhow::{Result, ensure};
 use num_rational::Rational64;
 use v_frame::{frame::Frame, pixel::Pixel};
 
 use self::solver::{FlatBlockFinder, NoiseModel};
-use crate::{GrainTableSegment, util::frame_into_u8};
+use crate::{Error, GrainTableSegment, OPEN_END_TIME, util::frame_into_u8};
 
 mod solver;
 
@@ -14,6 +13,7 @@ pub struct DiffGenerator {
     fps: Rational64,
     source_bit_depth: usize,
     denoised_bit_depth: usize,
+    full_range: bool,
     frame_count: usize,
     prev_timestamp: u64,
     flat_block_finder: FlatBlockFinder,
@@ -34,9 +34,20 @@ impl DiffGenerator {
             prev_timestamp: 0,
             source_bit_depth,
             denoised_bit_depth,
+            full_range: true,
         }
     }
 
+    /// Sets whether the source is full range, which it is assumed to be by
+    /// default. Segments generated for limited range sources clip the
+    /// output to the restricted range after adding grain.
+    #[must_use]
+    #[inline]
+    pub const fn with_full_range(mut self, full_range: bool) -> Self {
+        self.full_range = full_range;
+        self
+    }
+
     /// Processes the next frame and adds the results to the state of this
     /// `DiffGenerator`.
     ///
@@ -48,7 +59,7 @@ impl DiffGenerator {
         &mut self,
         source: &Frame<T>,
         denoised: &Frame<U>,
-    ) -> Result<()> {
+    ) -> Result<(), Error> {
         self.diff_frame_internal(
             &frame_into_u8(source, self.source_bit_depth),
             &frame_into_u8(denoised, self.denoised_bit_depth),
@@ -61,15 +72,17 @@ impl DiffGenerator {
     #[inline]
     pub fn finish(mut self) -> Vec<GrainTableSegment> {
         log::debug!("Updating final parameters");
-        self.grain_table.push(
-            self.noise_model
-                .get_grain_parameters(self.prev_timestamp, i64::MAX as u64),
-        );
+        let segment = self.segment(self.prev_timestamp, OPEN_END_TIME);
+        self.grain_table.push(segment);
 
         self.grain_table
     }
 
-    fn diff_frame_internal(&mut self, source: &Frame<u8>, denoised: &Frame<u8>) -> Result<()> {
+    fn diff_frame_internal(
+        &mut self,
+        source: &Frame<u8>,
+        denoised: &Frame<u8>,
+    ) -> Result<(), Error> {
         verify_dimensions_match(source, denoised)?;
 
         let (flat_blocks, num_flat_blocks) = self.flat_block_finder.run(&source.y_plane);
@@ -86,10 +99,8 @@ impl DiffGenerator {
                 self.prev_timestamp,
                 cur_timestamp
             );
-            self.grain_table.push(
-                self.noise_model
-                    .get_grain_parameters(self.prev_timestamp, cur_timestamp),
-            );
+            let segment = self.segment(self.prev_timestamp, cur_timestamp);
+            self.grain_table.push(segment);
             self.noise_model.save_latest();
             self.prev_timestamp = cur_timestamp;
         }
@@ -98,6 +109,13 @@ impl DiffGenerator {
 
         Ok(())
     }
+
+    fn segment(&self, start_ts: u64, end_ts: u64) -> GrainTableSegment {
+        GrainTableSegment {
+            clip_to_restricted_range: !self.full_range,
+            ..self.noise_model.get_grain_parameters(start_ts, end_ts)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -117,34 +135,107 @@ impl PartialEq for NoiseStatus {
     }
 }
 
-fn verify_dimensions_match(source: &Frame<u8>, denoised: &Frame<u8>) -> Result<()> {
-    let res_1 = (source.y_plane.width(), source.y_plane.height());
-    let res_2 = (denoised.y_plane.width(), denoised.y_plane.height());
-    ensure!(
-        res_1 == res_2,
-        "Luma resolutions were not equal, {}x{} != {}x{}",
-        res_1.0,
-        res_1.1,
-        res_2.0,
-        res_2.1
-    );
-
-    let res_1 = (
-        source.u_plane.as_ref().map(|p| p.width()),
-        source.u_plane.as_ref().map(|p| p.height()),
-    );
-    let res_2 = (
-        denoised.u_plane.as_ref().map(|p| p.width()),
-        denoised.u_plane.as_ref().map(|p| p.height()),
-    );
-    ensure!(
-        res_1 == res_2,
-        "Chroma resolutions were not equal, {}x{} != {}x{}",
-        res_1.0.unwrap_or(0),
-        res_1.1.unwrap_or(0),
-        res_2.0.unwrap_or(0),
-        res_2.1.unwrap_or(0)
-    );
+fn verify_dimensions_match(source: &Frame<u8>, denoised: &Frame<u8>) -> Result<(), Error> {
+    let source_size = (source.y_plane.width(), source.y_plane.height());
+    let denoised_size = (denoised.y_plane.width(), denoised.y_plane.height());
+    if source_size != denoised_size {
+        return Err(Error::ResolutionMismatch {
+            chroma: false,
+            source_size,
+            denoised_size,
+        });
+    }
+
+    let chroma_size = |frame: &Frame<u8>| {
+        frame
+            .u_plane
+            .as_ref()
+            .map_or((0, 0), |plane| (plane.width(), plane.height()))
+    };
+    let source_size = chroma_size(source);
+    let denoised_size = chroma_size(denoised);
+    if source_size != denoised_size {
+        return Err(Error::ResolutionMismatch {
+            chroma: true,
+            source_size,
+            denoised_size,
+        });
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use v_frame::{chroma::ChromaSubsampling, frame::FrameBuilder};
+
+    use super::*;
+    use crate::{ChromaFormat, Severity, random::RandomGenerator, validate_grain_table};
+
+    fn frame(rng: Option<&mut RandomGenerator>) -> Frame<u8> {
+        let mut frame = FrameBuilder::new(128, 128, ChromaSubsampling::Yuv420, 8)
+            .build::<u8>()
+            .expect("valid frame");
+        let mut noise = rng.map(|rng| move || rng.next_gaussian() / 128);
+        for plane in [
+            Some(&mut frame.y_plane),
+            frame.u_plane.as_mut(),
+            frame.v_plane.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            for pixel in plane.data_mut() {
+                let noise = noise.as_mut().map_or(0, |noise| noise());
+                *pixel = (128 + noise) as u8;
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn finish_returns_valid_segments() {
+        let mut rng = RandomGenerator::new(7391);
+        let mut generator = DiffGenerator::new(Rational64::new(24, 1), 8, 8).with_full_range(false);
+        for _ in 0..4 {
+            generator
+                .diff_frame(&frame(Some(&mut rng)), &frame(None))
+                .expect("frames have the same dimensions");
+        }
+
+        let segments = generator.finish();
+        assert!(!segments.is_empty());
+        assert!(
+            segments
+                .iter()
+                .all(|segment| segment.clip_to_restricted_range)
+        );
+        let errors: Vec<_> = validate_grain_table(&segments, Some(ChromaFormat::Yuv420))
+            .into_iter()
+            .filter(|(_, violation)| violation.severity() == Severity::Error)
+            .collect();
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn rejects_mismatched_resolutions() {
+        let small = FrameBuilder::new(64, 64, ChromaSubsampling::Yuv420, 8)
+            .build::<u8>()
+            .expect("valid frame");
+        let mut generator = DiffGenerator::new(Rational64::new(24, 1), 8, 8);
+        let err = generator
+            .diff_frame(&frame(None), &small)
+            .expect_err("resolutions differ");
+        assert!(
+            matches!(
+                err,
+                Error::ResolutionMismatch {
+                    chroma: false,
+                    source_size: (128, 128),
+                    denoised_size: (64, 64),
+                }
+            ),
+            "{err}"
+        );
+    }
+}
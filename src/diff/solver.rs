@@ -680,6 +680,11 @@ impl NoiseModel {
             chroma_scaling_from_luma: false,
             grain_scale_shift: 0,
             overlap_flag: true,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: false,
+            comments: Vec::new(),
         }
     }
 
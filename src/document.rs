@@ -0,0 +1,735 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// A versioned document format for grain tables, meant to be stored as JSON,
+// TOML or any other format supported by serde and edited by hand. Unlike the
+// serde representation of `GrainTableSegment`, its fields are part of a
+// schema which only changes with its version.
+
+use arrayvec::ArrayVec;
+use serde::{Deserialize, Deserializer, Serialize, de};
+
+use crate::{
+    DEFAULT_GRAIN_SEED, Error, GrainTableSegment, Severity, Violation, validate_grain_table,
+};
+
+/// The schema version of the [`GrainDocument`]s written by this crate, which
+/// is also the newest version it can read.
+pub const GRAIN_DOCUMENT_VERSION: u32 = 1;
+
+const TICKS_PER_SECOND: u64 = 10_000_000;
+
+/// A grain table as a versioned document with named fields, which can be
+/// stored with any serde format, such as JSON or TOML.
+///
+/// Version 1 of the schema has the following fields, of which only
+/// `version` and the `start` and `end` of each segment are required:
+///
+/// - `version`: the schema version, which must be at most
+///   [`GRAIN_DOCUMENT_VERSION`]
+/// - `metadata`: an optional table of `generator`, `description`, `width`,
+///   `height` and `frame_rate` (with `numerator` and `denominator`)
+/// - `segments`: a list of segments, ordered by time, with
+///   - `start` and `end`: a [`Timestamp`], such as `{ seconds = 1.5 }`,
+///     `{ frames = 36 }` or `{ ticks = 15000000 }`. Frames require a
+///     `frame_rate` in the metadata.
+///   - `apply_grain` and `update_grain`: booleans, which default to `true`
+///   - `random_seed`, `scaling_shift`, `ar_coeff_lag`, `ar_coeff_shift` and
+///     `grain_scale_shift`: integers, which default to the values of
+///     [`GrainTableSegment::default`]
+///   - `overlap`, `chroma_scaling_from_luma` and `clip_to_restricted_range`:
+///     booleans, which default to `false`
+///   - `luma`: a table of `scaling_points`, a list of `{ value, scaling }`
+///     pairs, and `ar_coeffs`, a list of integers
+///   - `cb` and `cr`: tables like `luma`, with the additional `mult`,
+///     `luma_mult` and `offset` integers
+///   - `comments`: a list of strings
+///
+/// Omitted `ar_coeffs` are all zero, in the count implied by `ar_coeff_lag`.
+/// Fields which are not part of the schema are ignored.
+///
+/// ```toml
+/// version = 1
+///
+/// [metadata]
+/// generator = "av1-grain"
+/// width = 1920
+/// height = 1080
+/// frame_rate = { numerator = 24, denominator = 1 }
+///
+/// [[segments]]
+/// start = { frames = 0 }
+/// end = { seconds = 10.0 }
+/// random_seed = 7391
+/// overlap = true
+/// luma = { scaling_points = [{ value = 0, scaling = 20 }, { value = 255, scaling = 40 }] }
+/// comments = ["reel 3, ISO 1600 push"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GrainDocument {
+    /// The schema version of the document
+    #[serde(deserialize_with = "deserialize_version")]
+    pub version: u32,
+    /// Information about how and for which video the table was made
+    #[serde(default)]
+    pub metadata: DocumentMetadata,
+    /// The segments of the table, ordered by time
+    #[serde(default)]
+    pub segments: Vec<DocumentSegment>,
+}
+
+/// Information about how and for which video a [`GrainDocument`] was made.
+/// None of it affects the grain, except that `frame_rate` is required for
+/// timestamps counting frames.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    /// The program which made the table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+    /// A description of the table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The width of the source video, in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// The height of the source video, in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// The frame rate of the source video
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<FrameRate>,
+}
+
+/// A frame rate, as a number of frames per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRate {
+    /// The number of frames per `denominator` seconds
+    pub numerator: u32,
+    /// The number of seconds `numerator` frames take
+    pub denominator: u32,
+}
+
+/// A time in a [`GrainDocument`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timestamp {
+    /// 10,000,000ths of a second, as in [`GrainTableSegment`]
+    Ticks(u64),
+    /// Seconds, which are rounded to the nearest tick
+    Seconds(f64),
+    /// Frames at the `frame_rate` of the document, which are rounded to the
+    /// nearest tick
+    Frames(u64),
+}
+
+/// The unit [`GrainDocument::from_segments`] prefers for timestamps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeUnit {
+    /// 10,000,000ths of a second
+    #[default]
+    Ticks,
+    /// Seconds
+    Seconds,
+    /// Frames at the `frame_rate` of the metadata
+    Frames,
+}
+
+/// A segment of a [`GrainDocument`], with the same meaning as the fields of
+/// the same names in [`GrainTableSegment`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentSegment {
+    /// The beginning of the segment
+    pub start: Timestamp,
+    /// The end of the segment, not inclusive
+    pub end: Timestamp,
+    /// Whether grain is applied during the segment
+    #[serde(default = "crate::default_true")]
+    pub apply_grain: bool,
+    /// Whether the segment signals new parameters
+    #[serde(default = "crate::default_true")]
+    pub update_grain: bool,
+    /// Random seed used for generating grain
+    #[serde(default = "default_random_seed")]
+    pub random_seed: u16,
+    /// The range and quantization step of the standard deviation of the grain
+    #[serde(default = "default_scaling_shift")]
+    pub scaling_shift: u8,
+    /// The number of auto-regressive coefficients
+    #[serde(default)]
+    pub ar_coeff_lag: u8,
+    /// The range of the auto-regressive coefficients
+    #[serde(default = "default_ar_coeff_shift")]
+    pub ar_coeff_shift: u8,
+    /// How much the Gaussian random numbers are scaled down
+    #[serde(default)]
+    pub grain_scale_shift: u8,
+    /// Whether film grain blocks overlap
+    #[serde(default)]
+    pub overlap: bool,
+    /// Whether chroma grain is scaled from luma
+    #[serde(default)]
+    pub chroma_scaling_from_luma: bool,
+    /// Whether samples are clipped to the studio range after adding grain
+    #[serde(default)]
+    pub clip_to_restricted_range: bool,
+    /// The luma grain
+    #[serde(default)]
+    pub luma: LumaGrain,
+    /// The Cb grain
+    #[serde(default)]
+    pub cb: ChromaGrain,
+    /// The Cr grain
+    #[serde(default)]
+    pub cr: ChromaGrain,
+    /// Comments about the segment
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
+}
+
+/// A point of a piecewise linear scaling function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScalingPoint {
+    /// The sample value of the point, in 8-bit units
+    pub value: u8,
+    /// The scaling of the grain at `value`
+    pub scaling: u8,
+}
+
+/// The luma grain of a [`DocumentSegment`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LumaGrain {
+    /// The points of the scaling function, in increasing order of value
+    #[serde(default)]
+    pub scaling_points: Vec<ScalingPoint>,
+    /// The auto-regressive coefficients
+    #[serde(default)]
+    pub ar_coeffs: Vec<i8>,
+}
+
+/// The grain of a chroma plane of a [`DocumentSegment`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChromaGrain {
+    /// The points of the scaling function, in increasing order of value
+    #[serde(default)]
+    pub scaling_points: Vec<ScalingPoint>,
+    /// The auto-regressive coefficients, ending with the coefficient of luma
+    #[serde(default)]
+    pub ar_coeffs: Vec<i8>,
+    /// Multiplier to the chroma value when indexing the scaling function
+    #[serde(default)]
+    pub mult: u8,
+    /// Multiplier to the average luma value when indexing the scaling
+    /// function
+    #[serde(default)]
+    pub luma_mult: u8,
+    /// Offset when indexing the scaling function
+    #[serde(default)]
+    pub offset: u16,
+}
+
+impl GrainDocument {
+    /// Creates a document of the current version from `segments`.
+    ///
+    /// Timestamps are written in `time_unit` if they can be converted back
+    /// exactly, and in ticks otherwise, so that no precision is lost. Frames
+    /// are only used if `metadata` has a frame rate.
+    #[must_use]
+    #[inline]
+    pub fn from_segments(
+        segments: &[GrainTableSegment],
+        metadata: DocumentMetadata,
+        time_unit: TimeUnit,
+    ) -> Self {
+        let timestamp = |ticks| timestamp_in(ticks, time_unit, metadata.frame_rate);
+        let segments = segments
+            .iter()
+            .map(|segment| DocumentSegment {
+                start: timestamp(segment.start_time),
+                end: timestamp(segment.end_time),
+                apply_grain: segment.apply_grain,
+                update_grain: segment.update_grain,
+                random_seed: segment.random_seed,
+                scaling_shift: segment.scaling_shift,
+                ar_coeff_lag: segment.ar_coeff_lag,
+                ar_coeff_shift: segment.ar_coeff_shift,
+                grain_scale_shift: segment.grain_scale_shift,
+                overlap: segment.overlap_flag,
+                chroma_scaling_from_luma: segment.chroma_scaling_from_luma,
+                clip_to_restricted_range: segment.clip_to_restricted_range,
+                luma: LumaGrain {
+                    scaling_points: scaling_points(&segment.scaling_points_y),
+                    ar_coeffs: segment.ar_coeffs_y.to_vec(),
+                },
+                cb: ChromaGrain {
+                    scaling_points: scaling_points(&segment.scaling_points_cb),
+                    ar_coeffs: segment.ar_coeffs_cb.to_vec(),
+                    mult: segment.cb_mult,
+                    luma_mult: segment.cb_luma_mult,
+                    offset: segment.cb_offset,
+                },
+                cr: ChromaGrain {
+                    scaling_points: scaling_points(&segment.scaling_points_cr),
+                    ar_coeffs: segment.ar_coeffs_cr.to_vec(),
+                    mult: segment.cr_mult,
+                    luma_mult: segment.cr_luma_mult,
+                    offset: segment.cr_offset,
+                },
+                comments: segment.comments.clone(),
+            })
+            .collect();
+
+        Self {
+            version: GRAIN_DOCUMENT_VERSION,
+            metadata,
+            segments,
+        }
+    }
+
+    /// Converts the document to grain table segments.
+    ///
+    /// # Errors
+    ///
+    /// - If the version of the document is not supported
+    /// - If a timestamp is negative or too large, or counts frames without a
+    ///   frame rate
+    /// - If a plane has more scaling points than AV1 can signal
+    /// - If a segment has a violation of [`Severity::Error`], as reported by
+    ///   [`validate_grain_table`] without a chroma format
+    #[inline]
+    pub fn to_segments(&self) -> Result<Vec<GrainTableSegment>, Error> {
+        check_version(self.version)?;
+
+        let segments = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| self.convert_segment(index, segment))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some((segment, violation)) = validate_grain_table(&segments, None)
+            .into_iter()
+            .find(|(_, violation)| violation.severity() == Severity::Error)
+        {
+            return Err(Error::InvalidDocumentSegment { segment, violation });
+        }
+        Ok(segments)
+    }
+
+    /// Creates a document from a grain table in the format described in
+    /// [`parse_grain_table`](crate::parse_grain_table), like
+    /// [`GrainDocument::from_segments`].
+    ///
+    /// # Errors
+    ///
+    /// - If the input does not contain a properly formatted film grain table
+    #[cfg(feature = "parse")]
+    #[inline]
+    pub fn from_grain_table(
+        input: &str,
+        metadata: DocumentMetadata,
+        time_unit: TimeUnit,
+    ) -> Result<Self, Error> {
+        Ok(Self::from_segments(
+            &crate::parse_grain_table(input)?,
+            metadata,
+            time_unit,
+        ))
+    }
+
+    /// Formats the document as a grain table, in the format written by
+    /// [`write_grain_table`](crate::write_grain_table). Its metadata is not
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// - If the document cannot be converted to segments, as described in
+    ///   [`GrainDocument::to_segments`]
+    #[cfg(feature = "create")]
+    #[inline]
+    pub fn to_grain_table(&self) -> Result<String, Error> {
+        Ok(crate::grain_table_to_string(&self.to_segments()?))
+    }
+
+    fn convert_segment(
+        &self,
+        index: usize,
+        segment: &DocumentSegment,
+    ) -> Result<GrainTableSegment, Error> {
+        let ticks = |timestamp| to_ticks(timestamp, self.metadata.frame_rate, index);
+        let lag = usize::from(segment.ar_coeff_lag);
+        let coeff_count = 2 * lag * (lag + 1);
+
+        Ok(GrainTableSegment {
+            start_time: ticks(segment.start)?,
+            end_time: ticks(segment.end)?,
+            scaling_points_y: collect_points(&segment.luma.scaling_points, index, 0)?,
+            scaling_points_cb: collect_points(&segment.cb.scaling_points, index, 1)?,
+            scaling_points_cr: collect_points(&segment.cr.scaling_points, index, 2)?,
+            scaling_shift: segment.scaling_shift,
+            ar_coeff_lag: segment.ar_coeff_lag,
+            ar_coeffs_y: collect_coeffs(&segment.luma.ar_coeffs, index, 0, coeff_count)?,
+            ar_coeffs_cb: collect_coeffs(&segment.cb.ar_coeffs, index, 1, coeff_count + 1)?,
+            ar_coeffs_cr: collect_coeffs(&segment.cr.ar_coeffs, index, 2, coeff_count + 1)?,
+            ar_coeff_shift: segment.ar_coeff_shift,
+            cb_mult: segment.cb.mult,
+            cb_luma_mult: segment.cb.luma_mult,
+            cb_offset: segment.cb.offset,
+            cr_mult: segment.cr.mult,
+            cr_luma_mult: segment.cr.luma_mult,
+            cr_offset: segment.cr.offset,
+            overlap_flag: segment.overlap,
+            chroma_scaling_from_luma: segment.chroma_scaling_from_luma,
+            grain_scale_shift: segment.grain_scale_shift,
+            random_seed: segment.random_seed,
+            apply_grain: segment.apply_grain,
+            update_grain: segment.update_grain,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: segment.clip_to_restricted_range,
+            comments: segment.comments.clone(),
+        })
+    }
+}
+
+fn scaling_points(points: &[[u8; 2]]) -> Vec<ScalingPoint> {
+    points
+        .iter()
+        .map(|&[value, scaling]| ScalingPoint { value, scaling })
+        .collect()
+}
+
+fn collect_points<const N: usize>(
+    points: &[ScalingPoint],
+    segment: usize,
+    plane: usize,
+) -> Result<ArrayVec<[u8; 2], N>, Error> {
+    if points.len() > N {
+        return Err(Error::TooManyDocumentPoints {
+            segment,
+            plane,
+            max: N,
+            actual: points.len(),
+        });
+    }
+    Ok(points
+        .iter()
+        .map(|point| [point.value, point.scaling])
+        .collect())
+}
+
+fn collect_coeffs<const N: usize>(
+    coeffs: &[i8],
+    segment: usize,
+    plane: usize,
+    expected_count: usize,
+) -> Result<ArrayVec<i8, N>, Error> {
+    if coeffs.is_empty() {
+        // Omitted coefficients are zero. Lags implying more coefficients
+        // than fit are reported by validation.
+        return Ok(std::iter::repeat_n(0, expected_count.min(N)).collect());
+    }
+    ArrayVec::try_from(coeffs).map_err(|_| Error::InvalidDocumentSegment {
+        segment,
+        violation: Violation::CoefficientCount {
+            plane,
+            expected: expected_count,
+            actual: coeffs.len(),
+        },
+    })
+}
+
+/// Converts `timestamp` to ticks. `segment` is the index of its segment.
+fn to_ticks(
+    timestamp: Timestamp,
+    frame_rate: Option<FrameRate>,
+    segment: usize,
+) -> Result<u64, Error> {
+    match timestamp {
+        Timestamp::Ticks(ticks) => Ok(ticks),
+        Timestamp::Seconds(seconds) => {
+            let ticks = (seconds * TICKS_PER_SECOND as f64).round();
+            // `u64::MAX as f64` is 2^64, which is out of range itself.
+            if (0.0..u64::MAX as f64).contains(&ticks) {
+                Ok(ticks as u64)
+            } else {
+                Err(Error::InvalidTimestamp { segment })
+            }
+        }
+        Timestamp::Frames(frames) => {
+            let frame_rate = frame_rate.ok_or(Error::MissingFrameRate { segment })?;
+            let numerator = u128::from(frame_rate.numerator);
+            if numerator == 0 {
+                return Err(Error::InvalidTimestamp { segment });
+            }
+            let ticks = (u128::from(frames)
+                * u128::from(frame_rate.denominator)
+                * u128::from(TICKS_PER_SECOND)
+                + numerator / 2)
+                / numerator;
+            u64::try_from(ticks).map_err(|_| Error::InvalidTimestamp { segment })
+        }
+    }
+}
+
+/// Converts `ticks` to `time_unit`, keeping it in ticks if it would not be
+/// converted back exactly.
+fn timestamp_in(ticks: u64, time_unit: TimeUnit, frame_rate: Option<FrameRate>) -> Timestamp {
+    let timestamp = match (time_unit, frame_rate) {
+        (TimeUnit::Seconds, _) => Timestamp::Seconds(ticks as f64 / TICKS_PER_SECOND as f64),
+        (TimeUnit::Frames, Some(frame_rate)) if frame_rate.denominator > 0 => {
+            let ticks_per_frames =
+                u128::from(frame_rate.denominator) * u128::from(TICKS_PER_SECOND);
+            let frames = (u128::from(ticks) * u128::from(frame_rate.numerator)
+                + ticks_per_frames / 2)
+                / ticks_per_frames;
+            match u64::try_from(frames) {
+                Ok(frames) => Timestamp::Frames(frames),
+                Err(_) => return Timestamp::Ticks(ticks),
+            }
+        }
+        _ => return Timestamp::Ticks(ticks),
+    };
+    if to_ticks(timestamp, frame_rate, 0).ok() == Some(ticks) {
+        timestamp
+    } else {
+        Timestamp::Ticks(ticks)
+    }
+}
+
+fn check_version(version: u32) -> Result<(), Error> {
+    if (1..=GRAIN_DOCUMENT_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedVersion {
+            version,
+            supported: GRAIN_DOCUMENT_VERSION,
+        })
+    }
+}
+
+/// Checks the version before the rest of a document is read, so that the
+/// error for newer documents names the version instead of a field.
+fn deserialize_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    check_version(version).map_err(de::Error::custom)?;
+    Ok(version)
+}
+
+const fn default_random_seed() -> u16 {
+    DEFAULT_GRAIN_SEED
+}
+
+const fn default_scaling_shift() -> u8 {
+    8
+}
+
+const fn default_ar_coeff_shift() -> u8 {
+    6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments() -> Vec<GrainTableSegment> {
+        vec![
+            GrainTableSegment {
+                start_time: 0,
+                end_time: 15_000_000,
+                scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+                scaling_points_cb: ArrayVec::from_iter([[0, 10], [255, 15]]),
+                ar_coeff_lag: 1,
+                ar_coeffs_y: ArrayVec::from_iter([4, -2, 1, 3]),
+                ar_coeffs_cb: ArrayVec::from_iter([1, 0, -1, 2, 5]),
+                ar_coeffs_cr: ArrayVec::from_iter([0; 5]),
+                cb_mult: 128,
+                cb_luma_mult: 192,
+                cb_offset: 256,
+                overlap_flag: true,
+                comments: vec!["reel 3, ISO 1600 push".to_owned()],
+                ..GrainTableSegment::default()
+            },
+            GrainTableSegment {
+                start_time: 15_000_000,
+                end_time: 9_223_372_036_854_775_807,
+                apply_grain: false,
+                ..GrainTableSegment::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trips_through_json_and_toml() {
+        let metadata = DocumentMetadata {
+            generator: Some("av1-grain".to_owned()),
+            width: Some(1920),
+            height: Some(1080),
+            ..DocumentMetadata::default()
+        };
+        let document = GrainDocument::from_segments(&segments(), metadata, TimeUnit::Seconds);
+        let times = document
+            .segments
+            .iter()
+            .map(|segment| (segment.start, segment.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            [
+                (Timestamp::Seconds(0.0), Timestamp::Seconds(1.5)),
+                (
+                    Timestamp::Seconds(1.5),
+                    Timestamp::Ticks(9_223_372_036_854_775_807)
+                ),
+            ]
+        );
+
+        let json = serde_json::to_string_pretty(&document).expect("document is serializable");
+        assert!(json.contains(r#""version": 1"#), "{json}");
+        assert!(json.contains(r#""value": 255"#), "{json}");
+        let from_json: GrainDocument = serde_json::from_str(&json).expect("JSON is valid");
+        assert_eq!(from_json, document);
+
+        let toml = toml::to_string(&document).expect("document is serializable");
+        let from_toml: GrainDocument = toml::from_str(&toml).expect("TOML is valid");
+        assert_eq!(from_toml, document);
+
+        assert_eq!(
+            document.to_segments().expect("document is valid"),
+            segments()
+        );
+    }
+
+    #[test]
+    fn converts_frames_with_frame_rate() {
+        let metadata = DocumentMetadata {
+            frame_rate: Some(FrameRate {
+                numerator: 24000,
+                denominator: 1001,
+            }),
+            ..DocumentMetadata::default()
+        };
+        let segments = [GrainTableSegment {
+            start_time: 417_083,
+            end_time: 15_015_000,
+            ..GrainTableSegment::default()
+        }];
+        let document = GrainDocument::from_segments(&segments, metadata, TimeUnit::Frames);
+        let segment = document.segments.first().expect("document has a segment");
+        assert_eq!(segment.start, Timestamp::Frames(1));
+        assert_eq!(segment.end, Timestamp::Frames(36));
+        assert_eq!(document.to_segments().expect("document is valid"), segments);
+
+        let document = GrainDocument {
+            metadata: DocumentMetadata::default(),
+            ..document
+        };
+        assert!(matches!(
+            document.to_segments(),
+            Err(Error::MissingFrameRate { segment: 0 })
+        ));
+    }
+
+    #[test]
+    fn reads_hand_written_toml() {
+        let document: GrainDocument = toml::from_str(
+            r#"
+version = 1
+
+[metadata]
+frame_rate = { numerator = 24, denominator = 1 }
+
+[[segments]]
+start = { frames = 0 }
+end = { seconds = 10.0 }
+ar_coeff_lag = 1
+overlap = true
+luma = { scaling_points = [{ value = 0, scaling = 20 }, { value = 255, scaling = 40 }] }
+comments = ["opening"]
+"#,
+        )
+        .expect("TOML is valid");
+        let segments = document.to_segments().expect("document is valid");
+        assert_eq!(
+            segments,
+            [GrainTableSegment {
+                start_time: 0,
+                end_time: 100_000_000,
+                scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+                ar_coeff_lag: 1,
+                ar_coeffs_y: ArrayVec::from_iter([0; 4]),
+                ar_coeffs_cb: ArrayVec::from_iter([0; 5]),
+                ar_coeffs_cr: ArrayVec::from_iter([0; 5]),
+                overlap_flag: true,
+                comments: vec!["opening".to_owned()],
+                ..GrainTableSegment::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let err = serde_json::from_str::<GrainDocument>(r#"{"version": 2, "segments": []}"#)
+            .expect_err("version 2 is not supported");
+        assert!(
+            err.to_string()
+                .contains("Unsupported grain document version 2, expected 1 to 1"),
+            "{err}"
+        );
+
+        let document = GrainDocument {
+            version: 0,
+            metadata: DocumentMetadata::default(),
+            segments: Vec::new(),
+        };
+        assert!(matches!(
+            document.to_segments(),
+            Err(Error::UnsupportedVersion {
+                version: 0,
+                supported: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_segments() {
+        let mut document =
+            GrainDocument::from_segments(&segments(), DocumentMetadata::default(), TimeUnit::Ticks);
+        if let Some(segment) = document.segments.first_mut() {
+            segment.luma.ar_coeffs.push(0);
+        }
+        assert!(matches!(
+            document.to_segments(),
+            Err(Error::InvalidDocumentSegment {
+                segment: 0,
+                violation: Violation::CoefficientCount {
+                    plane: 0,
+                    expected: 4,
+                    actual: 5
+                }
+            })
+        ));
+
+        if let Some(segment) = document.segments.first_mut() {
+            segment.luma.ar_coeffs.pop();
+            segment.start = Timestamp::Seconds(-1.0);
+        }
+        assert!(matches!(
+            document.to_segments(),
+            Err(Error::InvalidTimestamp { segment: 0 })
+        ));
+    }
+
+    #[cfg(all(feature = "parse", feature = "create"))]
+    #[test]
+    fn converts_grain_tables() {
+        let table = crate::grain_table_to_string(&segments());
+        let document =
+            GrainDocument::from_grain_table(&table, DocumentMetadata::default(), TimeUnit::Ticks)
+                .expect("table is valid");
+        assert_eq!(document.to_grain_table().expect("document is valid"), table);
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// The error type of parsing and writing grain tables and of generating them
+// from video, so that callers can react to each kind of failure.
+
+use std::{fmt, io, num::ParseIntError, ops::RangeInclusive, str::Utf8Error};
+
+use crate::Violation;
+
+/// An error from parsing or writing a grain table, from generating one, or
+/// from a call to the C API.
+///
+/// Errors found on a line of a grain table include its line number,
+/// starting from 1. Planes are numbered 0 for Y, 1 for Cb and 2 for Cr.
+///
+/// This implements [`std::error::Error`], so it converts to
+/// [`anyhow::Error`] with `?`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing failed, or the input is not valid UTF-8.
+    Io(io::Error),
+    /// The input does not start with a `filmgrn1` header. `line` is `None`
+    /// if the input is empty.
+    MalformedHeader { line: Option<usize> },
+    /// A line has a different tag than the one expected.
+    UnexpectedTag {
+        line: usize,
+        expected: &'static str,
+        actual: String,
+    },
+    /// The input ended where a line with the `expected` tag was required.
+    UnexpectedEnd { expected: &'static str },
+    /// A line has more or fewer values than its tag requires.
+    ValueCount {
+        line: usize,
+        tag: &'static str,
+        expected: RangeInclusive<usize>,
+        actual: usize,
+    },
+    /// A value is not a valid integer of its field's type.
+    InvalidValue {
+        line: usize,
+        field: &'static str,
+        value: String,
+        source: ParseIntError,
+    },
+    /// A value is outside of the range allowed for its field.
+    ValueOutOfRange {
+        line: usize,
+        field: &'static str,
+        value: u64,
+        range: RangeInclusive<u64>,
+    },
+    /// The end time of a segment is before its start time.
+    EndBeforeStart { line: usize },
+    /// A plane has more scaling points than AV1 can signal.
+    TooManyPoints {
+        line: usize,
+        plane: usize,
+        max: usize,
+        actual: usize,
+    },
+    /// The number of auto-regressive coefficients of a plane does not match
+    /// `ar_coeff_lag`.
+    CoefficientCount {
+        line: usize,
+        plane: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// A segment has a violation of [`Severity::Error`](crate::Severity).
+    /// `line` is the line of its `E` tag.
+    InvalidSegment { line: usize, violation: Violation },
+    /// The source and denoised frames have different resolutions.
+    ResolutionMismatch {
+        chroma: bool,
+        source_size: (usize, usize),
+        denoised_size: (usize, usize),
+    },
+    /// A grain document has a newer schema version than `supported`, or an
+    /// invalid one.
+    UnsupportedVersion { version: u32, supported: u32 },
+    /// A timestamp of a grain document is negative or too large. `segment`
+    /// is the index of its segment.
+    InvalidTimestamp { segment: usize },
+    /// A timestamp of a grain document counts frames, but the document has
+    /// no frame rate.
+    MissingFrameRate { segment: usize },
+    /// A plane of a grain document segment has more scaling points than AV1
+    /// can signal.
+    TooManyDocumentPoints {
+        segment: usize,
+        plane: usize,
+        max: usize,
+        actual: usize,
+    },
+    /// A grain document segment has a violation of
+    /// [`Severity::Error`](crate::Severity).
+    InvalidDocumentSegment {
+        segment: usize,
+        violation: Violation,
+    },
+    /// A segment of a [`GrainTable`](crate::GrainTable) starts before the
+    /// previous segment ends.
+    OverlappingSegments {
+        start_time: u64,
+        previous_end_time: u64,
+    },
+    /// A pointer passed to the C API is null.
+    NullPointer { argument: &'static str },
+    /// A string passed to the C API is not valid UTF-8.
+    InvalidUtf8 {
+        argument: &'static str,
+        source: Utf8Error,
+    },
+    /// A struct passed to the C API has a `struct_size` smaller than the
+    /// `expected` size of the struct.
+    StructSize {
+        argument: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A segment index passed to the C API is not less than the number of
+    /// segments in the table.
+    IndexOutOfBounds { index: usize, len: usize },
+    /// A value passed to the C API is outside of the range allowed for it.
+    InvalidArgument {
+        argument: &'static str,
+        value: i64,
+        range: RangeInclusive<i64>,
+    },
+}
+
+impl fmt::Display for Error {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PLANES: [&str; 3] = ["Y", "Cb", "Cr"];
+        let name = |plane: usize| PLANES.get(plane).copied().unwrap_or("unknown");
+        match self {
+            Self::Io(error) => write!(f, "Failed to read or write film grain table: {error}"),
+            Self::MalformedHeader { line: None } => write!(f, "Expected filmgrn1 header"),
+            Self::MalformedHeader { line: Some(line) } => {
+                write!(f, "line {line}: expected filmgrn1 header")
+            }
+            Self::UnexpectedTag {
+                line,
+                expected,
+                actual,
+            } => write!(f, "line {line}: expected {expected} line, got {actual}"),
+            Self::UnexpectedEnd { expected } => {
+                write!(f, "Expected {expected} line, got end of input")
+            }
+            Self::ValueCount {
+                line,
+                tag,
+                expected,
+                actual,
+            } => {
+                if expected.start() == expected.end() {
+                    write!(
+                        f,
+                        "line {line}: expected {} values on {tag} line, got {actual}",
+                        expected.start()
+                    )
+                } else {
+                    write!(
+                        f,
+                        "line {line}: expected {} to {} values on {tag} line, got {actual}",
+                        expected.start(),
+                        expected.end()
+                    )
+                }
+            }
+            Self::InvalidValue {
+                line,
+                field,
+                value,
+                source,
+            } => write!(
+                f,
+                "line {line}: failed to parse {field} value `{value}`: {source}"
+            ),
+            Self::ValueOutOfRange {
+                line,
+                field,
+                value,
+                range,
+            } => write!(
+                f,
+                "line {line}: {field} must be between {} and {}, got {value}",
+                range.start(),
+                range.end()
+            ),
+            Self::EndBeforeStart { line } => {
+                write!(f, "line {line}: start time must be before end time")
+            }
+            Self::TooManyPoints {
+                line,
+                plane,
+                max,
+                actual,
+            } => write!(
+                f,
+                "line {line}: expected at most {max} {} points, got {actual}",
+                name(*plane)
+            ),
+            Self::CoefficientCount {
+                line,
+                plane,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "line {line}: expected {expected} {} coefficients, got {actual}",
+                name(*plane)
+            ),
+            Self::InvalidSegment { line, violation } => write!(f, "line {line}: {violation}"),
+            Self::ResolutionMismatch {
+                chroma,
+                source_size,
+                denoised_size,
+            } => write!(
+                f,
+                "{} resolutions were not equal, {}x{} != {}x{}",
+                if *chroma { "Chroma" } else { "Luma" },
+                source_size.0,
+                source_size.1,
+                denoised_size.0,
+                denoised_size.1
+            ),
+            Self::UnsupportedVersion { version, supported } => write!(
+                f,
+                "Unsupported grain document version {version}, expected 1 to {supported}"
+            ),
+            Self::InvalidTimestamp { segment } => {
+                write!(f, "segment {segment}: timestamp is negative or too large")
+            }
+            Self::MissingFrameRate { segment } => write!(
+                f,
+                "segment {segment}: timestamp counts frames, but the document has no frame rate"
+            ),
+            Self::TooManyDocumentPoints {
+                segment,
+                plane,
+                max,
+                actual,
+            } => write!(
+                f,
+                "segment {segment}: expected at most {max} {} points, got {actual}",
+                name(*plane)
+            ),
+            Self::InvalidDocumentSegment { segment, violation } => {
+                write!(f, "segment {segment}: {violation}")
+            }
+            Self::OverlappingSegments {
+                start_time,
+                previous_end_time,
+            } => write!(
+                f,
+                "Segment starting at {start_time} overlaps the previous segment, which ends at {previous_end_time}"
+            ),
+            Self::NullPointer { argument } => write!(f, "{argument} is NULL"),
+            Self::InvalidUtf8 { argument, source } => {
+                write!(f, "{argument} is not valid UTF-8: {source}")
+            }
+            Self::StructSize {
+                argument,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{argument} has a struct_size of {actual}, expected at least {expected}"
+            ),
+            Self::IndexOutOfBounds { index, len } => write!(
+                f,
+                "Segment index {index} is out of bounds for a table of {len} segments"
+            ),
+            Self::InvalidArgument {
+                argument,
+                value,
+                range,
+            } => write!(
+                f,
+                "{argument} must be between {} and {}, got {value}",
+                range.start(),
+                range.end()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::InvalidValue { source, .. } => Some(source),
+            Self::InvalidUtf8 { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This module mirrors the `AVFilmGrainAOMParams` struct of FFmpeg's
+// libavutil, which holds the AV1 film grain parameters of
+// `AV_FRAME_DATA_FILM_GRAIN_PARAMS` side data in the `codec.aom` field of
+// `AVFilmGrainParams`.
+//
+// The random seed is stored in the enclosing `AVFilmGrainParams` rather than
+// in `AVFilmGrainAOMParams`, so it is passed separately when converting.
+// Like dav1d, FFmpeg centers the chroma multipliers and offsets on zero.
+
+use std::ffi::c_int;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y_POINTS,
+    util::{centered_field, checked_field, copy_prefix, scaling_points},
+};
+
+/// The `AVFilmGrainParamsType` of side data holding AV1 film grain
+/// parameters, `AV_FILM_GRAIN_PARAMS_AV1`.
+pub const AV_FILM_GRAIN_PARAMS_AV1: c_int = 1;
+
+/// A layout-compatible mirror of FFmpeg's `AVFilmGrainAOMParams`, as found in
+/// the `codec.aom` field of `AVFilmGrainParams` when its `type` is
+/// [`AV_FILM_GRAIN_PARAMS_AV1`].
+///
+/// Convert it from a [`GrainTableSegment`] with `From`, and back with
+/// [`AVFilmGrainAOMParams::to_segment`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AVFilmGrainAOMParams {
+    pub num_y_points: c_int,
+    /// Value and scaling of each luma scaling point
+    pub y_points: [[u8; 2]; NUM_Y_POINTS],
+    pub chroma_scaling_from_luma: c_int,
+    pub num_uv_points: [c_int; 2],
+    /// Value and scaling of each Cb and Cr scaling point
+    pub uv_points: [[[u8; 2]; NUM_UV_POINTS]; 2],
+    /// The scaling shift, between 8 and 11
+    pub scaling_shift: c_int,
+    pub ar_coeff_lag: c_int,
+    pub ar_coeffs_y: [i8; NUM_Y_COEFFS],
+    pub ar_coeffs_uv: [[i8; NUM_UV_COEFFS]; 2],
+    /// The AR coefficient shift, between 6 and 9
+    pub ar_coeff_shift: c_int,
+    pub grain_scale_shift: c_int,
+    /// The Cb and Cr multipliers, minus 128
+    pub uv_mult: [c_int; 2],
+    /// The Cb and Cr luma multipliers, minus 128
+    pub uv_mult_luma: [c_int; 2],
+    /// The Cb and Cr offsets, minus 256
+    pub uv_offset: [c_int; 2],
+    pub overlap_flag: c_int,
+    /// Whether the output is clipped to the restricted range, which
+    /// corresponds to `clip_to_restricted_range`
+    pub limit_output_range: c_int,
+}
+
+impl From<&GrainTableSegment> for AVFilmGrainAOMParams {
+    /// Converts a segment to the representation used by FFmpeg. The random
+    /// seed and the start and end times of the segment are not carried over.
+    /// The `seed` of the enclosing
+    /// `AVFilmGrainParams` should be set to the `random_seed` of the segment.
+    /// Segments with `apply_grain` unset convert to parameters without
+    /// scaling points, which add no grain.
+    #[inline]
+    fn from(segment: &GrainTableSegment) -> Self {
+        if !segment.apply_grain {
+            return Self::from(&GrainTableSegment::default());
+        }
+
+        let mut y_points = [[0; 2]; NUM_Y_POINTS];
+        copy_prefix(&mut y_points, &segment.scaling_points_y);
+        let mut uv_points = [[[0; 2]; NUM_UV_POINTS]; 2];
+        copy_prefix(&mut uv_points[0], &segment.scaling_points_cb);
+        copy_prefix(&mut uv_points[1], &segment.scaling_points_cr);
+        let mut ar_coeffs_y = [0; NUM_Y_COEFFS];
+        copy_prefix(&mut ar_coeffs_y, &segment.ar_coeffs_y);
+        let mut ar_coeffs_uv = [[0; NUM_UV_COEFFS]; 2];
+        copy_prefix(&mut ar_coeffs_uv[0], &segment.ar_coeffs_cb);
+        copy_prefix(&mut ar_coeffs_uv[1], &segment.ar_coeffs_cr);
+
+        Self {
+            num_y_points: segment.scaling_points_y.len() as c_int,
+            y_points,
+            chroma_scaling_from_luma: c_int::from(segment.chroma_scaling_from_luma),
+            num_uv_points: [
+                segment.scaling_points_cb.len() as c_int,
+                segment.scaling_points_cr.len() as c_int,
+            ],
+            uv_points,
+            scaling_shift: c_int::from(segment.scaling_shift),
+            ar_coeff_lag: c_int::from(segment.ar_coeff_lag),
+            ar_coeffs_y,
+            ar_coeffs_uv,
+            ar_coeff_shift: c_int::from(segment.ar_coeff_shift),
+            grain_scale_shift: c_int::from(segment.grain_scale_shift),
+            uv_mult: [
+                c_int::from(segment.cb_mult) - 128,
+                c_int::from(segment.cr_mult) - 128,
+            ],
+            uv_mult_luma: [
+                c_int::from(segment.cb_luma_mult) - 128,
+                c_int::from(segment.cr_luma_mult) - 128,
+            ],
+            uv_offset: [
+                c_int::from(segment.cb_offset) - 256,
+                c_int::from(segment.cr_offset) - 256,
+            ],
+            overlap_flag: c_int::from(segment.overlap_flag),
+            limit_output_range: c_int::from(segment.clip_to_restricted_range),
+        }
+    }
+}
+
+impl AVFilmGrainAOMParams {
+    /// Converts parameters from FFmpeg to a segment, with a `start_time` and
+    /// `end_time` of 0. `seed` is the `seed` field of the enclosing
+    /// `AVFilmGrainParams`.
+    ///
+    /// The coefficients of each plane are truncated to the number used by
+    /// `ar_coeff_lag`, which is how parsed segments store them.
+    ///
+    /// # Errors
+    ///
+    /// - If `seed` does not fit in 16 bits
+    /// - If a field is outside of the range that its bitstream syntax
+    ///   element can represent, such as a negative number of scaling points
+    #[inline]
+    pub fn to_segment(&self, seed: u64) -> Result<GrainTableSegment> {
+        let ar_coeff_lag = checked_field::<u8>(self.ar_coeff_lag, 0..=3, "ar_coeff_lag")?;
+        let num_pos_luma = 2 * usize::from(ar_coeff_lag) * usize::from(ar_coeff_lag + 1);
+        let [points_cb, points_cr] = &self.uv_points;
+        let [coeffs_cb, coeffs_cr] = &self.ar_coeffs_uv;
+        let [num_cb_points, num_cr_points] = self.num_uv_points;
+        let [cb_mult, cr_mult] = self.uv_mult;
+        let [cb_luma_mult, cr_luma_mult] = self.uv_mult_luma;
+        let [cb_offset, cr_offset] = self.uv_offset;
+
+        Ok(GrainTableSegment {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y: scaling_points(&self.y_points, self.num_y_points, "num_y_points")?,
+            scaling_points_cb: scaling_points(points_cb, num_cb_points, "num_uv_points")?,
+            scaling_points_cr: scaling_points(points_cr, num_cr_points, "num_uv_points")?,
+            scaling_shift: checked_field(self.scaling_shift, 8..=11, "scaling_shift")?,
+            ar_coeff_lag,
+            ar_coeffs_y: self
+                .ar_coeffs_y
+                .iter()
+                .copied()
+                .take(num_pos_luma)
+                .collect(),
+            ar_coeffs_cb: coeffs_cb.iter().copied().take(num_pos_luma + 1).collect(),
+            ar_coeffs_cr: coeffs_cr.iter().copied().take(num_pos_luma + 1).collect(),
+            ar_coeff_shift: checked_field(self.ar_coeff_shift, 6..=9, "ar_coeff_shift")?,
+            cb_mult: centered_field(cb_mult, 128, "uv_mult")?,
+            cb_luma_mult: centered_field(cb_luma_mult, 128, "uv_mult_luma")?,
+            cb_offset: centered_field(cb_offset, 256, "uv_offset")?,
+            cr_mult: centered_field(cr_mult, 128, "uv_mult")?,
+            cr_luma_mult: centered_field(cr_luma_mult, 128, "uv_mult_luma")?,
+            cr_offset: centered_field(cr_offset, 256, "uv_offset")?,
+            overlap_flag: self.overlap_flag != 0,
+            chroma_scaling_from_luma: self.chroma_scaling_from_luma != 0,
+            grain_scale_shift: checked_field(self.grain_scale_shift, 0..=3, "grain_scale_shift")?,
+            random_seed: u16::try_from(seed).context("seed must fit in 16 bits")?,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: self.limit_output_range != 0,
+            comments: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [128, 40], [255, 30]]),
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: (0..24).collect(),
+            ar_coeffs_cb: (-25..0).collect(),
+            ar_coeffs_cr: (100..125).collect(),
+            chroma_scaling_from_luma: true,
+            random_seed: 7391,
+            clip_to_restricted_range: true,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    #[test]
+    fn layout_matches_ffmpeg() {
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, y_points), 4);
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, uv_points), 44);
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, ar_coeffs_y), 92);
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, ar_coeffs_uv), 116);
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, ar_coeff_shift), 168);
+        assert_eq!(offset_of!(AVFilmGrainAOMParams, limit_output_range), 204);
+        assert_eq!(size_of::<AVFilmGrainAOMParams>(), 208);
+    }
+
+    #[test]
+    fn round_trip() {
+        let segment = segment();
+        let params = AVFilmGrainAOMParams::from(&segment);
+        assert_eq!(params.num_uv_points, [0, 0]);
+        assert_eq!(params.uv_mult, [-128, -128]);
+        assert_eq!(params.uv_offset, [-256, -256]);
+        assert_eq!(params.ar_coeffs_uv[1].last(), Some(&124));
+        assert_eq!(params.limit_output_range, 1);
+
+        assert_eq!(
+            params
+                .to_segment(u64::from(segment.random_seed))
+                .expect("valid params"),
+            segment
+        );
+    }
+
+    #[test]
+    fn rejects_unrepresentable_params() {
+        let params = AVFilmGrainAOMParams::from(&segment());
+        assert!(params.to_segment(1 << 16).is_err());
+        for invalid in [
+            AVFilmGrainAOMParams {
+                num_uv_points: [0, 11],
+                ..params
+            },
+            AVFilmGrainAOMParams {
+                uv_mult_luma: [128, 0],
+                ..params
+            },
+            AVFilmGrainAOMParams {
+                scaling_shift: 7,
+                ..params
+            },
+        ] {
+            assert!(invalid.to_segment(0).is_err());
+        }
+    }
+}
@@ -0,0 +1,1053 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This module converts between `GrainTableSegment`s and the film grain
+// characteristics SEI message of ITU-T H.274, which carries film grain for
+// HEVC and VVC. The two models differ enough that the conversion can only
+// be approximate, and every conversion reports what it could not carry over.
+//
+// The SEI describes the grain of each component separately for a number of
+// intensity intervals, with up to six model values per interval:
+//
+// | Index | Frequency filtering         | Auto-regression                 |
+// |-------|-----------------------------|---------------------------------|
+// | 0     | Standard deviation          | Standard deviation              |
+// | 1     | Horizontal high cutoff      | First order correlation         |
+// | 2     | Vertical high cutoff        | Colour correlation              |
+// | 3     | Horizontal low cutoff       | Diagonal first order correlation|
+// | 4     | Vertical low cutoff         | Aspect ratio                    |
+// | 5     | Colour correlation          | Second order correlation        |
+//
+// Standard deviations, correlations and aspect ratios are treated as fixed
+// point values with `log2_scale_factor` fractional bits, and standard
+// deviations are in units of the signalled bit depth.
+//
+// The standard deviation of each interval becomes an AV1 scaling point,
+// accounting for the amplitude of the Gaussian sequence and the variance
+// added by auto-regressive filtering. Correlations map directly onto the
+// corresponding AV1 coefficients. Frequency filtering is approximated by
+// a separable first order auto-regressive filter whose coefficient in each
+// direction is `1 - cutoff / 16`, which keeps white noise at a cutoff of 16
+// and gives smoother grain as the cutoff decreases.
+
+use std::fmt;
+
+use anyhow::{Result, ensure};
+use arrayvec::ArrayVec;
+
+use crate::{
+    DEFAULT_GRAIN_SEED, GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS,
+    NUM_Y_POINTS, random::GAUSSIAN_SEQUENCE,
+};
+
+/// The maximum number of model values of an intensity interval.
+pub const H274_MAX_MODEL_VALUES: usize = 6;
+
+/// The high cutoff frequency used when the SEI does not signal one.
+const DEFAULT_CUTOFF: i32 = 8;
+/// The cutoff frequency at which frequency filtering keeps the full band.
+const FULL_BAND_CUTOFF: f64 = 16.;
+
+/// The film grain model of an H.274 film grain characteristics SEI message,
+/// as signalled by `film_grain_model_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilmGrainModel {
+    /// Grain shaped by filtering white noise in the frequency domain
+    FrequencyFiltering,
+    /// Grain shaped by an auto-regressive filter
+    AutoRegression,
+}
+
+/// How the grain is combined with the decoded samples, as signalled by
+/// `blending_mode_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendingMode {
+    /// The grain is added to the samples
+    Additive,
+    /// The grain is multiplied with the samples
+    Multiplicative,
+}
+
+/// The grain of one component over a range of sample intensities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntensityInterval {
+    /// The lowest intensity of the interval, in 8-bit units
+    pub lower_bound: u8,
+    /// The highest intensity of the interval, inclusive, in 8-bit units
+    pub upper_bound: u8,
+    /// The `comp_model_value`s of the interval. Values which are not
+    /// provided take their default, which is 8 for high cutoffs, unity for
+    /// the aspect ratio and zero otherwise. The standard deviation must
+    /// always be provided.
+    pub model_values: ArrayVec<i32, H274_MAX_MODEL_VALUES>,
+}
+
+/// The parameters of an H.274 film grain characteristics SEI message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilmGrainCharacteristics {
+    /// The film grain model
+    pub model: FilmGrainModel,
+    /// The bit depth in which standard deviations are expressed, which is
+    /// that of the video unless a separate colour description is signalled
+    pub bit_depth: u8,
+    /// How the grain is combined with the samples
+    pub blending_mode: BlendingMode,
+    /// The number of fractional bits of the model values, between 0 and 15
+    pub log2_scale_factor: u8,
+    /// The intensity intervals of the Y, Cb and Cr components. A component
+    /// without intervals has no grain, as if `comp_model_present_flag` was
+    /// unset.
+    pub components: [Vec<IntensityInterval>; 3],
+}
+
+/// A part of the film grain parameters which could not be converted exactly.
+///
+/// Planes are numbered 0 for Y, 1 for Cb and 2 for Cr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionLoss {
+    /// Multiplicative blending was replaced with the additive blending of AV1.
+    MultiplicativeBlending,
+    /// The correlations or cutoffs of a plane differ between intervals, and
+    /// were averaged since AV1 filters the grain of a plane with a single
+    /// set of coefficients.
+    VaryingModelValues { plane: usize },
+    /// The low cutoff frequencies of a plane were ignored, since
+    /// auto-regressive filtering cannot remove low frequencies.
+    LowCutoffsIgnored { plane: usize },
+    /// The aspect ratio of the grain of a plane was ignored.
+    AspectRatioIgnored { plane: usize },
+    /// A plane has more intensity intervals than scaling points can
+    /// represent, so the intervals were reduced to their midpoints and
+    /// possibly resampled.
+    IntervalsApproximated { plane: usize },
+    /// The intervals of a plane do not cover every intensity, and AV1
+    /// extends or interpolates the scaling function over the gaps instead of
+    /// leaving them without grain.
+    UncoveredIntensities { plane: usize },
+    /// A standard deviation, cutoff or coefficient of a plane exceeded
+    /// the range of the target format and was clipped.
+    ValuesClipped { plane: usize },
+    /// The scaling function of a plane is interpolated between scaling
+    /// points, and was approximated by constant intensity intervals.
+    InterpolatedScaling { plane: usize },
+    /// Chroma grain was scaled by the luma intensity, and was converted to
+    /// intervals of the chroma intensity.
+    ChromaScaledFromLuma,
+    /// The chroma scaling of a plane combines luma and chroma intensities,
+    /// which intervals of the chroma intensity cannot represent.
+    ChromaCombination { plane: usize },
+    /// The auto-regressive coefficients of a plane include positions or
+    /// asymmetries which the target model cannot represent.
+    UnrepresentableCoefficients { plane: usize },
+    /// The random seed is not signalled, so a conversion back uses
+    /// [`DEFAULT_GRAIN_SEED`].
+    RandomSeed,
+    /// Grain blocks do not overlap, which cannot be signalled, so
+    /// a conversion back enables overlap.
+    NoOverlap,
+    /// Samples are clipped to the restricted range after adding grain,
+    /// which cannot be signalled, so a conversion back does not clip.
+    RestrictedRange,
+}
+
+impl fmt::Display for ConversionLoss {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PLANES: [&str; 3] = ["Y", "Cb", "Cr"];
+        let name = |plane: usize| PLANES.get(plane).copied().unwrap_or("unknown");
+        match *self {
+            Self::MultiplicativeBlending => {
+                write!(
+                    f,
+                    "multiplicative blending was replaced with additive blending"
+                )
+            }
+            Self::VaryingModelValues { plane } => write!(
+                f,
+                "{} correlations or cutoffs vary between intervals and were averaged",
+                name(plane)
+            ),
+            Self::LowCutoffsIgnored { plane } => {
+                write!(f, "{} low cutoff frequencies were ignored", name(plane))
+            }
+            Self::AspectRatioIgnored { plane } => {
+                write!(f, "{} grain aspect ratio was ignored", name(plane))
+            }
+            Self::IntervalsApproximated { plane } => write!(
+                f,
+                "{} has too many intensity intervals and they were approximated",
+                name(plane)
+            ),
+            Self::UncoveredIntensities { plane } => write!(
+                f,
+                "{} intensities outside of every interval receive interpolated grain",
+                name(plane)
+            ),
+            Self::ValuesClipped { plane } => {
+                write!(
+                    f,
+                    "{} values exceeded their range and were clipped",
+                    name(plane)
+                )
+            }
+            Self::InterpolatedScaling { plane } => write!(
+                f,
+                "{} scaling function was approximated by constant intervals",
+                name(plane)
+            ),
+            Self::ChromaScaledFromLuma => write!(
+                f,
+                "chroma scaling from luma was converted to chroma intensity intervals"
+            ),
+            Self::ChromaCombination { plane } => write!(
+                f,
+                "{} scaling combines luma and chroma intensities, which was ignored",
+                name(plane)
+            ),
+            Self::UnrepresentableCoefficients { plane } => write!(
+                f,
+                "{} auto-regressive coefficients could not be represented exactly",
+                name(plane)
+            ),
+            Self::RandomSeed => write!(f, "the random seed was not carried over"),
+            Self::NoOverlap => write!(f, "disabled block overlap was not carried over"),
+            Self::RestrictedRange => {
+                write!(f, "clipping to the restricted range was not carried over")
+            }
+        }
+    }
+}
+
+/// A coefficient of an auto-regressive filter, applied to the grain at
+/// the given row and column offset.
+#[derive(Debug, Clone, Copy)]
+struct Tap {
+    dy: isize,
+    dx: isize,
+    value: f64,
+}
+
+/// Converts an H.274 film grain characteristics SEI message to a segment,
+/// returning the parts of the message which could not be converted exactly.
+///
+/// The segment has a `start_time` and `end_time` of 0, which should be set
+/// from the persistence of the message. It uses [`DEFAULT_GRAIN_SEED`] and
+/// enables overlap, since neither is signalled by the message.
+///
+/// # Errors
+///
+/// - If the bit depth is not between 8 and 16, or `log2_scale_factor` is
+///   greater than 15
+/// - If an interval has no model values, a negative standard deviation, or
+///   a lower bound greater than its upper bound
+/// - If the intervals of a component overlap
+#[inline]
+pub fn h274_to_segment(
+    fgc: &FilmGrainCharacteristics,
+) -> Result<(GrainTableSegment, Vec<ConversionLoss>)> {
+    ensure!(
+        (8..=16).contains(&fgc.bit_depth),
+        "Bit depth must be between 8 and 16, got {}",
+        fgc.bit_depth
+    );
+    ensure!(
+        fgc.log2_scale_factor <= 15,
+        "log2_scale_factor must be between 0 and 15, got {}",
+        fgc.log2_scale_factor
+    );
+    let mut losses = Vec::new();
+    if fgc.blending_mode == BlendingMode::Multiplicative {
+        losses.push(ConversionLoss::MultiplicativeBlending);
+    }
+
+    let unit = f64::from(1u32 << fgc.log2_scale_factor);
+    let has_luma = !fgc.components[0].is_empty();
+    let mut planes: [(Vec<IntensityInterval>, Vec<Tap>); 3] = Default::default();
+    for (plane, (intervals, (sorted, taps))) in fgc.components.iter().zip(&mut planes).enumerate() {
+        *sorted = sorted_intervals(intervals, plane)?;
+        if sorted.is_empty() {
+            continue;
+        }
+        let defaults = default_model_values(fgc.model, 1 << fgc.log2_scale_factor);
+        let values = average_model_values(sorted, defaults, plane, &mut losses);
+        let correlation = |index: usize| values.get(index).map_or(0., |&value| value / unit);
+        let colour_correlation = match fgc.model {
+            FilmGrainModel::FrequencyFiltering => {
+                let mut clipped = false;
+                let mut coefficient = |index: usize| {
+                    let cutoff = values.get(index).copied().unwrap_or_default();
+                    let value = 1. - cutoff / FULL_BAND_CUTOFF;
+                    clipped |= !(0. ..=0.9375).contains(&value);
+                    value.clamp(0., 0.9375)
+                };
+                let horizontal = coefficient(1);
+                let vertical = coefficient(2);
+                if clipped {
+                    losses.push(ConversionLoss::ValuesClipped { plane });
+                }
+                if values
+                    .get(3..5)
+                    .is_some_and(|low| low.iter().any(|&v| v != 0.))
+                {
+                    losses.push(ConversionLoss::LowCutoffsIgnored { plane });
+                }
+                taps.extend([
+                    Tap {
+                        dy: 0,
+                        dx: -1,
+                        value: horizontal,
+                    },
+                    Tap {
+                        dy: -1,
+                        dx: 0,
+                        value: vertical,
+                    },
+                    Tap {
+                        dy: -1,
+                        dx: -1,
+                        value: -horizontal * vertical,
+                    },
+                ]);
+                correlation(5)
+            }
+            FilmGrainModel::AutoRegression => {
+                for (index, offsets) in [
+                    (1, [(0, -1), (-1, 0)]),
+                    (3, [(-1, -1), (-1, 1)]),
+                    (5, [(0, -2), (-2, 0)]),
+                ] {
+                    for (dy, dx) in offsets {
+                        taps.push(Tap {
+                            dy,
+                            dx,
+                            value: correlation(index),
+                        });
+                    }
+                }
+                if values
+                    .get(4)
+                    .is_some_and(|&aspect_ratio| aspect_ratio != unit)
+                {
+                    losses.push(ConversionLoss::AspectRatioIgnored { plane });
+                }
+                correlation(2)
+            }
+        };
+        taps.retain(|tap| tap.value != 0.);
+        if plane > 0 && has_luma && colour_correlation != 0. {
+            // No filter tap has an offset of zero, so it stands for the
+            // coefficient of the collocated luma grain
+            taps.push(Tap {
+                dy: 0,
+                dx: 0,
+                value: colour_correlation,
+            });
+        }
+    }
+
+    let ar_coeff_lag = planes
+        .iter()
+        .flat_map(|(_, taps)| taps)
+        .map(|tap| tap.dy.unsigned_abs().max(tap.dx.unsigned_abs()))
+        .max()
+        .unwrap_or(0) as u8;
+    let max_coeff = planes
+        .iter()
+        .flat_map(|(_, taps)| taps)
+        .map(|tap| tap.value.abs())
+        .fold(0., f64::max);
+    let ar_coeff_shift = if max_coeff < 127. / 128. { 7 } else { 6 };
+    let mut coeffs: [ArrayVec<i8, NUM_UV_COEFFS>; 3] = Default::default();
+    for (plane, ((_, taps), coeffs)) in planes.iter_mut().zip(&mut coeffs).enumerate() {
+        let (quantized, clipped) = quantize_taps(taps, ar_coeff_lag, ar_coeff_shift, plane > 0);
+        if clipped {
+            losses.push(ConversionLoss::ValuesClipped { plane });
+        }
+        // Use the quantized coefficients, so that the variance of the
+        // grain matches what a decoder generates.
+        *taps = coefficient_taps(&quantized, ar_coeff_lag, ar_coeff_shift);
+        *coeffs = quantized;
+    }
+
+    // The ratio between the standard deviation of each interval
+    // and that of the unscaled 8-bit grain
+    let sample_unit = unit * f64::from(1u32 << (fgc.bit_depth - 8));
+    let ratios = planes.each_ref().map(|(intervals, taps)| {
+        let grain_std = grain_std(taps, 0);
+        intervals
+            .iter()
+            .map(|interval| {
+                let std_dev = interval.model_values.first().copied().unwrap_or_default();
+                f64::from(std_dev) / sample_unit / grain_std
+            })
+            .collect::<Vec<_>>()
+    });
+    let max_ratio = ratios.iter().flatten().copied().fold(0., f64::max);
+    let scaling_shift = (8..=11)
+        .rev()
+        .find(|&shift| max_ratio * f64::from(1u32 << shift) <= 255.)
+        .unwrap_or(8);
+
+    let mut points: [ArrayVec<[u8; 2], NUM_Y_POINTS>; 3] = Default::default();
+    for (plane, (((intervals, _), ratios), points)) in
+        planes.iter().zip(&ratios).zip(&mut points).enumerate()
+    {
+        let limit = if plane == 0 {
+            NUM_Y_POINTS
+        } else {
+            NUM_UV_POINTS
+        };
+        let scaled = intervals
+            .iter()
+            .zip(ratios)
+            .map(|(interval, &ratio)| {
+                let scaling = (ratio * f64::from(1u32 << scaling_shift)).round();
+                if scaling > 255. {
+                    losses.push(ConversionLoss::ValuesClipped { plane });
+                }
+                (
+                    interval.lower_bound,
+                    interval.upper_bound,
+                    scaling.min(255.) as u8,
+                )
+            })
+            .collect::<Vec<_>>();
+        *points = intervals_to_points(&scaled, limit, plane, &mut losses);
+    }
+    remove_duplicates(&mut losses);
+
+    let [points_y, points_cb, points_cr] = points;
+    let [coeffs_y, ar_coeffs_cb, ar_coeffs_cr] = coeffs;
+    // Chroma scaling is indexed by the chroma intensity alone
+    let (cb_mult, cb_luma_mult, cb_offset) = identity_combination(&points_cb);
+    let (cr_mult, cr_luma_mult, cr_offset) = identity_combination(&points_cr);
+    Ok((
+        GrainTableSegment {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y: points_y,
+            scaling_points_cb: points_cb.into_iter().collect(),
+            scaling_points_cr: points_cr.into_iter().collect(),
+            scaling_shift,
+            ar_coeff_lag,
+            ar_coeffs_y: coeffs_y.into_iter().take(NUM_Y_COEFFS).collect(),
+            ar_coeffs_cb,
+            ar_coeffs_cr,
+            ar_coeff_shift,
+            cb_mult,
+            cb_luma_mult,
+            cb_offset,
+            cr_mult,
+            cr_luma_mult,
+            cr_offset,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: DEFAULT_GRAIN_SEED,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: false,
+            comments: Vec::new(),
+        },
+        losses,
+    ))
+}
+
+/// Converts a segment to an H.274 film grain characteristics SEI message
+/// using `model`, returning the parts of the segment which could not be
+/// converted exactly.
+///
+/// The message uses additive blending, an 8-bit bit depth and
+/// a `log2_scale_factor` equal to the `ar_coeff_shift` of the segment,
+/// so that coefficients keep their precision. Segments with `apply_grain`
+/// unset convert to a message without any components.
+///
+/// # Errors
+///
+/// - If the segment contains parameters outside of the ranges allowed by
+///   the AV1 specification
+/// - If scaling points are not in increasing order
+#[inline]
+pub fn segment_to_h274(
+    segment: &GrainTableSegment,
+    model: FilmGrainModel,
+) -> Result<(FilmGrainCharacteristics, Vec<ConversionLoss>)> {
+    if !segment.apply_grain {
+        return segment_to_h274(
+            &GrainTableSegment {
+                random_seed: segment.random_seed,
+                ..GrainTableSegment::default()
+            },
+            model,
+        );
+    }
+    ensure!(
+        (8..=11).contains(&segment.scaling_shift),
+        "scaling_shift must be between 8 and 11"
+    );
+    ensure!(
+        segment.ar_coeff_lag <= 3,
+        "ar_coeff_lag must be between 0 and 3"
+    );
+    ensure!(
+        (6..=9).contains(&segment.ar_coeff_shift),
+        "ar_coeff_shift must be between 6 and 9"
+    );
+    ensure!(
+        segment.grain_scale_shift <= 3,
+        "grain_scale_shift must be between 0 and 3"
+    );
+
+    let mut losses = Vec::new();
+    let log2_scale_factor = segment.ar_coeff_shift;
+    let unit = f64::from(1u32 << log2_scale_factor);
+    let has_luma = !segment.scaling_points_y.is_empty();
+    let mut components: [Vec<IntensityInterval>; 3] = Default::default();
+    for (plane, component) in components.iter_mut().enumerate() {
+        let (points, coeffs): (&[[u8; 2]], &[i8]) = match plane {
+            0 => (&segment.scaling_points_y, &segment.ar_coeffs_y),
+            1 => (&segment.scaling_points_cb, &segment.ar_coeffs_cb),
+            _ => (&segment.scaling_points_cr, &segment.ar_coeffs_cr),
+        };
+        let points = if plane > 0 && segment.chroma_scaling_from_luma {
+            if plane == 1 && has_luma {
+                losses.push(ConversionLoss::ChromaScaledFromLuma);
+            }
+            &segment.scaling_points_y
+        } else {
+            let combination = match plane {
+                1 => (segment.cb_mult, segment.cb_luma_mult, segment.cb_offset),
+                2 => (segment.cr_mult, segment.cr_luma_mult, segment.cr_offset),
+                _ => identity_combination(points),
+            };
+            if !points.is_empty() && combination != identity_combination(points) {
+                losses.push(ConversionLoss::ChromaCombination { plane });
+            }
+            points
+        };
+        if points.is_empty() {
+            continue;
+        }
+
+        let taps = coefficient_taps(coeffs, segment.ar_coeff_lag, segment.ar_coeff_shift);
+        let (filter_taps, luma_taps): (Vec<Tap>, Vec<Tap>) =
+            taps.into_iter().partition(|tap| (tap.dy, tap.dx) != (0, 0));
+        let tap = |dy: isize, dx: isize| {
+            filter_taps
+                .iter()
+                .find(|tap| (tap.dy, tap.dx) == (dy, dx))
+                .map_or(0., |tap| tap.value)
+        };
+        let fixed = |value: f64| (value * unit).round() as i32;
+        let colour_correlation = if plane > 0 && has_luma {
+            luma_taps.first().map_or(0., |tap| tap.value)
+        } else {
+            0.
+        };
+
+        let mut represented = vec![(0, -1), (-1, 0)];
+        let mut values = match model {
+            FilmGrainModel::FrequencyFiltering => {
+                represented.push((-1, -1));
+                let mut clipped = false;
+                let mut cutoff = |value: f64| {
+                    let cutoff = (FULL_BAND_CUTOFF * (1. - value)).round();
+                    clipped |= !(2. ..=FULL_BAND_CUTOFF).contains(&cutoff);
+                    cutoff.clamp(2., FULL_BAND_CUTOFF) as i32
+                };
+                let values = [
+                    0,
+                    cutoff(tap(0, -1)),
+                    cutoff(tap(-1, 0)),
+                    0,
+                    0,
+                    fixed(colour_correlation),
+                ];
+                if clipped {
+                    losses.push(ConversionLoss::ValuesClipped { plane });
+                }
+                values
+            }
+            FilmGrainModel::AutoRegression => {
+                let mut asymmetric = false;
+                let mut pair = |a: (isize, isize), b: (isize, isize)| {
+                    represented.extend([a, b]);
+                    let (a, b) = (tap(a.0, a.1), tap(b.0, b.1));
+                    asymmetric |= a != b;
+                    fixed(a.midpoint(b))
+                };
+                let values = [
+                    0,
+                    pair((0, -1), (-1, 0)),
+                    fixed(colour_correlation),
+                    pair((-1, -1), (-1, 1)),
+                    unit as i32,
+                    pair((0, -2), (-2, 0)),
+                ];
+                if asymmetric {
+                    losses.push(ConversionLoss::UnrepresentableCoefficients { plane });
+                }
+                values
+            }
+        };
+        if filter_taps
+            .iter()
+            .any(|tap| !represented.contains(&(tap.dy, tap.dx)))
+        {
+            losses.push(ConversionLoss::UnrepresentableCoefficients { plane });
+        }
+        // Only signal model values up to the last one which differs from
+        // its default.
+        let count = values
+            .iter()
+            .zip(default_model_values(model, 1 << log2_scale_factor))
+            .rposition(|(&value, default)| value != default)
+            .map_or(1, |index| index + 1);
+
+        let grain_std = grain_std(&filter_taps, segment.grain_scale_shift);
+        let scale = grain_std * unit / f64::from(1u32 << segment.scaling_shift);
+        let intervals = points_to_intervals(points)?;
+        if has_scaling_ramps(points) {
+            losses.push(ConversionLoss::InterpolatedScaling { plane });
+        }
+        for (lower_bound, upper_bound, scaling) in intervals {
+            values[0] = (f64::from(scaling) * scale).round() as i32;
+            component.push(IntensityInterval {
+                lower_bound,
+                upper_bound,
+                model_values: values.into_iter().take(count).collect(),
+            });
+        }
+    }
+    if segment.random_seed != DEFAULT_GRAIN_SEED {
+        losses.push(ConversionLoss::RandomSeed);
+    }
+    if !segment.overlap_flag {
+        losses.push(ConversionLoss::NoOverlap);
+    }
+    if segment.clip_to_restricted_range {
+        losses.push(ConversionLoss::RestrictedRange);
+    }
+    remove_duplicates(&mut losses);
+
+    Ok((
+        FilmGrainCharacteristics {
+            model,
+            bit_depth: 8,
+            blending_mode: BlendingMode::Additive,
+            log2_scale_factor,
+            components,
+        },
+        losses,
+    ))
+}
+
+/// Removes repeated losses, keeping the first of each.
+fn remove_duplicates(losses: &mut Vec<ConversionLoss>) {
+    let mut seen = Vec::with_capacity(losses.len());
+    losses.retain(|&loss| {
+        let first = !seen.contains(&loss);
+        seen.push(loss);
+        first
+    });
+}
+
+/// Returns the intervals of a component sorted by intensity, checking that
+/// they are valid and do not overlap.
+fn sorted_intervals(
+    intervals: &[IntensityInterval],
+    plane: usize,
+) -> Result<Vec<IntensityInterval>> {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by_key(|interval| interval.lower_bound);
+    let mut previous_upper_bound: Option<u8> = None;
+    for interval in &sorted {
+        ensure!(
+            interval.lower_bound <= interval.upper_bound,
+            "Intensity interval of plane {plane} has a lower bound above its upper bound"
+        );
+        ensure!(
+            interval
+                .model_values
+                .first()
+                .is_some_and(|&std_dev| std_dev >= 0),
+            "Intensity interval of plane {plane} must have a non-negative standard deviation"
+        );
+        ensure!(
+            previous_upper_bound.is_none_or(|upper_bound| interval.lower_bound > upper_bound),
+            "Intensity intervals of plane {plane} overlap"
+        );
+        previous_upper_bound = Some(interval.upper_bound);
+    }
+    Ok(sorted)
+}
+
+/// Returns the default model values of `model`, with a unit aspect ratio
+/// of `unit`.
+fn default_model_values(model: FilmGrainModel, unit: i32) -> [i32; H274_MAX_MODEL_VALUES] {
+    match model {
+        FilmGrainModel::FrequencyFiltering => [0, DEFAULT_CUTOFF, DEFAULT_CUTOFF, 0, 0, 0],
+        FilmGrainModel::AutoRegression => [0, 0, 0, 0, unit, 0],
+    }
+}
+
+/// Returns the model values of a component averaged over its intervals,
+/// filling in the defaults of values which are not provided.
+fn average_model_values(
+    intervals: &[IntensityInterval],
+    defaults: [i32; H274_MAX_MODEL_VALUES],
+    plane: usize,
+    losses: &mut Vec<ConversionLoss>,
+) -> [f64; H274_MAX_MODEL_VALUES] {
+    let mut averages = defaults.map(f64::from);
+    for (index, (average, default)) in averages.iter_mut().zip(defaults).enumerate().skip(1) {
+        let values = intervals
+            .iter()
+            .map(|interval| interval.model_values.get(index).copied().unwrap_or(default))
+            .collect::<Vec<_>>();
+        if values.windows(2).any(|pair| pair.first() != pair.last()) {
+            losses.push(ConversionLoss::VaryingModelValues { plane });
+        }
+        *average = values.iter().copied().map(f64::from).sum::<f64>() / values.len().max(1) as f64;
+    }
+    averages
+}
+
+/// Quantizes filter taps into AV1 coefficients, returning them along with
+/// whether any of them had to be clipped. Chroma coefficients end with
+/// the coefficient of the collocated luma grain.
+fn quantize_taps(
+    taps: &[Tap],
+    ar_coeff_lag: u8,
+    ar_coeff_shift: u8,
+    chroma: bool,
+) -> (ArrayVec<i8, NUM_UV_COEFFS>, bool) {
+    let lag = isize::from(ar_coeff_lag);
+    let offsets = (-lag..=0)
+        .flat_map(|dy| (-lag..=lag).map(move |dx| (dy, dx)))
+        .take_while(|&offset| offset != (0, 0))
+        .chain(chroma.then_some((0, 0)));
+
+    let mut clipped = false;
+    let coeffs = offsets
+        .map(|offset| {
+            let value = taps
+                .iter()
+                .find(|tap| (tap.dy, tap.dx) == offset)
+                .map_or(0., |tap| tap.value);
+            let coeff = (value * f64::from(1u32 << ar_coeff_shift)).round();
+            clipped |= !(-128. ..=127.).contains(&coeff);
+            coeff.clamp(-128., 127.) as i8
+        })
+        .collect();
+    (coeffs, clipped)
+}
+
+/// Returns the non-zero coefficients of a plane as filter taps. A luma
+/// coefficient following the filter coefficients has an offset of zero.
+fn coefficient_taps(coeffs: &[i8], ar_coeff_lag: u8, ar_coeff_shift: u8) -> Vec<Tap> {
+    let lag = isize::from(ar_coeff_lag);
+    (-lag..=0)
+        .flat_map(|dy| (-lag..=lag).map(move |dx| (dy, dx)))
+        .take_while(|&offset| offset != (0, 0))
+        .chain([(0, 0)])
+        .zip(coeffs)
+        .filter(|&(_, &coeff)| coeff != 0)
+        .map(|((dy, dx), &coeff)| Tap {
+            dy,
+            dx,
+            value: f64::from(coeff) / f64::from(1u32 << ar_coeff_shift),
+        })
+        .collect()
+}
+
+/// Returns the standard deviation of unscaled 8-bit AV1 grain after
+/// auto-regressive filtering with `taps`, ignoring the luma contribution
+/// to chroma grain.
+fn grain_std(taps: &[Tap], grain_scale_shift: u8) -> f64 {
+    let sequence_variance = GAUSSIAN_SEQUENCE
+        .iter()
+        .map(|&value| f64::from(value).powi(2))
+        .sum::<f64>()
+        / GAUSSIAN_SEQUENCE.len() as f64;
+    let shift = f64::from(1u32 << (4 + grain_scale_shift));
+    (sequence_variance * variance_gain(taps)).sqrt() / shift
+}
+
+/// Estimates the factor by which filtering white noise with `taps`
+/// increases its variance, from the energy of the impulse response.
+fn variance_gain(taps: &[Tap]) -> f64 {
+    const ROWS: usize = 48;
+    const COLS: usize = 96;
+
+    let mut response = vec![0.; ROWS * COLS];
+    for y in 0..ROWS {
+        for x in 0..COLS {
+            let mut value = if (y, x) == (0, COLS / 2) { 1. } else { 0. };
+            for tap in taps.iter().filter(|tap| (tap.dy, tap.dx) != (0, 0)) {
+                if let (Some(ty), Some(tx)) =
+                    (y.checked_add_signed(tap.dy), x.checked_add_signed(tap.dx))
+                    && tx < COLS
+                {
+                    value += tap.value * response.get(ty * COLS + tx).copied().unwrap_or_default();
+                }
+            }
+            if let Some(sample) = response.get_mut(y * COLS + x) {
+                *sample = value;
+            }
+        }
+    }
+    response.iter().map(|value| value * value).sum()
+}
+
+/// Builds scaling points from sorted `(lower_bound, upper_bound, scaling)`
+/// intervals, using both bounds of every interval if there is room, and
+/// otherwise the midpoints of the intervals.
+fn intervals_to_points(
+    intervals: &[(u8, u8, u8)],
+    limit: usize,
+    plane: usize,
+    losses: &mut Vec<ConversionLoss>,
+) -> ArrayVec<[u8; 2], NUM_Y_POINTS> {
+    let mut points = ArrayVec::<[u8; 2], NUM_Y_POINTS>::new();
+    if intervals.is_empty() {
+        return points;
+    }
+
+    let mut expected_lower_bound = Some(0u8);
+    for &(lower_bound, upper_bound, _) in intervals {
+        if Some(lower_bound) != expected_lower_bound {
+            losses.push(ConversionLoss::UncoveredIntensities { plane });
+        }
+        expected_lower_bound = upper_bound.checked_add(1);
+    }
+    if expected_lower_bound.is_some() {
+        losses.push(ConversionLoss::UncoveredIntensities { plane });
+    }
+
+    if intervals.len() * 2 <= limit {
+        for &(lower_bound, upper_bound, scaling) in intervals {
+            for value in [lower_bound, upper_bound] {
+                if points.last().is_none_or(|&[previous, _]| value > previous) {
+                    points.push([value, scaling]);
+                }
+            }
+        }
+    } else {
+        losses.push(ConversionLoss::IntervalsApproximated { plane });
+        let count = intervals.len().min(limit);
+        for i in 0..count {
+            let index = (i * (intervals.len() - 1))
+                .checked_div(count - 1)
+                .unwrap_or_default();
+            if let Some(&(lower_bound, upper_bound, scaling)) = intervals.get(index) {
+                points.push([lower_bound.midpoint(upper_bound), scaling]);
+            }
+        }
+    }
+    points
+}
+
+/// Splits the intensity range into `(lower_bound, upper_bound, scaling)`
+/// intervals around each scaling point, with bounds halfway between
+/// consecutive points, merging neighbouring intervals of equal scaling.
+fn points_to_intervals(points: &[[u8; 2]]) -> Result<Vec<(u8, u8, u8)>> {
+    let mut intervals: Vec<(u8, u8, u8)> = Vec::with_capacity(points.len());
+    let mut lower_bound = 0;
+    for (i, &[value, scaling]) in points.iter().enumerate() {
+        let upper_bound = match points.get(i + 1) {
+            Some(&[next, _]) => {
+                ensure!(next > value, "Scaling points must be in increasing order");
+                value.midpoint(next)
+            }
+            None => 255,
+        };
+        match intervals.last_mut() {
+            Some(last) if last.2 == scaling => last.1 = upper_bound,
+            _ => intervals.push((lower_bound, upper_bound, scaling)),
+        }
+        lower_bound = upper_bound.saturating_add(1);
+    }
+    Ok(intervals)
+}
+
+/// Whether the scaling function interpolates between points of different
+/// scaling, which constant intervals cannot represent.
+fn has_scaling_ramps(points: &[[u8; 2]]) -> bool {
+    points.windows(2).any(|pair| {
+        let [[a, scaling_a], [b, scaling_b]] = pair else {
+            return false;
+        };
+        scaling_a != scaling_b && b - a > 1
+    })
+}
+
+/// The chroma multipliers and offset which index the scaling function by
+/// the chroma intensity alone, or zeros for a plane without scaling points.
+fn identity_combination<T>(points: &[T]) -> (u8, u8, u16) {
+    if points.is_empty() {
+        (0, 0, 0)
+    } else {
+        (192, 128, 256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(lower_bound: u8, upper_bound: u8, model_values: &[i32]) -> IntensityInterval {
+        IntensityInterval {
+            lower_bound,
+            upper_bound,
+            model_values: model_values.iter().copied().collect(),
+        }
+    }
+
+    fn characteristics(model: FilmGrainModel) -> FilmGrainCharacteristics {
+        FilmGrainCharacteristics {
+            model,
+            bit_depth: 8,
+            blending_mode: BlendingMode::Additive,
+            log2_scale_factor: 7,
+            components: [
+                vec![
+                    interval(0, 127, &[3 * 128, 32]),
+                    interval(128, 255, &[6 * 128, 32]),
+                ],
+                vec![interval(0, 255, &[2 * 128, 0, 16])],
+                Vec::new(),
+            ],
+        }
+    }
+
+    #[test]
+    fn auto_regression_round_trip() {
+        let fgc = characteristics(FilmGrainModel::AutoRegression);
+        let (segment, losses) = h274_to_segment(&fgc).expect("valid characteristics");
+        assert_eq!(losses, []);
+        assert_eq!(segment.ar_coeff_lag, 1);
+        assert_eq!(segment.ar_coeff_shift, 7);
+        assert_eq!(segment.ar_coeffs_y.as_slice(), [0, 32, 0, 32]);
+        assert_eq!(segment.ar_coeffs_cb.as_slice(), [0, 0, 0, 0, 16]);
+        assert_eq!(segment.ar_coeffs_cr.as_slice(), [0, 0, 0, 0, 0]);
+        assert_eq!(
+            segment
+                .scaling_points_y
+                .iter()
+                .map(|&[value, _]| value)
+                .collect::<Vec<_>>(),
+            [0, 127, 128, 255]
+        );
+        assert!(segment.scaling_points_cr.is_empty());
+        assert_eq!(
+            (segment.cb_mult, segment.cb_luma_mult, segment.cb_offset),
+            (192, 128, 256)
+        );
+
+        let (back, losses) =
+            segment_to_h274(&segment, FilmGrainModel::AutoRegression).expect("valid segment");
+        assert_eq!(losses, []);
+        let [luma, cb, cr] = &back.components;
+        assert!(cr.is_empty());
+        assert_eq!(luma.len(), 2);
+        for (interval, expected_std) in luma.iter().zip([3, 6]) {
+            let [std_dev, correlation] = interval.model_values.as_slice() else {
+                panic!("unexpected model values {:?}", interval.model_values);
+            };
+            assert!((std_dev - expected_std * 128).abs() <= 3);
+            assert_eq!(*correlation, 32);
+        }
+        let [chroma] = cb.as_slice() else {
+            panic!("expected one Cb interval, got {cb:?}");
+        };
+        assert_eq!((chroma.lower_bound, chroma.upper_bound), (0, 255));
+        assert_eq!(chroma.model_values.get(1..), Some([0, 16].as_slice()));
+    }
+
+    #[test]
+    fn frequency_filtering_becomes_separable_filter() {
+        let fgc = FilmGrainCharacteristics {
+            blending_mode: BlendingMode::Multiplicative,
+            components: [
+                vec![interval(16, 235, &[4 * 128, 8, 12, 2])],
+                Vec::new(),
+                Vec::new(),
+            ],
+            ..characteristics(FilmGrainModel::FrequencyFiltering)
+        };
+        let (segment, losses) = h274_to_segment(&fgc).expect("valid characteristics");
+        assert_eq!(
+            losses,
+            [
+                ConversionLoss::MultiplicativeBlending,
+                ConversionLoss::LowCutoffsIgnored { plane: 0 },
+                ConversionLoss::UncoveredIntensities { plane: 0 },
+            ]
+        );
+        // Coefficients of 1/2 horizontally and 1/4 vertically
+        assert_eq!(segment.ar_coeffs_y.as_slice(), [-16, 32, 0, 64]);
+        assert!(segment.scaling_points_cb.is_empty());
+
+        let (back, _) =
+            segment_to_h274(&segment, FilmGrainModel::FrequencyFiltering).expect("valid segment");
+        let [luma, _, _] = &back.components;
+        let [interval] = luma.as_slice() else {
+            panic!("expected one luma interval, got {luma:?}");
+        };
+        assert_eq!(interval.model_values.get(1..), Some([8, 12].as_slice()));
+    }
+
+    #[test]
+    fn reports_unrepresentable_segment_features() {
+        let (segment, _) = h274_to_segment(&characteristics(FilmGrainModel::AutoRegression))
+            .expect("valid characteristics");
+        let segment = GrainTableSegment {
+            ar_coeffs_y: ArrayVec::from_iter([0, 32, 5, 20]),
+            chroma_scaling_from_luma: true,
+            overlap_flag: false,
+            random_seed: 1,
+            clip_to_restricted_range: true,
+            ..segment
+        };
+        let (fgc, losses) =
+            segment_to_h274(&segment, FilmGrainModel::FrequencyFiltering).expect("valid segment");
+        assert_eq!(
+            losses,
+            [
+                ConversionLoss::UnrepresentableCoefficients { plane: 0 },
+                ConversionLoss::ChromaScaledFromLuma,
+                ConversionLoss::RandomSeed,
+                ConversionLoss::NoOverlap,
+                ConversionLoss::RestrictedRange,
+            ]
+        );
+        let [luma, cb, cr] = &fgc.components;
+        assert_eq!(luma.len(), cb.len());
+        assert_eq!(luma.len(), cr.len());
+    }
+
+    #[test]
+    fn rejects_invalid_characteristics() {
+        let mut fgc = characteristics(FilmGrainModel::AutoRegression);
+        fgc.components[0].push(interval(100, 140, &[128]));
+        assert!(h274_to_segment(&fgc).is_err());
+
+        let mut fgc = characteristics(FilmGrainModel::AutoRegression);
+        fgc.components[1] = vec![interval(0, 255, &[])];
+        assert!(h274_to_segment(&fgc).is_err());
+
+        let fgc = FilmGrainCharacteristics {
+            bit_depth: 7,
+            ..characteristics(FilmGrainModel::AutoRegression)
+        };
+        assert!(h274_to_segment(&fgc).is_err());
+    }
+}
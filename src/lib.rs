@@ -9,27 +9,62 @@
 
 #![warn(clippy::indexing_slicing, reason = "use get_unchecked instead")]
 
+#[cfg(feature = "bitstream")]
+mod bitstream;
+#[cfg(feature = "capi")]
+mod capi;
 #[cfg(feature = "create")]
 mod create;
+#[cfg(feature = "dav1d")]
+mod dav1d;
 #[cfg(feature = "diff")]
 mod diff;
+#[cfg(feature = "serialize")]
+mod document;
+mod error;
 #[cfg(all(feature = "estimate", feature = "unstable"))]
 mod estimate;
+#[cfg(feature = "ffmpeg")]
+mod ffmpeg;
+#[cfg(feature = "h274")]
+mod h274;
 #[cfg(feature = "parse")]
 mod parse;
+mod presets;
+pub mod random;
+#[cfg(feature = "synthesize")]
+mod synthesize;
+mod table;
 mod util;
+mod validate;
 
 use arrayvec::ArrayVec;
+#[cfg(feature = "bitstream")]
+pub use bitstream::*;
 #[cfg(feature = "create")]
 pub use create::*;
+#[cfg(feature = "dav1d")]
+pub use dav1d::*;
 #[cfg(feature = "diff")]
 pub use diff::*;
+#[cfg(feature = "serialize")]
+pub use document::*;
+pub use error::*;
 #[cfg(all(feature = "estimate", feature = "unstable"))]
 pub use estimate::*;
+#[cfg(feature = "ffmpeg")]
+pub use ffmpeg::*;
+#[cfg(feature = "h274")]
+pub use h274::*;
 #[cfg(feature = "parse")]
 pub use parse::*;
-#[cfg(any(feature = "diff", feature = "estimate"))]
+pub use presets::*;
+#[cfg(feature = "synthesize")]
+pub use synthesize::*;
+pub use table::*;
+#[cfg(any(feature = "diff", feature = "estimate", feature = "synthesize"))]
 pub use v_frame;
+pub use validate::*;
 
 /// The max number of luma scaling points for grain synthesis
 pub const NUM_Y_POINTS: usize = 14;
@@ -49,6 +84,10 @@ pub type ScalingPoints = ArrayVec<[u8; 2], NUM_Y_POINTS>;
 
 /// Specifies parameters for enabling decoder-side grain synthesis for
 /// a segment of video from `start_time` to `end_time`.
+///
+/// Its serde representation mirrors these fields, and may change when fields
+/// are added. `GrainDocument` is a stable, versioned format for storing
+/// grain tables.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serialize", derive(serde::Deserialize, serde::Serialize))]
 pub struct GrainTableSegment {
@@ -59,6 +98,10 @@ pub struct GrainTableSegment {
     pub end_time: u64,
 
     /// Values for the cutoffs and scale factors for luma scaling points
+    ///
+    /// The cutoffs of all scaling points are in 8-bit units, regardless of
+    /// the bit depth of the video. For higher bit depths, the scaling function
+    /// is linearly interpolated between the 8-bit values.
     pub scaling_points_y: ArrayVec<[u8; 2], NUM_Y_POINTS>,
     /// Values for the cutoffs and scale factors for Cb scaling points
     pub scaling_points_cb: ArrayVec<[u8; 2], NUM_UV_POINTS>,
@@ -113,4 +156,71 @@ pub struct GrainTableSegment {
     pub grain_scale_shift: u8,
     /// Random seed used for generating grain
     pub random_seed: u16,
+
+    /// Whether grain is applied during this segment. Segments with this unset
+    /// mark intervals without film grain, and their other parameters are
+    /// ignored.
+    #[cfg_attr(feature = "serialize", serde(default = "default_true"))]
+    pub apply_grain: bool,
+    /// Whether this segment signals new parameters, which is the
+    /// `dynamic-grain` field of grain tables. If unset, only the random seed
+    /// is updated and the parameters of the previous segment are reused.
+    #[cfg_attr(feature = "serialize", serde(default = "default_true"))]
+    pub update_grain: bool,
+    /// The reference frame whose parameters are reused when `update_grain`
+    /// is unset, as signalled by `film_grain_params_ref_idx` in the AV1
+    /// frame header. Grain tables do not store it.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub film_grain_params_ref_idx: Option<u8>,
+    /// Whether to clip the samples to the studio range after adding grain,
+    /// which is intended for limited range video.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub clip_to_restricted_range: bool,
+    /// Comments attached to this segment in a grain table, without their
+    /// leading `#`. They are kept by parsing and written by the annotated
+    /// writer, and have no effect on the grain.
+    #[cfg_attr(feature = "serialize", serde(default))]
+    pub comments: Vec<String>,
+}
+
+impl Default for GrainTableSegment {
+    /// Returns a segment covering no time without any scaling points, so
+    /// that no grain is added, and with the other parameters set to their
+    /// smallest valid values.
+    #[inline]
+    fn default() -> Self {
+        Self {
+            start_time: 0,
+            end_time: 0,
+            scaling_points_y: ArrayVec::new(),
+            scaling_points_cb: ArrayVec::new(),
+            scaling_points_cr: ArrayVec::new(),
+            scaling_shift: 8,
+            ar_coeff_lag: 0,
+            ar_coeffs_y: ArrayVec::new(),
+            ar_coeffs_cb: ArrayVec::from_iter([0]),
+            ar_coeffs_cr: ArrayVec::from_iter([0]),
+            ar_coeff_shift: 6,
+            cb_mult: 0,
+            cb_luma_mult: 0,
+            cb_offset: 0,
+            cr_mult: 0,
+            cr_luma_mult: 0,
+            cr_offset: 0,
+            overlap_flag: false,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: DEFAULT_GRAIN_SEED,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: false,
+            comments: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+const fn default_true() -> bool {
+    true
 }
@@ -7,9 +7,17 @@
 // Media Patent License 1.0 was not distributed with this source code in the
 // PATENTS file, you can obtain it at www.aomedia.org/license/patent.
 
+use std::{
+    fmt, io::BufRead, iter::FusedIterator, mem, num::ParseIntError, ops::RangeInclusive,
+    str::FromStr,
+};
+
 use arrayvec::ArrayVec;
 
-use crate::{GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y_POINTS};
+use crate::{
+    Error, GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y_POINTS, Severity,
+    Violation, util::line_annotation,
+};
 
 /// This file has the implementation details of the grain table.
 ///
@@ -17,8 +25,17 @@ use crate::{GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y
 /// editability. Array parameters are separated from the non-array
 /// parameters and prefixed with a few characters to make for easy
 /// localization with a parameter set. Each entry is prefixed with "E"
-/// and the other parameters are only specified if "apply-grain" is
-/// non-zero.
+/// and the other parameters are only specified if "dynamic-grain" is
+/// non-zero. Otherwise, the parameters of the previous entry are reused.
+/// Parameters given to entries with "dynamic-grain" unset are also
+/// accepted, as are entries with "apply-grain" unset which omit them.
+/// The "p" line may end with an additional clip_to_restricted_range value,
+/// as written by annotated tables, which is assumed to be unset otherwise.
+///
+/// Everything after a "#" on a line is a comment. Comments are kept in the
+/// `comments` of the segment they precede or are part of, except for those
+/// after the last segment, which are discarded. Comments labelling the
+/// values of a line, as written by annotated tables, are not kept.
 ///
 /// ```text
 /// filmgrn1
@@ -37,140 +54,378 @@ use crate::{GrainTableSegment, NUM_UV_COEFFS, NUM_UV_POINTS, NUM_Y_COEFFS, NUM_Y
 ///
 /// - If the file cannot be opened
 /// - If the file does not contain a properly formatted film grain table
+/// - If a segment has a violation of [`Severity::Error`], as reported by
+///   [`validate_grain_table`](crate::validate_grain_table) without a chroma
+///   format
 #[inline]
-pub fn parse_grain_table(input: &str) -> anyhow::Result<Vec<GrainTableSegment>> {
-    let lines = non_empty_lines(input);
-    let Some((header, body)) = lines.split_first() else {
-        anyhow::bail!("Expected filmgrn1 header");
-    };
+pub fn parse_grain_table(input: &str) -> Result<Vec<GrainTableSegment>, Error> {
+    GrainTableReader::new(input.as_bytes()).collect()
+}
+
+/// Parses a grain table from a reader, in the format described in
+/// [`parse_grain_table`].
+///
+/// # Errors
+///
+/// - If the input cannot be read or is not valid UTF-8
+/// - If the input does not contain a properly formatted film grain table
+#[inline]
+pub fn parse_grain_table_from<R: BufRead>(reader: R) -> Result<Vec<GrainTableSegment>, Error> {
+    GrainTableReader::new(reader).collect()
+}
+
+/// Parses a grain table like [`parse_grain_table`], but repairs the
+/// problems it safely can instead of failing, returning the segments along
+/// with a warning for each repair.
+///
+/// Scaling points are sorted, deduplicated and truncated to the maximum
+/// count, coefficients are padded with zeros or trimmed to the count implied
+/// by `ar_coeff_lag`, out of range shifts and offsets are clamped, and
+/// unexpected lines between segments are skipped.
+///
+/// # Errors
+///
+/// - If the input does not contain a film grain table, or contains values
+///   which are not integers
+/// - If a segment has a violation of [`Severity::Error`] which cannot be
+///   repaired, such as overlapping the previous segment
+#[inline]
+pub fn parse_grain_table_lenient(
+    input: &str,
+) -> Result<(Vec<GrainTableSegment>, Vec<ParseWarning>), Error> {
+    parse_grain_table_lenient_from(input.as_bytes())
+}
 
-    let (header_tag, header_values) = header.split()?;
-    anyhow::ensure!(
-        header_tag == "filmgrn1" && header_values.is_empty(),
-        "line {}: expected filmgrn1 header",
-        header.line_no
-    );
+/// Parses a grain table from a reader like [`parse_grain_table_lenient`].
+///
+/// # Errors
+///
+/// - If the input cannot be read or is not valid UTF-8
+/// - If the input does not contain a film grain table which can be
+///   repaired, as described in [`parse_grain_table_lenient`]
+#[inline]
+pub fn parse_grain_table_lenient_from<R: BufRead>(
+    reader: R,
+) -> Result<(Vec<GrainTableSegment>, Vec<ParseWarning>), Error> {
+    let mut reader = GrainTableReader::with_mode(reader, ParseMode::Lenient);
+    let segments = reader.by_ref().collect::<Result<_, _>>()?;
+    Ok((segments, reader.take_warnings()))
+}
 
-    parse_segments(body)
+/// How a [`GrainTableReader`] handles problems in a table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first problem.
+    #[default]
+    Strict,
+    /// Repair the problems which can be repaired safely, and record a
+    /// [`ParseWarning`] for each of them.
+    Lenient,
 }
 
+/// A problem found while parsing a table in [`ParseMode::Lenient`], which
+/// was repaired instead of failing.
 #[derive(Debug)]
-struct LineFields<'a> {
-    line_no: usize,
-    fields: Vec<&'a str>,
+pub struct ParseWarning {
+    /// The error strict parsing would have returned, with its line number
+    pub error: Error,
+    /// How the problem was repaired
+    pub repair: Repair,
 }
 
-impl<'a> LineFields<'a> {
-    fn split(&self) -> anyhow::Result<(&'a str, &[&'a str])> {
-        self.fields
-            .split_first()
-            .map(|(tag, values)| (*tag, values))
-            .ok_or_else(|| anyhow::anyhow!("line {}: expected a non-empty line", self.line_no))
+/// How a problem found by lenient parsing was repaired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// Values after the last which could be used were dropped.
+    Truncated,
+    /// Scaling points were sorted, and points repeating an x value dropped.
+    Sorted,
+    /// Coefficients were padded with zeros or trimmed to the expected count.
+    Resized,
+    /// A value was clamped to the nearest value in its range.
+    Clamped,
+    /// A line was skipped.
+    Skipped,
+}
+
+impl fmt::Display for ParseWarning {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let repair = match self.repair {
+            Repair::Truncated => "truncated",
+            Repair::Sorted => "sorted",
+            Repair::Resized => "resized",
+            Repair::Clamped => "clamped",
+            Repair::Skipped => "skipped",
+        };
+        write!(f, "{} ({repair})", self.error)
     }
 }
 
-fn non_empty_lines(input: &str) -> Vec<LineFields<'_>> {
-    input
-        .lines()
-        .enumerate()
-        .filter_map(|(index, line)| {
-            let fields: Vec<_> = line.split_whitespace().collect();
-            (!fields.is_empty()).then_some(LineFields {
-                line_no: index + 1,
-                fields,
-            })
-        })
-        .collect()
+/// Where lenient parsing records the problems it repairs. Strict parsing
+/// has nowhere to record them, and fails instead.
+type Warnings<'a> = Option<&'a mut Vec<ParseWarning>>;
+
+/// Records `error` as a warning in lenient mode, or returns it otherwise.
+fn repair(warnings: &mut Warnings<'_>, error: Error, repair: Repair) -> Result<(), Error> {
+    let Some(warnings) = warnings else {
+        return Err(error);
+    };
+    warnings.push(ParseWarning { error, repair });
+    Ok(())
 }
 
-fn expect_tag<'a>(line: &'a LineFields<'_>, tag: &str) -> anyhow::Result<&'a [&'a str]> {
-    let (actual, values) = line.split()?;
-    anyhow::ensure!(
-        actual == tag,
-        "line {}: expected {tag} line, got {actual}",
-        line.line_no
-    );
-    Ok(values)
+/// An iterator parsing the segments of a grain table from a reader one at a
+/// time, in the format described in [`parse_grain_table`].
+///
+/// Only the line being parsed and the previous segment are held in memory,
+/// so tables with any number of segments can be processed without loading
+/// them fully.
+///
+/// The iterator ends after the first error, which includes the number of the
+/// line it was found on.
+#[derive(Debug)]
+pub struct GrainTableReader<R> {
+    reader: R,
+    line: String,
+    line_no: usize,
+    /// Whether `line` was peeked at, and should be returned again
+    pushed_back: bool,
+    state: ReaderState,
+    /// The parameters reused by segments with `dynamic-grain` unset
+    previous: Option<GrainTableSegment>,
+    /// The warnings of lenient parsing, or `None` when parsing strictly
+    warnings: Option<Vec<ParseWarning>>,
+    /// The comments read since the last segment
+    comments: Vec<String>,
 }
 
-fn parse_values<T>(line: &LineFields<'_>, values: &[&str], label: &str) -> anyhow::Result<Vec<T>>
-where
-    T: std::str::FromStr,
-    T::Err: std::fmt::Display,
-{
-    values
-        .iter()
-        .map(|value| {
-            value.parse::<T>().map_err(|error| {
-                anyhow::anyhow!(
-                    "line {}: failed to parse {label} value `{value}`: {error}",
-                    line.line_no
-                )
-            })
-        })
-        .collect()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReaderState {
+    Header,
+    FirstSegment,
+    Segments,
+    Done,
 }
 
-fn parse_segments(lines: &[LineFields<'_>]) -> anyhow::Result<Vec<GrainTableSegment>> {
-    let mut index = 0;
-    let mut saw_segment = false;
-    let mut segments = Vec::new();
+impl<R: BufRead> GrainTableReader<R> {
+    /// Creates an iterator over the segments of the table in `reader`,
+    /// parsed in [`ParseMode::Strict`].
+    #[inline]
+    pub const fn new(reader: R) -> Self {
+        Self::with_mode(reader, ParseMode::Strict)
+    }
+
+    /// Creates an iterator over the segments of the table in `reader`,
+    /// parsed in `mode`.
+    #[inline]
+    pub const fn with_mode(reader: R, mode: ParseMode) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            line_no: 0,
+            pushed_back: false,
+            state: ReaderState::Header,
+            previous: None,
+            warnings: match mode {
+                ParseMode::Strict => None,
+                ParseMode::Lenient => Some(Vec::new()),
+            },
+            comments: Vec::new(),
+        }
+    }
+
+    /// Returns the warnings recorded since the last call, for the segments
+    /// which were already returned. This is always empty in
+    /// [`ParseMode::Strict`].
+    #[must_use]
+    #[inline]
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        self.warnings.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Reads the next non-empty line, or returns `None` at the end of input.
+    fn next_line(&mut self) -> Result<Option<LineFields<'_>>, Error> {
+        if self.pushed_back {
+            self.pushed_back = false;
+        } else {
+            loop {
+                self.line.clear();
+                let read = self.reader.read_line(&mut self.line)?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                self.line_no += 1;
 
-    while let Some(line) = lines.get(index) {
-        let (tag, _) = line.split()?;
-        if tag != "E" {
-            if saw_segment {
-                break;
+                let (content, comment) = split_comment(&self.line);
+                let fields: Vec<_> = content.split_whitespace().collect();
+                if let Some(comment) = comment {
+                    // Annotations are regenerated when the table is written,
+                    // so keeping them would repeat them.
+                    let annotation = fields
+                        .split_first()
+                        .and_then(|(tag, values)| line_annotation(tag, values));
+                    if annotation.as_deref() != Some(comment) {
+                        self.comments.push(comment.to_owned());
+                    }
+                }
+                if !fields.is_empty() {
+                    break;
+                }
             }
-            anyhow::bail!("line {}: expected E line, got {tag}", line.line_no);
         }
 
-        saw_segment = true;
-        let e_params = e_params(line)?;
-        index += 1;
+        Ok(Some(LineFields {
+            line_no: self.line_no,
+            fields: split_comment(&self.line).0.split_whitespace().collect(),
+        }))
+    }
+
+    /// Whether the next non-empty line has the tag `tag`, without consuming it.
+    fn peek_tag(&mut self, tag: &str) -> Result<bool, Error> {
+        let Some(line) = self.next_line()? else {
+            return Ok(false);
+        };
+        let matches = line.split().0 == tag;
+        self.pushed_back = true;
+        Ok(matches)
+    }
 
-        if !e_params.apply {
-            continue;
+    fn required_line(&mut self, expected_tag: &'static str) -> Result<LineFields<'_>, Error> {
+        let Some(line) = self.next_line()? else {
+            return Err(Error::UnexpectedEnd {
+                expected: expected_tag,
+            });
+        };
+        expect_tag(&line, expected_tag)?;
+        Ok(line)
+    }
+
+    fn read_header(&mut self) -> Result<(), Error> {
+        let Some(header) = self.next_line()? else {
+            return Err(Error::MalformedHeader { line: None });
+        };
+
+        let (header_tag, header_values) = header.split();
+        if header_tag != "filmgrn1" || !header_values.is_empty() {
+            return Err(Error::MalformedHeader {
+                line: Some(header.line_no),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the next segment, or returns `None` at the end of the table.
+    fn read_segment(
+        &mut self,
+        warnings: &mut Warnings<'_>,
+    ) -> Result<Option<GrainTableSegment>, Error> {
+        if self.state == ReaderState::Header {
+            self.read_header()?;
+            self.state = ReaderState::FirstSegment;
+        }
+
+        let first = self.state == ReaderState::FirstSegment;
+        let line = loop {
+            let Some(line) = self.next_line()? else {
+                if first {
+                    return Err(Error::UnexpectedEnd { expected: "E" });
+                }
+                return Ok(None);
+            };
+            let (tag, _) = line.split();
+            if first || tag == "E" {
+                break line;
+            }
+            // Strict parsing ends the table at the first line which does
+            // not start a segment.
+            let error = Error::UnexpectedTag {
+                line: line.line_no,
+                expected: "E",
+                actual: tag.to_owned(),
+            };
+            if repair(warnings, error, Repair::Skipped).is_err() {
+                return Ok(None);
+            }
+        };
+        let e_params = e_params(&line)?;
+        let line_no = line.line_no;
+        self.state = ReaderState::Segments;
+
+        let previous_end = self.previous.as_ref().map(|previous| previous.end_time);
+        let comment_count = self.comments.len();
+        let mut next_comments = Vec::new();
+        let params = if (e_params.apply && e_params.update) || self.peek_tag("p")? {
+            self.read_params(e_params.apply, warnings)?
+        } else {
+            // The comments before the line which was peeked at belong to the
+            // next segment.
+            next_comments = self.comments.split_off(comment_count);
+            if e_params.update {
+                GrainTableSegment::default()
+            } else {
+                self.previous.take().unwrap_or_default()
+            }
+        };
+        let segment = GrainTableSegment {
+            start_time: e_params.start,
+            end_time: e_params.end,
+            random_seed: e_params.seed,
+            apply_grain: e_params.apply,
+            update_grain: e_params.update,
+            comments: mem::replace(&mut self.comments, next_comments),
+            ..params
+        };
+        let overlaps = previous_end.is_some_and(|end| segment.start_time < end);
+        if let Some(violation) = overlaps
+            .then_some(Violation::OverlapsPrevious)
+            .into_iter()
+            .chain(segment.validate(None))
+            .find(|violation| violation.severity() == Severity::Error)
+        {
+            return Err(Error::InvalidSegment {
+                line: line_no,
+                violation,
+            });
         }
+        self.previous = Some(segment.clone());
+        Ok(Some(segment))
+    }
 
-        let p_params = p_params(required_line(lines, index, "p")?)?;
-        index += 1;
-        let s_y_params =
-            s_params::<NUM_Y_POINTS>(required_line(lines, index, "sY")?, "sY", "Y-plane")?;
-        index += 1;
+    /// Reads the parameter lines following an E line. Their values are only
+    /// checked against the ranges allowed by AV1 if the segment applies
+    /// grain, as tables may leave them zeroed otherwise.
+    fn read_params(
+        &mut self,
+        apply: bool,
+        warnings: &mut Warnings<'_>,
+    ) -> Result<GrainTableSegment, Error> {
+        let p_params = p_params(&self.required_line("p")?, apply, warnings)?;
+        let s_y_params = s_params::<NUM_Y_POINTS>(&self.required_line("sY")?, "sY", 0, warnings)?;
         let s_cb_params =
-            s_params::<NUM_UV_POINTS>(required_line(lines, index, "sCb")?, "sCb", "Cb-plane")?;
-        index += 1;
+            s_params::<NUM_UV_POINTS>(&self.required_line("sCb")?, "sCb", 1, warnings)?;
         let s_cr_params =
-            s_params::<NUM_UV_POINTS>(required_line(lines, index, "sCr")?, "sCr", "Cr-plane")?;
-        index += 1;
+            s_params::<NUM_UV_POINTS>(&self.required_line("sCr")?, "sCr", 2, warnings)?;
 
         let coeff_count = (2 * p_params.ar_coeff_lag * (p_params.ar_coeff_lag + 1)) as usize;
-        let c_y_params = c_params::<NUM_Y_COEFFS>(
-            required_line(lines, index, "cY")?,
-            "cY",
-            "Y-plane",
-            coeff_count,
-        )?;
-        index += 1;
+        let c_y_params =
+            c_params::<NUM_Y_COEFFS>(&self.required_line("cY")?, "cY", 0, coeff_count, warnings)?;
         let c_cb_params = c_params::<NUM_UV_COEFFS>(
-            required_line(lines, index, "cCb")?,
+            &self.required_line("cCb")?,
             "cCb",
-            "Cb-plane",
+            1,
             coeff_count + 1,
+            warnings,
         )?;
-        index += 1;
         let c_cr_params = c_params::<NUM_UV_COEFFS>(
-            required_line(lines, index, "cCr")?,
+            &self.required_line("cCr")?,
             "cCr",
-            "Cr-plane",
+            2,
             coeff_count + 1,
+            warnings,
         )?;
-        index += 1;
 
-        segments.push(GrainTableSegment {
-            start_time: e_params.start,
-            end_time: e_params.end,
+        Ok(GrainTableSegment {
             scaling_points_y: s_y_params,
             scaling_points_cb: s_cb_params,
             scaling_points_cr: s_cr_params,
@@ -189,29 +444,90 @@ fn parse_segments(lines: &[LineFields<'_>]) -> anyhow::Result<Vec<GrainTableSegm
             overlap_flag: p_params.overlap_flag,
             chroma_scaling_from_luma: p_params.chroma_scaling_from_luma,
             grain_scale_shift: p_params.grain_scale_shift,
-            random_seed: e_params.seed,
+            clip_to_restricted_range: p_params.clip_to_restricted_range,
+            ..GrainTableSegment::default()
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for GrainTableReader<R> {
+    type Item = Result<GrainTableSegment, Error>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == ReaderState::Done {
+            return None;
+        }
+
+        let mut warnings = self.warnings.take();
+        let result = self.read_segment(&mut warnings.as_mut());
+        self.warnings = warnings;
+        if !matches!(result, Ok(Some(_))) {
+            self.state = ReaderState::Done;
+        }
+        result.transpose()
+    }
+}
+
+impl<R: BufRead> FusedIterator for GrainTableReader<R> {}
+
+#[derive(Debug)]
+struct LineFields<'a> {
+    line_no: usize,
+    fields: Vec<&'a str>,
+}
+
+impl<'a> LineFields<'a> {
+    fn split(&self) -> (&'a str, &[&'a str]) {
+        self.fields
+            .split_first()
+            .map(|(tag, values)| (*tag, values))
+            .expect("empty lines are skipped")
+    }
+}
+
+/// Splits a line into its content and the text of its comment, if any.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.split_once('#') {
+        Some((content, comment)) => (content, Some(comment.trim())),
+        None => (line, None),
+    }
+}
+
+fn expect_tag<'a>(line: &'a LineFields<'_>, tag: &'static str) -> Result<&'a [&'a str], Error> {
+    let (actual, values) = line.split();
+    if actual != tag {
+        return Err(Error::UnexpectedTag {
+            line: line.line_no,
+            expected: tag,
+            actual: actual.to_owned(),
         });
     }
+    Ok(values)
+}
 
-    anyhow::ensure!(saw_segment, "Expected at least one grain table segment");
-    Ok(segments)
+fn parse_value<T: FromStr<Err = ParseIntError>>(
+    line: &LineFields<'_>,
+    field: &'static str,
+    value: &str,
+) -> Result<T, Error> {
+    value.parse().map_err(|source| Error::InvalidValue {
+        line: line.line_no,
+        field,
+        value: value.to_owned(),
+        source,
+    })
 }
 
-fn required_line<'a, 'input>(
-    lines: &'a [LineFields<'input>],
-    index: usize,
-    expected_tag: &str,
-) -> anyhow::Result<&'a LineFields<'input>> {
-    let Some(line) = lines.get(index) else {
-        anyhow::bail!("Expected {expected_tag} line, got end of input");
-    };
-    let (actual_tag, _) = line.split()?;
-    anyhow::ensure!(
-        actual_tag == expected_tag,
-        "line {}: expected {expected_tag} line, got {actual_tag}",
-        line.line_no
-    );
-    Ok(line)
+fn parse_values<T: FromStr<Err = ParseIntError>>(
+    line: &LineFields<'_>,
+    values: &[&str],
+    field: &'static str,
+) -> Result<Vec<T>, Error> {
+    values
+        .iter()
+        .map(|value| parse_value(line, field, value))
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -220,48 +536,36 @@ struct EParams {
     pub end: u64,
     pub apply: bool,
     pub seed: u16,
+    pub update: bool,
 }
 
-fn e_params(line: &LineFields<'_>) -> anyhow::Result<EParams> {
+fn e_params(line: &LineFields<'_>) -> Result<EParams, Error> {
     let values = expect_tag(line, "E")?;
-    let [start, end, apply, seed, _dynamic_grain] = values else {
-        anyhow::bail!(
-            "line {}: expected 5 values on E line, got {}",
-            line.line_no,
-            values.len()
-        );
+    let [start, end, apply, seed, update] = values else {
+        return Err(Error::ValueCount {
+            line: line.line_no,
+            tag: "E",
+            expected: 5..=5,
+            actual: values.len(),
+        });
     };
 
-    let start = start.parse::<u64>().map_err(|error| {
-        anyhow::anyhow!("line {}: failed to parse start_time: {error}", line.line_no)
-    })?;
-    let end = end.parse::<u64>().map_err(|error| {
-        anyhow::anyhow!("line {}: failed to parse end_time: {error}", line.line_no)
-    })?;
-    let apply = apply.parse::<u8>().map_err(|error| {
-        anyhow::anyhow!(
-            "line {}: failed to parse apply_grain: {error}",
-            line.line_no
-        )
-    })? > 0;
-    let seed = seed.parse::<u16>().map_err(|error| {
-        anyhow::anyhow!(
-            "line {}: failed to parse random_seed: {error}",
-            line.line_no
-        )
-    })?;
-
-    anyhow::ensure!(
-        end >= start,
-        "line {}: start time must be before end time",
-        line.line_no
-    );
+    let start = parse_value(line, "start_time", start)?;
+    let end = parse_value(line, "end_time", end)?;
+    let apply = parse_value::<u8>(line, "apply_grain", apply)? > 0;
+    let seed = parse_value(line, "random_seed", seed)?;
+    let update = parse_value::<u8>(line, "update_grain", update)? > 0;
+
+    if end < start {
+        return Err(Error::EndBeforeStart { line: line.line_no });
+    }
 
     Ok(EParams {
         start,
         end,
         apply,
         seed,
+        update,
     })
 }
 
@@ -279,11 +583,23 @@ struct PParams {
     cr_mult: u8,
     cr_luma_mult: u8,
     cr_offset: u16,
+    clip_to_restricted_range: bool,
 }
 
-#[allow(clippy::too_many_lines)]
-fn p_params(line: &LineFields<'_>) -> anyhow::Result<PParams> {
+fn p_params(
+    line: &LineFields<'_>,
+    check_ranges: bool,
+    warnings: &mut Warnings<'_>,
+) -> Result<PParams, Error> {
     let values = expect_tag(line, "p")?;
+    // clip_to_restricted_range is an optional extension, which tables
+    // written by libaom do not contain.
+    let (values, clip_to_restricted_range) = match values {
+        [values @ .., clip_to_restricted_range] if values.len() == 12 => {
+            (values, Some(*clip_to_restricted_range))
+        }
+        _ => (values, None),
+    };
     let [
         ar_coeff_lag,
         ar_coeff_shift,
@@ -299,156 +615,157 @@ fn p_params(line: &LineFields<'_>) -> anyhow::Result<PParams> {
         cr_offset,
     ] = values
     else {
-        anyhow::bail!(
-            "line {}: expected 12 values on p line, got {}",
-            line.line_no,
-            values.len()
-        );
+        return Err(Error::ValueCount {
+            line: line.line_no,
+            tag: "p",
+            expected: 12..=13,
+            actual: values.len() + usize::from(clip_to_restricted_range.is_some()),
+        });
     };
 
-    let params = PParams {
-        ar_coeff_lag: ar_coeff_lag.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse ar_coeff_lag: {error}",
-                line.line_no
-            )
-        })?,
-        ar_coeff_shift: ar_coeff_shift.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse ar_coeff_shift: {error}",
-                line.line_no
-            )
-        })?,
-        grain_scale_shift: grain_scale_shift.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse grain_scale_shift: {error}",
-                line.line_no
-            )
-        })?,
-        scaling_shift: scaling_shift.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse scaling_shift: {error}",
-                line.line_no
-            )
-        })?,
-        chroma_scaling_from_luma: chroma_scaling_from_luma.parse::<u8>().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse chroma_scaling_from_luma: {error}",
-                line.line_no
-            )
-        })? > 0,
-        overlap_flag: overlap_flag.parse::<u8>().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse overlap_flag: {error}",
-                line.line_no
-            )
-        })? > 0,
-        cb_mult: cb_mult.parse().map_err(|error| {
-            anyhow::anyhow!("line {}: failed to parse cb_mult: {error}", line.line_no)
-        })?,
-        cb_luma_mult: cb_luma_mult.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse cb_luma_mult: {error}",
-                line.line_no
-            )
-        })?,
-        cb_offset: cb_offset.parse().map_err(|error| {
-            anyhow::anyhow!("line {}: failed to parse cb_offset: {error}", line.line_no)
-        })?,
-        cr_mult: cr_mult.parse().map_err(|error| {
-            anyhow::anyhow!("line {}: failed to parse cr_mult: {error}", line.line_no)
-        })?,
-        cr_luma_mult: cr_luma_mult.parse().map_err(|error| {
-            anyhow::anyhow!(
-                "line {}: failed to parse cr_luma_mult: {error}",
-                line.line_no
-            )
-        })?,
-        cr_offset: cr_offset.parse().map_err(|error| {
-            anyhow::anyhow!("line {}: failed to parse cr_offset: {error}", line.line_no)
-        })?,
+    let mut params = PParams {
+        ar_coeff_lag: parse_value(line, "ar_coeff_lag", ar_coeff_lag)?,
+        ar_coeff_shift: parse_value(line, "ar_coeff_shift", ar_coeff_shift)?,
+        grain_scale_shift: parse_value(line, "grain_scale_shift", grain_scale_shift)?,
+        scaling_shift: parse_value(line, "scaling_shift", scaling_shift)?,
+        chroma_scaling_from_luma: parse_value::<u8>(
+            line,
+            "chroma_scaling_from_luma",
+            chroma_scaling_from_luma,
+        )? > 0,
+        overlap_flag: parse_value::<u8>(line, "overlap_flag", overlap_flag)? > 0,
+        cb_mult: parse_value(line, "cb_mult", cb_mult)?,
+        cb_luma_mult: parse_value(line, "cb_luma_mult", cb_luma_mult)?,
+        cb_offset: parse_value(line, "cb_offset", cb_offset)?,
+        cr_mult: parse_value(line, "cr_mult", cr_mult)?,
+        cr_luma_mult: parse_value(line, "cr_luma_mult", cr_luma_mult)?,
+        cr_offset: parse_value(line, "cr_offset", cr_offset)?,
+        clip_to_restricted_range: clip_to_restricted_range
+            .map(|value| parse_value::<u8>(line, "clip_to_restricted_range", value))
+            .transpose()?
+            .is_some_and(|clip_to_restricted_range| clip_to_restricted_range > 0),
     };
 
-    anyhow::ensure!(
-        (8..=11).contains(&params.scaling_shift),
-        "line {}: scaling_shift must be between 8 and 11",
-        line.line_no
-    );
-    anyhow::ensure!(
-        params.ar_coeff_lag <= 3,
-        "line {}: ar_coeff_lag must be between 0 and 3",
-        line.line_no
-    );
-    anyhow::ensure!(
-        (6..=9).contains(&params.ar_coeff_shift),
-        "line {}: ar_coeff_shift must be between 6 and 9",
-        line.line_no
-    );
+    let mut check = |field, value, range| check_range(line, field, value, range, warnings);
+    params.ar_coeff_lag = check("ar_coeff_lag", params.ar_coeff_lag.into(), 0..=3)? as u8;
+    if check_ranges {
+        params.scaling_shift = check("scaling_shift", params.scaling_shift.into(), 8..=11)? as u8;
+        params.ar_coeff_shift = check("ar_coeff_shift", params.ar_coeff_shift.into(), 6..=9)? as u8;
+        params.grain_scale_shift =
+            check("grain_scale_shift", params.grain_scale_shift.into(), 0..=3)? as u8;
+        params.cb_offset = check("cb_offset", params.cb_offset.into(), 0..=511)? as u16;
+        params.cr_offset = check("cr_offset", params.cr_offset.into(), 0..=511)? as u16;
+    }
 
     Ok(params)
 }
 
+/// Checks that `value` is in `range`, clamping it in lenient mode.
+fn check_range(
+    line: &LineFields<'_>,
+    field: &'static str,
+    value: u64,
+    range: RangeInclusive<u64>,
+    warnings: &mut Warnings<'_>,
+) -> Result<u64, Error> {
+    if range.contains(&value) {
+        return Ok(value);
+    }
+    let clamped = value.clamp(*range.start(), *range.end());
+    let error = Error::ValueOutOfRange {
+        line: line.line_no,
+        field,
+        value,
+        range,
+    };
+    repair(warnings, error, Repair::Clamped)?;
+    Ok(clamped)
+}
+
 fn s_params<const N: usize>(
     line: &LineFields<'_>,
-    tag: &str,
-    label: &str,
-) -> anyhow::Result<ArrayVec<[u8; 2], N>> {
+    tag: &'static str,
+    plane: usize,
+    warnings: &mut Warnings<'_>,
+) -> Result<ArrayVec<[u8; 2], N>, Error> {
     let values = expect_tag(line, tag)?;
-    let parsed = parse_values::<u8>(line, values, label)?;
-    let Some((count, points)) = parsed.split_first() else {
-        anyhow::bail!("line {}: expected {label} point count", line.line_no);
+    let parsed = parse_values::<u8>(line, values, tag)?;
+    let Some((&count, values)) = parsed.split_first() else {
+        return Err(Error::ValueCount {
+            line: line.line_no,
+            tag,
+            expected: 1..=1,
+            actual: 0,
+        });
     };
 
-    let count = *count as usize;
-    let expected_values = count * 2;
-    anyhow::ensure!(
-        points.len() == expected_values,
-        "line {}: expected {expected_values} {label} point values, got {}",
-        line.line_no,
-        points.len()
-    );
-    anyhow::ensure!(
-        count <= N,
-        "line {}: expected at most {N} {label} points, got {count}",
-        line.line_no
-    );
-
-    let mut output = ArrayVec::new();
-    for point in points.chunks_exact(2) {
-        let [x, y] = point else {
-            unreachable!("chunks_exact(2) only yields two-value chunks");
+    let count = usize::from(count);
+    let mut points: Vec<[u8; 2]> = values
+        .chunks_exact(2)
+        .map(|point| {
+            let [x, y] = point else {
+                unreachable!("chunks_exact(2) only yields two-value chunks");
+            };
+            [*x, *y]
+        })
+        .collect();
+    if values.len() != count * 2 {
+        let error = Error::ValueCount {
+            line: line.line_no,
+            tag,
+            expected: 1 + count * 2..=1 + count * 2,
+            actual: parsed.len(),
+        };
+        repair(warnings, error, Repair::Truncated)?;
+    }
+    points.truncate(count);
+
+    if warnings.is_some() && !points.is_sorted_by(|[x0, _], [x1, _]| x0 < x1) {
+        let error = Error::InvalidSegment {
+            line: line.line_no,
+            violation: Violation::UnorderedScalingPoints { plane },
+        };
+        repair(warnings, error, Repair::Sorted)?;
+        points.sort_by_key(|&[x, _]| x);
+        points.dedup_by_key(|&mut [x, _]| x);
+    }
+
+    if points.len() > N {
+        let error = Error::TooManyPoints {
+            line: line.line_no,
+            plane,
+            max: N,
+            actual: points.len(),
         };
-        output.push([*x, *y]);
+        repair(warnings, error, Repair::Truncated)?;
+        points.truncate(N);
     }
-    Ok(output)
+
+    Ok(points.into_iter().collect())
 }
 
 fn c_params<const N: usize>(
     line: &LineFields<'_>,
-    tag: &str,
-    label: &str,
+    tag: &'static str,
+    plane: usize,
     expected_count: usize,
-) -> anyhow::Result<ArrayVec<i8, N>> {
+    warnings: &mut Warnings<'_>,
+) -> Result<ArrayVec<i8, N>, Error> {
     let values = expect_tag(line, tag)?;
-    let parsed = parse_values::<i8>(line, values, label)?;
-    anyhow::ensure!(
-        parsed.len() == expected_count,
-        "line {}: expected {expected_count} {label} coeffs, got {}",
-        line.line_no,
-        parsed.len()
-    );
-    anyhow::ensure!(
-        parsed.len() <= N,
-        "line {}: expected at most {N} {label} coeffs, got {}",
-        line.line_no,
-        parsed.len()
-    );
-
-    let mut output = ArrayVec::new();
-    for value in parsed {
-        output.push(value);
-    }
-    Ok(output)
+    let mut parsed = parse_values::<i8>(line, values, tag)?;
+    if parsed.len() != expected_count {
+        let error = Error::CoefficientCount {
+            line: line.line_no,
+            plane,
+            expected: expected_count,
+            actual: parsed.len(),
+        };
+        repair(warnings, error, Repair::Resized)?;
+        parsed.resize(expected_count, 0);
+    }
+
+    // ar_coeff_lag is at most 3, so the coefficients always fit.
+    Ok(parsed.into_iter().collect())
 }
 
 #[cfg(test)]
@@ -506,6 +823,11 @@ E 0 9223372036854775807 1 7391 1
             chroma_scaling_from_luma: false,
             grain_scale_shift: 0,
             random_seed: 7391,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: false,
+            comments: Vec::new(),
         };
         let output = parse_grain_table(input).expect("Test failed");
         assert_eq!(vec![expected], output);
@@ -584,6 +906,11 @@ E 0 9223372036854775807 1 7391 1
             chroma_scaling_from_luma: false,
             grain_scale_shift: 0,
             random_seed: 7391,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: false,
+            comments: Vec::new(),
         };
         let output = parse_grain_table(input).expect("Test failed");
         assert_eq!(vec![expected], output);
@@ -681,7 +1008,10 @@ E 0 100 1 7391 1
 "#;
 
         let err = parse_grain_table(input).expect_err("missing cCr line should fail");
-        assert!(err.to_string().contains("Expected cCr line"));
+        assert!(
+            matches!(err, Error::UnexpectedEnd { expected: "cCr" }),
+            "{err}"
+        );
     }
 
     #[test]
@@ -698,9 +1028,291 @@ E 0 100 1 7391 1
 "#;
 
         let err = parse_grain_table(input).expect_err("too many Y points should fail");
+        assert!(
+            matches!(
+                err,
+                Error::TooManyPoints {
+                    line: 4,
+                    plane: 0,
+                    max: 14,
+                    actual: 15
+                }
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn parse_grain_table_from_reader() {
+        let input = "filmgrn1\nE 0 100 1 7391 1\n  p 0 6 0 8 0 1 128 192 256 128 192 256\n  sY 2 0 20 255 40\n  sCb 0\n  sCr 0\n  cY\n  cCb 0\n  cCr 0\n";
+        let output = parse_grain_table_from(input.as_bytes()).expect("valid table");
+        assert_eq!(output, parse_grain_table(input).expect("valid table"));
+
+        let err = parse_grain_table_from(&b"filmgrn1\n\xff"[..]).expect_err("invalid UTF-8");
+        assert!(matches!(err, Error::Io(_)), "{err}");
+    }
+
+    const SEGMENT_LINES: &str = "  p 0 6 0 8 0 1 128 192 256 128 192 256\n  sY 2 0 20 255 40\n  sCb 0\n  sCr 0\n  cY\n  cCb 0\n  cCr 0\n";
+
+    #[test]
+    fn grain_table_reader_streams_segments() {
+        let count = 10_000_u64;
+        let segments = (0..count).flat_map(|i| {
+            let apply = u8::from(i % 10 != 9);
+            let mut segment = format!("E {} {} {apply} 7391 1\n", i * 10, (i + 1) * 10);
+            if apply == 1 {
+                segment.push_str(SEGMENT_LINES);
+            }
+            segment.into_bytes()
+        });
+        let input: Vec<u8> = b"filmgrn1\n".iter().copied().chain(segments).collect();
+
+        let mut reader = GrainTableReader::new(input.as_slice());
+        let first = reader.next().expect("a segment").expect("valid segment");
+        assert_eq!((first.start_time, first.end_time), (0, 10));
+        assert_eq!(first.scaling_points_y.as_slice(), &[[0, 20], [255, 40]]);
+        let disabled = reader
+            .map(|segment| segment.expect("valid segment"))
+            .filter(|segment| !segment.apply_grain)
+            .count();
+        assert_eq!(disabled, (count / 10) as usize);
+    }
+
+    #[test]
+    fn parse_disabled_and_reused_segments() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{SEGMENT_LINES}E 10 20 1 1234 0\nE 20 30 0 7391 1\nE 30 40 0 \
+             7391 1\n  p 0 0 0 0 0 0 0 0 0 0 0 0\n  sY 0\n  sCb 0\n  sCr 0\n  cY\n  cCb 0\n  \
+             cCr 0\n"
+        );
+        let segments = parse_grain_table(&input).expect("valid table");
+        let [applied, reused, disabled, zeroed] = segments.as_slice() else {
+            panic!("expected 4 segments, got {}", segments.len());
+        };
+
+        assert!(applied.apply_grain && applied.update_grain);
+        assert_eq!(
+            reused,
+            &GrainTableSegment {
+                start_time: 10,
+                end_time: 20,
+                random_seed: 1234,
+                update_grain: false,
+                ..applied.clone()
+            }
+        );
+        assert_eq!(
+            disabled,
+            &GrainTableSegment {
+                start_time: 20,
+                end_time: 30,
+                random_seed: 7391,
+                apply_grain: false,
+                ..GrainTableSegment::default()
+            }
+        );
+        assert!(!zeroed.apply_grain);
+        assert_eq!(zeroed.scaling_shift, 0);
+    }
+
+    #[test]
+    fn parse_optional_clip_to_restricted_range() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{SEGMENT_LINES}E 10 20 1 7391 1\n{}",
+            SEGMENT_LINES.replace("192 256\n", "192 256 1\n")
+        );
+        let segments = parse_grain_table(&input).expect("valid table");
+        let [unclipped, clipped] = segments.as_slice() else {
+            panic!("expected 2 segments, got {}", segments.len());
+        };
+        assert!(!unclipped.clip_to_restricted_range);
+        assert!(clipped.clip_to_restricted_range);
+
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{}",
+            SEGMENT_LINES.replace("192 256\n", "192 256 1 0\n")
+        );
+        let err = parse_grain_table(&input).expect_err("14 values should fail");
+        assert!(
+            err.to_string()
+                .contains("line 3: expected 12 to 13 values on p line, got 14"),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "create")]
+    #[test]
+    fn write_then_parse_is_lossless() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{}E 10 20 1 1234 0\nE 20 30 0 7391 1\n\tp 0 0 0 0 0 \
+             0 0 0 0 0 0 0\n\tsY 0 \n\tsCb 0\n\tsCr 0\n\tcY\n\tcCb 0\n\tcCr 0\nE 30 40 0 7391 \
+             0\n",
+            SEGMENT_LINES.replace("  ", "\t").replace("sY 2 ", "sY 2  ")
+        );
+        let segments = parse_grain_table(&input).expect("valid table");
+        assert_eq!(segments.len(), 4);
+        assert_eq!(crate::grain_table_to_string(&segments), input);
+    }
+
+    #[test]
+    fn grain_table_reader_reports_line_numbers() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{SEGMENT_LINES}\nE 10 20 1 7391 1\n{}",
+            SEGMENT_LINES.replace("sY 2", "sY 3")
+        );
+
+        let mut reader = GrainTableReader::new(input.as_bytes());
+        assert!(reader.next().expect("a segment").is_ok());
+        let err = reader
+            .next()
+            .expect("an error")
+            .expect_err("invalid sY line should fail");
         assert!(
             err.to_string()
-                .contains("expected at most 14 Y-plane points")
+                .contains("line 13: expected 7 values on sY line, got 5"),
+            "{err}"
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn rejects_segments_with_validation_errors() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{}",
+            SEGMENT_LINES.replace("sY 2 0 20 255 40", "sY 2 255 20 0 40")
+        );
+        let err = parse_grain_table(&input).expect_err("unordered points should fail");
+        assert!(
+            err.to_string()
+                .contains("line 2: Y scaling points must be in increasing order"),
+            "{err}"
+        );
+
+        let input =
+            format!("filmgrn1\nE 0 10 1 7391 1\n{SEGMENT_LINES}E 5 20 1 7391 1\n{SEGMENT_LINES}");
+        let err = parse_grain_table(&input).expect_err("overlapping segments should fail");
+        assert!(
+            err.to_string()
+                .contains("line 10: segment starts before the previous segment ends"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_repairs_segments() {
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n  p 0 12 0 8 0 1 128 192 256 128 192 256\n  sY 3 255 40 0 20 0 30\n  sCb 0\n  sCr 0\n  cY 5\n  cCb\n  cCr 0\nunknown 1 2\nE 10 20 1 7391 1\n{}",
+            SEGMENT_LINES.replace("sY 2 0 20 255 40", "sY 2 0 20 255 40 10")
+        );
+        parse_grain_table(&input).expect_err("strict parsing should fail");
+
+        let (segments, warnings) =
+            parse_grain_table_lenient(&input).expect("lenient parsing should succeed");
+        let [first, second] = segments.as_slice() else {
+            panic!("expected 2 segments, got {}", segments.len());
+        };
+        assert_eq!(first.ar_coeff_shift, 9);
+        assert_eq!(first.scaling_points_y.as_slice(), &[[0, 20], [255, 40]]);
+        assert!(first.ar_coeffs_y.is_empty());
+        assert_eq!(first.ar_coeffs_cb.as_slice(), &[0]);
+        assert_eq!(second.scaling_points_y.as_slice(), &[[0, 20], [255, 40]]);
+
+        let repairs = warnings
+            .iter()
+            .map(|warning| (warning.error.to_string(), warning.repair))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repairs,
+            [
+                (
+                    "line 3: ar_coeff_shift must be between 6 and 9, got 12".to_owned(),
+                    Repair::Clamped
+                ),
+                (
+                    "line 4: Y scaling points must be in increasing order".to_owned(),
+                    Repair::Sorted
+                ),
+                (
+                    "line 7: expected 0 Y coefficients, got 1".to_owned(),
+                    Repair::Resized
+                ),
+                (
+                    "line 8: expected 1 Cb coefficients, got 0".to_owned(),
+                    Repair::Resized
+                ),
+                (
+                    "line 10: expected E line, got unknown".to_owned(),
+                    Repair::Skipped
+                ),
+                (
+                    "line 13: expected 5 values on sY line, got 6".to_owned(),
+                    Repair::Truncated
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_truncates_scaling_points() {
+        let points = (0..16)
+            .map(|i| format!("{} 10", i * 16))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let input = format!(
+            "filmgrn1\nE 0 10 1 7391 1\n{}",
+            SEGMENT_LINES.replace("sY 2 0 20 255 40", &format!("sY 16 {points}"))
+        );
+        let err = parse_grain_table(&input).expect_err("strict parsing should fail");
+        assert!(matches!(
+            err,
+            Error::TooManyPoints {
+                line: 4,
+                plane: 0,
+                max: NUM_Y_POINTS,
+                actual: 16
+            }
+        ));
+
+        let (segments, warnings) =
+            parse_grain_table_lenient(&input).expect("lenient parsing should succeed");
+        assert_eq!(segments.len(), 1);
+        assert_eq!(
+            segments
+                .first()
+                .map(|segment| segment.scaling_points_y.len()),
+            Some(NUM_Y_POINTS)
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings.first().map(|warning| warning.repair),
+            Some(Repair::Truncated)
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let input = format!(
+            "# reel 3, ISO 1600 push\nfilmgrn1\n# opening\nE 0 10 1 7391 1 # wide shot\n{SEGMENT_LINES}E 10 20 1 7391 0\n\n# close-up\nE 20 30 1 7391 1\n{}# end\n",
+            SEGMENT_LINES.replace("sY 2 0 20 255 40", "sY 2 0 20 255 40 # soft")
+        );
+        let segments = parse_grain_table(&input).expect("comments should be accepted");
+        let comments = segments
+            .iter()
+            .map(|segment| segment.comments.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            [
+                vec!["reel 3, ISO 1600 push", "opening", "wide shot"],
+                vec![],
+                vec!["close-up", "soft"],
+            ]
+        );
+        assert_eq!(
+            segments
+                .get(2)
+                .map(|segment| segment.scaling_points_y.as_slice()),
+            Some([[0, 20], [255, 40]].as_slice())
         );
     }
 }
@@ -0,0 +1,949 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// The film grain test vectors of libaom, which aomenc applies with
+// `--film-grain-test=<number>`, so that they can be used as presets and
+// test fixtures without aomenc. The values are those of the
+// `film_grain_test_vectors` array in `av1/encoder/grain_test_vectors.h`,
+// as built into libaom 3.6.0.
+
+use crate::GrainTableSegment;
+
+/// The number of film grain test vectors in libaom.
+pub const NUM_AOM_TEST_VECTORS: u8 = 16;
+
+/// A film grain test vector of libaom, as applied by aomenc with
+/// `--film-grain-test=<number>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AomTestVector {
+    /// The number of the test vector, from 1 to [`NUM_AOM_TEST_VECTORS`]
+    pub number: u8,
+    /// The name of the test vector in aomenc, such as `test-1`
+    pub name: &'static str,
+    /// How the test vector adds grain to the chroma planes
+    pub chroma: TestVectorChroma,
+    /// Whether aomenc signals the parameters again on inter frames, rather
+    /// than reusing those of a reference frame. Key frames always signal
+    /// them.
+    pub update_parameters: bool,
+    params: TestVectorParams,
+}
+
+/// How a film grain test vector adds grain to the chroma planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestVectorChroma {
+    /// Only luma has scaling points, so chroma is left unchanged.
+    None,
+    /// Chroma grain is scaled with the luma scaling function.
+    ScalingFromLuma,
+    /// Chroma grain is scaled with the scaling points of each chroma plane.
+    ScalingPoints,
+}
+
+/// The parameters of a test vector, in a form which can be stored in a
+/// static.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TestVectorParams {
+    scaling_points_y: &'static [[u8; 2]],
+    scaling_points_cb: &'static [[u8; 2]],
+    scaling_points_cr: &'static [[u8; 2]],
+    scaling_shift: u8,
+    ar_coeff_lag: u8,
+    ar_coeffs_y: &'static [i8],
+    ar_coeffs_cb: &'static [i8],
+    ar_coeffs_cr: &'static [i8],
+    ar_coeff_shift: u8,
+    cb_mult: u8,
+    cb_luma_mult: u8,
+    cb_offset: u16,
+    cr_mult: u8,
+    cr_luma_mult: u8,
+    cr_offset: u16,
+    overlap_flag: bool,
+    chroma_scaling_from_luma: bool,
+    grain_scale_shift: u8,
+    random_seed: u16,
+    clip_to_restricted_range: bool,
+}
+
+impl AomTestVector {
+    /// Returns the parameters of the test vector for the segment of video
+    /// from `start_time` to `end_time`.
+    ///
+    /// The parameters are always complete, so `update_grain` is set
+    /// regardless of [`update_parameters`](Self::update_parameters).
+    #[must_use]
+    #[inline]
+    pub fn params(&self, start_time: u64, end_time: u64) -> GrainTableSegment {
+        let params = &self.params;
+        GrainTableSegment {
+            start_time,
+            end_time,
+            scaling_points_y: params.scaling_points_y.iter().copied().collect(),
+            scaling_points_cb: params.scaling_points_cb.iter().copied().collect(),
+            scaling_points_cr: params.scaling_points_cr.iter().copied().collect(),
+            scaling_shift: params.scaling_shift,
+            ar_coeff_lag: params.ar_coeff_lag,
+            ar_coeffs_y: params.ar_coeffs_y.iter().copied().collect(),
+            ar_coeffs_cb: params.ar_coeffs_cb.iter().copied().collect(),
+            ar_coeffs_cr: params.ar_coeffs_cr.iter().copied().collect(),
+            ar_coeff_shift: params.ar_coeff_shift,
+            cb_mult: params.cb_mult,
+            cb_luma_mult: params.cb_luma_mult,
+            cb_offset: params.cb_offset,
+            cr_mult: params.cr_mult,
+            cr_luma_mult: params.cr_luma_mult,
+            cr_offset: params.cr_offset,
+            overlap_flag: params.overlap_flag,
+            chroma_scaling_from_luma: params.chroma_scaling_from_luma,
+            grain_scale_shift: params.grain_scale_shift,
+            random_seed: params.random_seed,
+            apply_grain: true,
+            update_grain: true,
+            film_grain_params_ref_idx: None,
+            clip_to_restricted_range: params.clip_to_restricted_range,
+            comments: Vec::new(),
+        }
+    }
+}
+
+/// Returns the film grain test vector of libaom with the given `number`,
+/// from 1 to [`NUM_AOM_TEST_VECTORS`], as passed to aomenc's
+/// `--film-grain-test`.
+#[must_use]
+#[inline]
+pub fn aom_test_vector(number: u8) -> Option<AomTestVector> {
+    AOM_TEST_VECTORS
+        .get(usize::from(number.checked_sub(1)?))
+        .copied()
+}
+
+/// The film grain test vectors of libaom, in the order of their numbers.
+pub static AOM_TEST_VECTORS: [AomTestVector; NUM_AOM_TEST_VECTORS as usize] = [
+    AomTestVector {
+        number: 1,
+        name: "test-1",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [16, 0],
+                [25, 136],
+                [33, 144],
+                [41, 160],
+                [48, 168],
+                [56, 136],
+                [67, 128],
+                [82, 144],
+                [97, 152],
+                [113, 144],
+                [128, 176],
+                [143, 168],
+                [158, 176],
+                [178, 184],
+            ],
+            scaling_points_cb: &[
+                [16, 0],
+                [20, 64],
+                [28, 88],
+                [60, 104],
+                [90, 136],
+                [105, 160],
+                [134, 168],
+                [168, 208],
+            ],
+            scaling_points_cr: &[
+                [16, 0],
+                [28, 96],
+                [56, 80],
+                [66, 96],
+                [80, 104],
+                [108, 96],
+                [122, 112],
+                [137, 112],
+                [169, 176],
+            ],
+            scaling_shift: 11,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[0, 0, -58, 0, 0, 0, -76, 100, -43, 0, -51, 82],
+            ar_coeffs_cb: &[0, 0, -49, 0, 0, 0, -36, 22, -30, 0, -38, 7, 39],
+            ar_coeffs_cr: &[0, 0, -47, 0, 0, 0, -31, 31, -25, 0, -32, 13, -100],
+            ar_coeff_shift: 8,
+            cb_mult: 247,
+            cb_luma_mult: 192,
+            cb_offset: 18,
+            cr_mult: 229,
+            cr_luma_mult: 192,
+            cr_offset: 54,
+            overlap_flag: false,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 2,
+        name: "test-2",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 96], [255, 96]],
+            scaling_points_cb: &[[0, 64], [255, 64]],
+            scaling_points_cr: &[[0, 64], [255, 64]],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 127,
+            ],
+            ar_coeffs_cr: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 127,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 3,
+        name: "test-3",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 192], [255, 192]],
+            scaling_points_cb: &[[0, 128], [255, 128]],
+            scaling_points_cr: &[[0, 128], [255, 128]],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                4, -7, 2, 4, 12, -12, 5, -8, 6, 8, -19, -16, 19, -10, -2, 17, -42, 58, -2, -13, 9,
+                14, -36, 67, 0,
+            ],
+            ar_coeffs_cr: &[
+                4, -7, 2, 4, 12, -12, 5, -8, 6, 8, -19, -16, 19, -10, -2, 17, -42, 58, -2, -13, 9,
+                14, -36, 67, 0,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 1,
+            random_seed: 45231,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 4,
+        name: "test-4",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [16, 0],
+                [24, 137],
+                [53, 146],
+                [63, 155],
+                [78, 155],
+                [107, 150],
+                [122, 147],
+                [136, 147],
+                [166, 153],
+            ],
+            scaling_points_cb: &[
+                [16, 0],
+                [20, 72],
+                [27, 82],
+                [33, 91],
+                [69, 121],
+                [95, 143],
+                [108, 154],
+                [134, 169],
+                [147, 177],
+            ],
+            scaling_points_cr: &[
+                [16, 0],
+                [24, 95],
+                [54, 93],
+                [65, 94],
+                [79, 98],
+                [109, 107],
+                [124, 119],
+                [139, 136],
+                [169, 170],
+            ],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                7, -9, 2, 4, 7, -12, 7, -18, 18, -30, -27, -42, 13, -20, 7, -18, 6, 107, 55, -2,
+                -4, -9, -22, 113,
+            ],
+            ar_coeffs_cb: &[
+                -3, -1, -4, 3, -6, -2, 3, 1, -4, -10, -10, -5, -5, -3, -1, -13, -28, -25, -31, -6,
+                -4, 14, -64, 66, 0,
+            ],
+            ar_coeffs_cr: &[
+                0, 4, -3, 13, 0, 1, -3, 0, -3, -10, -68, -4, -2, -5, 2, -3, -20, 62, -31, 0, -4,
+                -1, -8, -29, 0,
+            ],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 5,
+        name: "test-5",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: false,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 64], [255, 64]],
+            scaling_points_cb: &[
+                [0, 96],
+                [32, 90],
+                [64, 83],
+                [96, 76],
+                [128, 68],
+                [159, 59],
+                [191, 48],
+                [223, 34],
+                [255, 0],
+            ],
+            scaling_points_cr: &[
+                [0, 0],
+                [32, 34],
+                [64, 48],
+                [96, 59],
+                [128, 68],
+                [159, 76],
+                [191, 83],
+                [223, 90],
+                [255, 96],
+            ],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                -2, 2, -5, 7, -6, 4, -2, -1, 1, -2, 0, -2, 2, -3, -5, 13, -13, 6, -14, 8, -1, 18,
+                -36, 58, 0,
+            ],
+            ar_coeffs_cr: &[
+                -2, -1, -3, 14, -4, -1, -3, 0, -1, 7, -31, 7, 2, 0, 1, 0, -7, 50, -8, -2, 2, 2, 2,
+                -4, 0,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 1063,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 6,
+        name: "test-6",
+        chroma: TestVectorChroma::None,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [0, 96],
+                [20, 92],
+                [39, 88],
+                [59, 84],
+                [78, 80],
+                [98, 75],
+                [118, 70],
+                [137, 65],
+                [157, 60],
+                [177, 53],
+                [196, 46],
+                [216, 38],
+                [235, 27],
+                [255, 0],
+            ],
+            scaling_points_cb: &[],
+            scaling_points_cr: &[],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            ar_coeffs_cr: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 2754,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 7,
+        name: "test-7",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [0, 0],
+                [20, 27],
+                [39, 38],
+                [59, 46],
+                [78, 53],
+                [98, 60],
+                [118, 65],
+                [137, 70],
+                [157, 75],
+                [177, 80],
+                [196, 84],
+                [216, 88],
+                [235, 92],
+                [255, 96],
+            ],
+            scaling_points_cb: &[[0, 0], [255, 0]],
+            scaling_points_cr: &[[0, 0], [255, 0]],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            ar_coeffs_cr: &[
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 8,
+        name: "test-8",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 96], [255, 96]],
+            scaling_points_cb: &[[0, 62], [255, 62]],
+            scaling_points_cr: &[[0, 62], [255, 62]],
+            scaling_shift: 11,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                0, -2, -2, 8, 5, -1, 1, -1, 5, 16, -33, -9, 6, -1, -3, 10, -47, 63, 0, -15, 3, 11,
+                -42, 75, -69,
+            ],
+            ar_coeffs_cr: &[
+                1, -1, -1, 9, 5, 0, 1, -1, 5, 15, -32, -10, 8, -2, -4, 11, -46, 62, 1, -16, 3, 13,
+                -43, 75, -55,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 9,
+        name: "test-9",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: false,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 48], [255, 48]],
+            scaling_points_cb: &[[0, 32], [255, 32]],
+            scaling_points_cr: &[[0, 32], [255, 32]],
+            scaling_shift: 10,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[10, -30, -20, -39, 1, -24, 12, 103, 60, -9, -24, 113],
+            ar_coeffs_cb: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 127],
+            ar_coeffs_cr: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 127],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 10,
+        name: "test-10",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 48], [255, 48]],
+            scaling_points_cb: &[[0, 32], [255, 32]],
+            scaling_points_cr: &[[0, 32], [255, 32]],
+            scaling_shift: 10,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[10, -30, -20, -39, 1, -24, 12, 103, 60, -9, -24, 113],
+            ar_coeffs_cb: &[-7, -6, -48, -22, 2, -3, -45, 73, -11, -26, -52, 76, 0],
+            ar_coeffs_cr: &[-7, -6, -48, -22, 2, -3, -45, 73, -11, -26, -52, 76, 0],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 11,
+        name: "test-11",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: false,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 32], [255, 32]],
+            scaling_points_cb: &[
+                [0, 48],
+                [32, 45],
+                [64, 42],
+                [96, 38],
+                [128, 34],
+                [159, 29],
+                [191, 24],
+                [223, 17],
+                [255, 0],
+            ],
+            scaling_points_cr: &[
+                [0, 0],
+                [32, 17],
+                [64, 24],
+                [96, 29],
+                [128, 34],
+                [159, 38],
+                [191, 42],
+                [223, 45],
+                [255, 48],
+            ],
+            scaling_shift: 10,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                7, -9, 2, 4, 7, -12, 7, -18, 18, -30, -27, -42, 13, -20, 7, -18, 6, 107, 55, -2,
+                -4, -9, -22, 113,
+            ],
+            ar_coeffs_cb: &[
+                -3, -1, -4, 3, -6, -2, 3, 1, -4, -10, -10, -5, -5, -3, -1, -13, -28, -25, -31, -6,
+                -4, 14, -64, 66, 0,
+            ],
+            ar_coeffs_cr: &[
+                0, 4, -3, 13, 0, 1, -3, 0, -3, -10, -68, -4, -2, -5, 2, -3, -20, 62, -31, 0, -4,
+                -1, -8, -29, 0,
+            ],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 1357,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 12,
+        name: "test-12",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [16, 0],
+                [24, 49],
+                [39, 69],
+                [46, 84],
+                [53, 91],
+                [63, 100],
+                [78, 114],
+                [92, 134],
+                [164, 139],
+            ],
+            scaling_points_cb: &[
+                [16, 0],
+                [20, 31],
+                [26, 42],
+                [33, 54],
+                [40, 65],
+                [47, 72],
+                [56, 85],
+                [84, 123],
+                [152, 157],
+            ],
+            scaling_points_cr: &[
+                [16, 0],
+                [25, 14],
+                [39, 33],
+                [47, 40],
+                [54, 47],
+                [64, 62],
+                [79, 76],
+                [94, 83],
+                [167, 101],
+            ],
+            scaling_shift: 10,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[0, 0, -58, 0, 0, 0, -76, 100, -43, 0, -51, 82],
+            ar_coeffs_cb: &[0, 0, -49, 0, 0, 0, -36, 22, -30, 0, -38, 7, 39],
+            ar_coeffs_cr: &[0, 0, -47, 0, 0, 0, -31, 31, -25, 0, -32, 13, -100],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: false,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 13,
+        name: "test-13",
+        chroma: TestVectorChroma::None,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [0, 48],
+                [20, 46],
+                [39, 44],
+                [59, 42],
+                [78, 40],
+                [98, 38],
+                [118, 35],
+                [137, 33],
+                [157, 30],
+                [177, 27],
+                [196, 23],
+                [216, 19],
+                [235, 13],
+                [255, 0],
+            ],
+            scaling_points_cb: &[],
+            scaling_points_cr: &[],
+            scaling_shift: 10,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[10, -30, -20, -39, 1, -24, 12, 103, 60, -9, -24, 113],
+            ar_coeffs_cb: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ar_coeffs_cr: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 14,
+        name: "test-14",
+        chroma: TestVectorChroma::None,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [0, 0],
+                [20, 13],
+                [39, 19],
+                [59, 23],
+                [78, 27],
+                [98, 30],
+                [118, 33],
+                [137, 35],
+                [157, 38],
+                [177, 40],
+                [196, 42],
+                [216, 44],
+                [235, 46],
+                [255, 48],
+            ],
+            scaling_points_cb: &[],
+            scaling_points_cr: &[],
+            scaling_shift: 10,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[10, -30, -20, -39, 1, -24, 12, 103, 60, -9, -24, 113],
+            ar_coeffs_cb: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ar_coeffs_cr: &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ar_coeff_shift: 8,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: true,
+        },
+    },
+    AomTestVector {
+        number: 15,
+        name: "test-15",
+        chroma: TestVectorChroma::ScalingFromLuma,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[[0, 96]],
+            scaling_points_cb: &[],
+            scaling_points_cr: &[],
+            scaling_shift: 11,
+            ar_coeff_lag: 2,
+            ar_coeffs_y: &[5, -15, -10, -19, 0, -12, 6, 51, 30, -5, -12, 56],
+            ar_coeffs_cb: &[2, 2, -24, -5, 1, 1, -18, 37, -2, 0, -15, 39, -70],
+            ar_coeffs_cr: &[2, 3, -24, -5, -1, 0, -18, 38, -2, 0, -15, 39, -55],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: true,
+            grain_scale_shift: 0,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+    AomTestVector {
+        number: 16,
+        name: "test-16",
+        chroma: TestVectorChroma::ScalingPoints,
+        update_parameters: true,
+        params: TestVectorParams {
+            scaling_points_y: &[
+                [16, 0],
+                [58, 126],
+                [87, 120],
+                [97, 122],
+                [112, 125],
+                [126, 131],
+                [141, 139],
+                [199, 153],
+            ],
+            scaling_points_cb: &[
+                [16, 0],
+                [59, 68],
+                [66, 76],
+                [73, 82],
+                [79, 85],
+                [86, 86],
+                [151, 95],
+                [192, 101],
+            ],
+            scaling_points_cr: &[
+                [16, 0],
+                [59, 64],
+                [89, 80],
+                [99, 86],
+                [114, 90],
+                [129, 93],
+                [144, 97],
+                [203, 85],
+            ],
+            scaling_shift: 10,
+            ar_coeff_lag: 3,
+            ar_coeffs_y: &[
+                4, 1, 3, 0, 1, -3, 8, -3, 7, -23, 1, -25, 0, -10, 6, -17, -4, 53, 36, 5, -5, -17,
+                8, 66,
+            ],
+            ar_coeffs_cb: &[
+                0, -2, -2, 8, 5, -1, 1, -1, 5, 16, -33, -9, 6, -1, -3, 10, -47, 63, 0, -15, 3, 11,
+                -42, 75, -69,
+            ],
+            ar_coeffs_cr: &[
+                1, -1, -1, 9, 5, 0, 1, -1, 5, 15, -32, -10, 8, -2, -4, 11, -46, 62, 1, -16, 3, 13,
+                -43, 75, -55,
+            ],
+            ar_coeff_shift: 7,
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            cr_mult: 128,
+            cr_luma_mult: 192,
+            cr_offset: 256,
+            overlap_flag: true,
+            chroma_scaling_from_luma: false,
+            grain_scale_shift: 2,
+            random_seed: 45231,
+            clip_to_restricted_range: false,
+        },
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChromaFormat, Severity};
+
+    #[test]
+    fn vectors_are_numbered_and_named() {
+        assert!(aom_test_vector(0).is_none());
+        assert!(aom_test_vector(NUM_AOM_TEST_VECTORS + 1).is_none());
+        for number in 1..=NUM_AOM_TEST_VECTORS {
+            let vector = aom_test_vector(number).expect("number is in range");
+            assert_eq!(vector.number, number);
+            assert_eq!(vector.name, format!("test-{number}"));
+        }
+
+        let chroma = |number| aom_test_vector(number).map(|vector| vector.chroma);
+        assert_eq!(chroma(1), Some(TestVectorChroma::ScalingPoints));
+        assert_eq!(chroma(6), Some(TestVectorChroma::None));
+        assert_eq!(chroma(15), Some(TestVectorChroma::ScalingFromLuma));
+    }
+
+    #[test]
+    fn vectors_are_valid() {
+        for vector in &AOM_TEST_VECTORS {
+            let segment = vector.params(0, 10_000_000);
+            let errors = segment
+                .validate(Some(ChromaFormat::Yuv420))
+                .into_iter()
+                .filter(|violation| violation.severity() == Severity::Error)
+                .collect::<Vec<_>>();
+            assert_eq!(errors, [], "{}", vector.name);
+
+            let chroma = if segment.chroma_scaling_from_luma {
+                TestVectorChroma::ScalingFromLuma
+            } else if segment.scaling_points_cb.is_empty() && segment.scaling_points_cr.is_empty() {
+                TestVectorChroma::None
+            } else {
+                TestVectorChroma::ScalingPoints
+            };
+            assert_eq!(vector.chroma, chroma, "{}", vector.name);
+        }
+    }
+
+    #[cfg(all(feature = "create", feature = "parse"))]
+    #[test]
+    fn vectors_survive_a_round_trip() {
+        let segments = AOM_TEST_VECTORS
+            .iter()
+            .zip(0..)
+            .map(|(vector, index)| vector.params(index * 10_000_000, (index + 1) * 10_000_000))
+            .collect::<Vec<_>>();
+        let mut annotated = Vec::new();
+        crate::write_grain_table_with_mode(&mut annotated, &segments, crate::WriteMode::Annotated)
+            .expect("writing to a Vec cannot fail");
+        let annotated = String::from_utf8(annotated).expect("grain tables are ASCII");
+        assert_eq!(
+            crate::parse_grain_table(&annotated).expect("table is valid"),
+            segments
+        );
+
+        // Plain tables are read by aomenc, which has no clipping flag.
+        let table = crate::grain_table_to_string(&segments);
+        let unclipped = segments
+            .into_iter()
+            .map(|segment| GrainTableSegment {
+                clip_to_restricted_range: false,
+                ..segment
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            crate::parse_grain_table(&table).expect("table is valid"),
+            unclipped
+        );
+    }
+}
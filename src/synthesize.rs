@@ -0,0 +1,891 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// This is an implementation of the film grain synthesis process described
+// in section 7.18.3 of the AV1 specification. It is intended to produce
+// output identical to a conforming decoder, so that grain tables can be
+// previewed and tested without a round trip through an encoder and decoder.
+
+mod scaling;
+mod template;
+
+use anyhow::{Result, ensure};
+use v_frame::{chroma::ChromaSubsampling, frame::Frame, pixel::Pixel, plane::Plane};
+
+pub use self::{
+    scaling::ScalingLuts,
+    template::{GRAIN_TEMPLATE_HEIGHT, GRAIN_TEMPLATE_WIDTH, GrainTemplates},
+};
+use self::{
+    scaling::{ChromaScaling, scale},
+    template::GrainBlock,
+};
+use crate::{GrainTableSegment, random::block_offsets};
+
+/// The luma height of each noise stripe, not counting the overlap
+/// with the following stripe.
+const STRIPE_HEIGHT: usize = 32;
+/// The luma size of each noise block, including the overlap
+/// with the following block.
+const BLOCK_SIZE: usize = 34;
+
+/// Applies the film grain described by `segment` to `frame`, following
+/// the film grain synthesis process of the AV1 specification.
+///
+/// The output matches that of a conforming AV1 decoder applying the same
+/// parameters to the same decoded frame. Frames may be 8-bit, or 10-bit or
+/// 12-bit stored as `u16`. The scaling points of `segment` are always
+/// specified in 8-bit units, and are interpolated to the frame's bit depth.
+/// Segments with `apply_grain` unset leave the frame unchanged.
+///
+/// With `clip_to_restricted_range` set, chroma is clipped to the chroma
+/// studio range. This differs from a decoder for video using the identity
+/// matrix, whose chroma is clipped to the luma studio range instead.
+///
+/// # Errors
+///
+/// - If the frame is not 8-bit, 10-bit or 12-bit
+/// - If the segment contains parameters outside of the ranges allowed by the
+///   AV1 specification
+#[inline]
+pub fn apply_grain<T: Pixel>(segment: &GrainTableSegment, frame: &mut Frame<T>) -> Result<()> {
+    if !segment.apply_grain {
+        return Ok(());
+    }
+    let bit_depth = frame.bit_depth.get();
+    validate_params(segment, bit_depth)?;
+
+    let (sub_x, sub_y) = subsampling_factors(frame.subsampling);
+    let templates = GrainTemplates::new(segment, frame.subsampling, bit_depth);
+    let luts = ScalingLuts::new(segment);
+
+    let Frame {
+        y_plane,
+        u_plane,
+        v_plane,
+        ..
+    } = frame;
+    let luma_width = y_plane.width();
+    let luma_height = y_plane.height();
+
+    // Chroma is blended first, because it is scaled based on the luma values
+    // from before grain was applied.
+    let chroma_planes = [
+        (
+            u_plane.as_mut(),
+            &templates.cb,
+            luts.cb(),
+            !segment.scaling_points_cb.is_empty(),
+        ),
+        (
+            v_plane.as_mut(),
+            &templates.cr,
+            luts.cr(),
+            !segment.scaling_points_cr.is_empty(),
+        ),
+    ];
+    for (plane, template, scaling, has_points) in chroma_planes {
+        let Some(plane) = plane else {
+            continue;
+        };
+        if !has_points && !segment.chroma_scaling_from_luma {
+            continue;
+        }
+
+        let noise = generate_noise(
+            segment,
+            template,
+            luma_width,
+            luma_height,
+            plane.width(),
+            plane.height(),
+            sub_x,
+            sub_y,
+            bit_depth,
+        );
+        blend_chroma(
+            plane,
+            y_plane,
+            &noise,
+            scaling,
+            segment.scaling_shift,
+            sub_x,
+            sub_y,
+            bit_depth,
+            segment.clip_to_restricted_range,
+        );
+    }
+
+    if !segment.scaling_points_y.is_empty() {
+        let noise = generate_noise(
+            segment,
+            &templates.luma,
+            luma_width,
+            luma_height,
+            luma_width,
+            luma_height,
+            false,
+            false,
+            bit_depth,
+        );
+        blend_luma(
+            y_plane,
+            &noise,
+            luts.y_lut(),
+            segment.scaling_shift,
+            bit_depth,
+            segment.clip_to_restricted_range,
+        );
+    }
+
+    Ok(())
+}
+
+/// Generates the grain templates for `segment`, as used by [`apply_grain`]
+/// for frames with the given chroma subsampling and bit depth.
+///
+/// This exposes the raw grain texture before it is scaled, which is useful
+/// to visualize the size and shape of the grain, or to check the effect
+/// of a set of auto-regressive coefficients.
+///
+/// # Errors
+///
+/// - If `bit_depth` is not 8, 10 or 12
+/// - If the segment contains parameters outside of the ranges allowed by the
+///   AV1 specification
+#[inline]
+pub fn generate_grain_templates(
+    segment: &GrainTableSegment,
+    subsampling: ChromaSubsampling,
+    bit_depth: u8,
+) -> Result<GrainTemplates> {
+    validate_params(segment, bit_depth)?;
+
+    Ok(GrainTemplates::new(segment, subsampling, bit_depth))
+}
+
+fn validate_params(segment: &GrainTableSegment, bit_depth: u8) -> Result<()> {
+    ensure!(
+        matches!(bit_depth, 8 | 10 | 12),
+        "Grain synthesis only supports 8-bit, 10-bit and 12-bit frames, got {bit_depth}-bit"
+    );
+    ensure!(
+        (8..=11).contains(&segment.scaling_shift),
+        "scaling_shift must be between 8 and 11"
+    );
+    ensure!(
+        segment.ar_coeff_lag <= 3,
+        "ar_coeff_lag must be between 0 and 3"
+    );
+    ensure!(
+        (6..=9).contains(&segment.ar_coeff_shift),
+        "ar_coeff_shift must be between 6 and 9"
+    );
+    ensure!(
+        segment.grain_scale_shift <= 3,
+        "grain_scale_shift must be between 0 and 3"
+    );
+
+    Ok(())
+}
+
+/// Returns whether chroma is subsampled horizontally and vertically.
+const fn subsampling_factors(subsampling: ChromaSubsampling) -> (bool, bool) {
+    match subsampling {
+        ChromaSubsampling::Yuv420 | ChromaSubsampling::Monochrome => (true, true),
+        ChromaSubsampling::Yuv422 => (true, false),
+        ChromaSubsampling::Yuv444 => (false, false),
+    }
+}
+
+/// Builds the noise for one plane out of randomly offset 32x32 luma blocks
+/// of its grain template, blending the overlapping edges of adjacent blocks
+/// if `overlap_flag` is set.
+#[allow(clippy::too_many_arguments)]
+fn generate_noise(
+    segment: &GrainTableSegment,
+    template: &GrainBlock,
+    luma_width: usize,
+    luma_height: usize,
+    width: usize,
+    height: usize,
+    sub_x: bool,
+    sub_y: bool,
+    bit_depth: u8,
+) -> Vec<i16> {
+    let (grain_min, grain_max) = (grain_min(bit_depth), grain_max(bit_depth));
+    let block_width = BLOCK_SIZE >> usize::from(sub_x);
+    let block_height = BLOCK_SIZE >> usize::from(sub_y);
+    let stripe_height = STRIPE_HEIGHT >> usize::from(sub_y);
+    // Blocks are written slightly past the edge of the plane,
+    // but those samples are never read.
+    let stripe_stride = width + block_width;
+
+    let mut noise = vec![0i16; width * height];
+    let mut stripe = vec![0i16; stripe_stride * block_height];
+    let mut overhang = vec![0i16; stripe_stride * (block_height - stripe_height)];
+
+    let num_stripes = luma_height.div_ceil(2).div_ceil(16);
+    for stripe_num in 0..num_stripes {
+        let blocks = (0..luma_width.div_ceil(2))
+            .step_by(16)
+            .zip(block_offsets(segment.random_seed, stripe_num));
+        for (x, (offset_x, offset_y)) in blocks {
+            let (offset_x, offset_y) = (usize::from(offset_x), usize::from(offset_y));
+            let template_x = if sub_x {
+                6 + offset_x
+            } else {
+                9 + offset_x * 2
+            };
+            let template_y = if sub_y {
+                6 + offset_y
+            } else {
+                9 + offset_y * 2
+            };
+            let column = (x * 2) >> usize::from(sub_x);
+
+            for (i, stripe_row) in stripe.chunks_exact_mut(stripe_stride).enumerate() {
+                // SAFETY: Random offsets are at most 15, so the block always
+                // lies within the template for the plane's subsampling.
+                let template_row = unsafe { template.get_unchecked(template_y + i) };
+                for j in 0..block_width {
+                    let mut grain =
+                        i32::from(*unsafe { template_row.get_unchecked(template_x + j) });
+                    // SAFETY: The stripe is wide enough to contain the last block
+                    // starting within the plane.
+                    let value = unsafe { stripe_row.get_unchecked_mut(column + j) };
+                    if segment.overlap_flag && x > 0 {
+                        let old = i32::from(*value);
+                        let blended = match (sub_x, j) {
+                            (false, 0) => Some(old * 27 + grain * 17),
+                            (false, 1) => Some(old * 17 + grain * 27),
+                            (true, 0) => Some(old * 23 + grain * 22),
+                            _ => None,
+                        };
+                        if let Some(blended) = blended {
+                            grain = round2(blended, 5).clamp(grain_min, grain_max);
+                        }
+                    }
+                    *value = grain as i16;
+                }
+            }
+        }
+
+        let first_row = stripe_num * stripe_height;
+        let rows = noise
+            .chunks_exact_mut(width)
+            .skip(first_row)
+            .take(stripe_height);
+        for (i, (noise_row, stripe_row)) in rows.zip(stripe.chunks_exact(stripe_stride)).enumerate()
+        {
+            let overhang_row = overhang.chunks_exact(stripe_stride).nth(i);
+            for (x, (value, &grain)) in noise_row.iter_mut().zip(stripe_row).enumerate() {
+                let mut grain = i32::from(grain);
+                if let Some(overhang_row) = overhang_row
+                    && segment.overlap_flag
+                    && stripe_num > 0
+                {
+                    // SAFETY: The overhang rows have the same stride as the stripe.
+                    let old = i32::from(*unsafe { overhang_row.get_unchecked(x) });
+                    grain = match (sub_y, i) {
+                        (false, 0) => old * 27 + grain * 17,
+                        (false, _) => old * 17 + grain * 27,
+                        (true, _) => old * 23 + grain * 22,
+                    };
+                    grain = round2(grain, 5).clamp(grain_min, grain_max);
+                }
+                *value = grain as i16;
+            }
+        }
+
+        overhang.copy_from_slice(
+            stripe
+                .get(stripe_stride * stripe_height..)
+                .expect("stripe contains overhang rows"),
+        );
+    }
+
+    noise
+}
+
+fn blend_luma<T: Pixel>(
+    plane: &mut Plane<T>,
+    noise: &[i16],
+    lut: &[u8; 256],
+    scaling_shift: u8,
+    bit_depth: u8,
+    restricted: bool,
+) {
+    let width = plane.width();
+    let (min, max) = sample_range(bit_depth, restricted, false);
+    let shift = u32::from(scaling_shift);
+    for (row, noise_row) in plane.rows_mut().zip(noise.chunks_exact(width)) {
+        for (pixel, &noise) in row.iter_mut().zip(noise_row) {
+            let orig = i32::from((*pixel).into());
+            let noise = round2(scale(lut, orig, bit_depth) * i32::from(noise), shift);
+            *pixel = to_pixel((orig + noise).clamp(min, max));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn blend_chroma<T: Pixel>(
+    plane: &mut Plane<T>,
+    luma: &Plane<T>,
+    noise: &[i16],
+    scaling: &ChromaScaling,
+    scaling_shift: u8,
+    sub_x: bool,
+    sub_y: bool,
+    bit_depth: u8,
+    restricted: bool,
+) {
+    let width = plane.width();
+    let (min, max) = sample_range(bit_depth, restricted, true);
+    let luma_width = luma.width();
+    let shift = u32::from(scaling_shift);
+    for (y, (row, noise_row)) in plane.rows_mut().zip(noise.chunks_exact(width)).enumerate() {
+        let luma_row = luma
+            .row(y << usize::from(sub_y))
+            .expect("chroma rows map onto luma rows");
+        for (x, (pixel, &noise)) in row.iter_mut().zip(noise_row).enumerate() {
+            let luma_x = x << usize::from(sub_x);
+            let luma_next_x = (luma_x + 1).min(luma_width - 1);
+            let luma_value = |x: usize| {
+                i32::from(
+                    (*luma_row
+                        .get(x)
+                        .expect("chroma columns map onto luma columns"))
+                    .into(),
+                )
+            };
+            let average_luma = if sub_x {
+                round2(luma_value(luma_x) + luma_value(luma_next_x), 1)
+            } else {
+                luma_value(luma_x)
+            };
+
+            let orig = i32::from((*pixel).into());
+            let noise = round2(
+                scaling.scale(average_luma, orig, bit_depth) * i32::from(noise),
+                shift,
+            );
+            *pixel = to_pixel((orig + noise).clamp(min, max));
+        }
+    }
+}
+
+/// The smallest value of a grain sample at `bit_depth`.
+const fn grain_min(bit_depth: u8) -> i32 {
+    -(128 << (bit_depth - 8))
+}
+
+/// The largest value of a grain sample at `bit_depth`.
+const fn grain_max(bit_depth: u8) -> i32 {
+    (128 << (bit_depth - 8)) - 1
+}
+
+/// The largest pixel value at `bit_depth`.
+const fn pixel_max(bit_depth: u8) -> i32 {
+    (1 << bit_depth) - 1
+}
+
+/// The range of pixel values after blending, which is the studio range if
+/// `restricted` is set. Chroma is clipped to the chroma studio range, which
+/// assumes the matrix coefficients are not the identity.
+const fn sample_range(bit_depth: u8, restricted: bool, chroma: bool) -> (i32, i32) {
+    if !restricted {
+        return (0, pixel_max(bit_depth));
+    }
+    let shift = bit_depth - 8;
+    (16 << shift, if chroma { 240 } else { 235 } << shift)
+}
+
+fn to_pixel<T: Pixel>(value: i32) -> T {
+    T::try_from(value as u16).expect("value is clipped to the frame's bit depth")
+}
+
+/// Rounds `x` to the nearest multiple of `2^shift` and divides by `2^shift`,
+/// as defined by the `Round2` function of the AV1 specification.
+const fn round2(x: i32, shift: u32) -> i32 {
+    if shift == 0 {
+        x
+    } else {
+        (x + (1 << (shift - 1))) >> shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+    use v_frame::frame::FrameBuilder;
+
+    use super::*;
+    use crate::{aom_test_vector, random::RandomGenerator};
+
+    fn flat_frame<T: Pixel>(subsampling: ChromaSubsampling, bit_depth: u8, value: u16) -> Frame<T> {
+        let mut frame = FrameBuilder::new(64, 64, subsampling, bit_depth)
+            .build::<T>()
+            .expect("valid frame");
+        let value = T::try_from(value).expect("value fits in the pixel type");
+        for plane in [
+            Some(&mut frame.y_plane),
+            frame.u_plane.as_mut(),
+            frame.v_plane.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            plane.data_mut().fill(value);
+        }
+        frame
+    }
+
+    fn luma_segment() -> GrainTableSegment {
+        GrainTableSegment {
+            end_time: 100,
+            scaling_points_y: ArrayVec::from_iter([[0, 64], [255, 64]]),
+            ..GrainTableSegment::default()
+        }
+    }
+
+    /// With a lag of 0 the template is the unfiltered Gaussian sequence,
+    /// so the noise of the first sample can be derived directly from the
+    /// random number generators.
+    fn first_sample_noise(segment: &GrainTableSegment, bit_depth: u8) -> i32 {
+        let (offset_x, offset_y) = block_offsets(segment.random_seed, 0)
+            .next()
+            .expect("the generator never ends");
+        let template_x = 9 + usize::from(offset_x) * 2;
+        let template_y = 9 + usize::from(offset_y) * 2;
+
+        let mut luma_rng = RandomGenerator::new(segment.random_seed);
+        let gaussian = std::iter::repeat_with(|| luma_rng.next_gaussian())
+            .nth(template_y * GRAIN_TEMPLATE_WIDTH + template_x)
+            .expect("the generator never ends");
+        let grain = round2(
+            i32::from(gaussian),
+            u32::from(12 - bit_depth + segment.grain_scale_shift),
+        );
+        round2(64 * grain, u32::from(segment.scaling_shift))
+    }
+
+    /// Builds the frame used for the reference outputs, whose size is not a
+    /// multiple of the 32x32 luma blocks of the noise.
+    ///
+    /// The reference outputs were made by encoding this frame losslessly
+    /// with libaom 3.6.0 and `--film-grain-test=<number>`, then decoding it
+    /// with both dav1d 1.0.0 and libaom 3.6.0, which produced identical
+    /// frames. Decoding without grain gives back this frame exactly.
+    fn reference_frame<T: Pixel>(bit_depth: u8) -> Frame<T> {
+        let mut frame = FrameBuilder::new(104, 76, ChromaSubsampling::Yuv420, bit_depth)
+            .build::<T>()
+            .expect("valid frame");
+        let planes = [
+            Some(&mut frame.y_plane),
+            frame.u_plane.as_mut(),
+            frame.v_plane.as_mut(),
+        ];
+        for (index, plane) in planes.into_iter().flatten().enumerate() {
+            for y in 0..plane.height() {
+                for x in 0..plane.width() {
+                    let value =
+                        (x * 29 + y * 53 + index * 97 + (x * y) % 17) * 37 % (1 << bit_depth);
+                    *plane.pixel_mut(x, y).expect("pixel is in the plane") =
+                        T::try_from(u16::try_from(value).expect("value fits in 16 bits"))
+                            .expect("value fits in the pixel type");
+                }
+            }
+        }
+        frame
+    }
+
+    /// Returns the 64-bit FNV-1a hash of the samples of each plane, taken as
+    /// little-endian `u16`s in raster order.
+    fn plane_checksums<T: Pixel>(frame: &Frame<T>) -> [u64; 3] {
+        let checksum = |plane: Option<&Plane<T>>| {
+            plane
+                .into_iter()
+                .flat_map(Plane::pixels)
+                .flat_map(|pixel| Into::<u16>::into(pixel).to_le_bytes())
+                .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                    (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                })
+        };
+        [
+            checksum(Some(&frame.y_plane)),
+            checksum(frame.u_plane.as_ref()),
+            checksum(frame.v_plane.as_ref()),
+        ]
+    }
+
+    /// Checks the output for each libaom test vector against the checksums
+    /// of the reference decoders.
+    fn assert_matches_reference<T: Pixel>(bit_depth: u8, checksums: &[(u8, [u64; 3])]) {
+        for &(number, expected) in checksums {
+            let segment = aom_test_vector(number)
+                .expect("test vector exists")
+                .params(0, 1);
+            let mut frame = reference_frame::<T>(bit_depth);
+            apply_grain(&segment, &mut frame).expect("valid segment");
+            assert_eq!(
+                plane_checksums(&frame),
+                expected,
+                "test-{number} at {bit_depth} bits"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_reference_decoders_at_8_bits() {
+        // Covers overlap on (2) and off (1, 12), lags 2 (1) and 3 (2, 4),
+        // explicit chroma points (1, 4, 5), luma only grain (6, 13, 14),
+        // chroma scaling from luma (15) and grain_scale_shift (3, 16).
+        assert_matches_reference::<u8>(
+            8,
+            &[
+                (
+                    1,
+                    [0x44f3051f67a7219b, 0x1531bab1540feef0, 0x02533eedf095f84f],
+                ),
+                (
+                    2,
+                    [0xb0faed7f2ad4d187, 0x124232195b63c86b, 0xd06b82ab29d346b0],
+                ),
+                (
+                    3,
+                    [0x16fa20241436b687, 0xf4864c5dcdb6083b, 0xb91c86ed95d4bb50],
+                ),
+                (
+                    4,
+                    [0x7a1d6f100edc4a55, 0xd3596f81db9e46a7, 0x40cd71a1e0c5e56c],
+                ),
+                (
+                    5,
+                    [0xc713f6801771b702, 0xdb9e4118ce9554d1, 0x1feb3728ae0dc7e6],
+                ),
+                (
+                    6,
+                    [0x841f6847c315da01, 0x541a1ca3f2a65e4f, 0x53312b270b8f9023],
+                ),
+                (
+                    7,
+                    [0xd3b7f98fc5b9cb86, 0xc9e478c1646aec35, 0x80d10550b8a2b241],
+                ),
+                (
+                    8,
+                    [0xb0faed7f2ad4d187, 0xecd24f17f454bcb9, 0x839745b193353b7f],
+                ),
+                (
+                    9,
+                    [0x0bed37d9df1caa61, 0xb757c5f02bd3bf5c, 0xcf8b6eedfa26f574],
+                ),
+                (
+                    10,
+                    [0x0bed37d9df1caa61, 0xc7b03f65db711e21, 0x0a9a25d782ad319f],
+                ),
+                (
+                    11,
+                    [0x4d2fb4c5d11a7d27, 0x8510c192e0a2a9d0, 0xf7af6b2b489d3f99],
+                ),
+                (
+                    12,
+                    [0xb47ff7375a72932d, 0x78bfa2e4e492323c, 0xc08597e8c9443287],
+                ),
+                (
+                    13,
+                    [0x9fd574263ed1828b, 0x541a1ca3f2a65e4f, 0x53312b270b8f9023],
+                ),
+                (
+                    14,
+                    [0xd44af3d6cf5065e2, 0x541a1ca3f2a65e4f, 0x53312b270b8f9023],
+                ),
+                (
+                    15,
+                    [0x30a02f435e0fedd0, 0x067c7333b446e5e3, 0x903785f28fb61882],
+                ),
+                (
+                    16,
+                    [0x7d5de2e681999860, 0xb9d36cc54fba74ed, 0x3730e3da76876c1c],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn matches_reference_decoders_at_10_bits() {
+        // Besides the 8-bit cases, this covers the interpolation of the
+        // scaling functions between their 8-bit points (1, 4, 12, 16) and
+        // grain_scale_shift (3, 16) at a higher bit depth.
+        assert_matches_reference::<u16>(
+            10,
+            &[
+                (
+                    1,
+                    [0x5cc0c6ab4c3dcac5, 0x71c07615783066af, 0x58370fda0961e65c],
+                ),
+                (
+                    2,
+                    [0xfae726d204a7aac6, 0x84717e2d88329d65, 0xd929a6dfa04bcd1d],
+                ),
+                (
+                    3,
+                    [0x53050efea4e2be8f, 0x1e216435b65dea65, 0x43b1d305a16b5615],
+                ),
+                (
+                    4,
+                    [0xa8855568f25524d9, 0xc9adcf79745e0e53, 0x30433399305f846c],
+                ),
+                (
+                    5,
+                    [0xed1a2e88c13ff075, 0xbe77deb2f0329fa7, 0xec1f9e9fcbca9842],
+                ),
+                (
+                    6,
+                    [0xbe21990184156487, 0x3ede08aae1cf68ef, 0xc8a8733db042db22],
+                ),
+                (
+                    7,
+                    [0xea8971a0d5eb1445, 0xd8dd147f0c02d3cc, 0x1c191fff61f07b82],
+                ),
+                (
+                    8,
+                    [0xfae726d204a7aac6, 0x8008f29d8469ff31, 0x2c3cfac103b1e464],
+                ),
+                (
+                    9,
+                    [0xdeccc2807e2ee77a, 0x565337ce8c705ea8, 0xb920e1050703a39f],
+                ),
+                (
+                    10,
+                    [0xdeccc2807e2ee77a, 0x9457ad0221530674, 0x83d529cd8b7634ae],
+                ),
+                (
+                    11,
+                    [0x67958b487b11c22e, 0x4d8623b5ca95d0c2, 0x11eabf82c006df66],
+                ),
+                (
+                    12,
+                    [0xf6cb0f0d2d2dcd90, 0x94b55d3768e4ac30, 0x717ae2fdd1b09566],
+                ),
+                (
+                    13,
+                    [0xcbed801fd173551c, 0x3ede08aae1cf68ef, 0xc8a8733db042db22],
+                ),
+                (
+                    14,
+                    [0xf02e05932ff38150, 0x3ede08aae1cf68ef, 0xc8a8733db042db22],
+                ),
+                (
+                    15,
+                    [0x10d0ccf2dd0446f0, 0xfe7032bd9039a0fc, 0x97f1eed715ef4ac1],
+                ),
+                (
+                    16,
+                    [0x54a7240893f595c9, 0x1f6839c3810456fd, 0xa47f74285d873058],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn matches_reference_decoders_at_12_bits() {
+        assert_matches_reference::<u16>(
+            12,
+            &[
+                (
+                    1,
+                    [0xc4bb6c84c00ed795, 0x75d7ebf066807421, 0x7f592e42675ee92f],
+                ),
+                (
+                    2,
+                    [0x8c55f66a2eac30e7, 0x342e9788e734b13f, 0x8ec9da388af0735c],
+                ),
+                (
+                    3,
+                    [0xdb6657d7d20abc7c, 0x998c983b34354c9c, 0x0986b57aa9b502ed],
+                ),
+                (
+                    4,
+                    [0x94a3d976ced42982, 0xf8371cdc5b0c1202, 0x0b435d425f65840b],
+                ),
+                (
+                    5,
+                    [0x3c6aa2e955e4ae16, 0xf1e4289ca52cf2fc, 0xb4d5afb2b5ed3670],
+                ),
+                (
+                    6,
+                    [0xb61134513d203f5a, 0x3b24514e8e376c07, 0xcbd452b325654ca6],
+                ),
+                (
+                    7,
+                    [0xe043a97ffbc4ccc8, 0x89b12d1f4e38396d, 0x86d79dcc29569cea],
+                ),
+                (
+                    8,
+                    [0x8c55f66a2eac30e7, 0xf9aee0eff34de04b, 0x5285151f97e8785f],
+                ),
+                (
+                    9,
+                    [0x91c843e1ba332497, 0x9ede37eadb28a13e, 0x5d795f90735461c4],
+                ),
+                (
+                    10,
+                    [0x91c843e1ba332497, 0x0067507fe85e196f, 0x3f7ed6c0f0433f12],
+                ),
+                (
+                    11,
+                    [0x734dcaa48c66421a, 0x45810a15352514e9, 0x034635ac2d125f9f],
+                ),
+                (
+                    12,
+                    [0x7df429b8e74e0512, 0x6472eac058fc0dbc, 0xd2cb1a8dcaf53af8],
+                ),
+                (
+                    13,
+                    [0x766337672997ff72, 0x3b24514e8e376c07, 0xcbd452b325654ca6],
+                ),
+                (
+                    14,
+                    [0x3d147f2f275e5dd0, 0x3b24514e8e376c07, 0xcbd452b325654ca6],
+                ),
+                (
+                    15,
+                    [0x22b310d02d6795e8, 0x9a77061cc5bc254c, 0xa985fbd63edce807],
+                ),
+                (
+                    16,
+                    [0x6454393d274cebc4, 0xe973dae1035e468d, 0x60464180e2a102d1],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn no_scaling_points_leaves_frame_unchanged() {
+        let segment = GrainTableSegment {
+            scaling_points_y: ArrayVec::new(),
+            ..luma_segment()
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        apply_grain(&segment, &mut frame).expect("valid segment");
+        assert_eq!(frame, flat_frame(ChromaSubsampling::Yuv420, 8, 128));
+    }
+
+    #[test]
+    fn disabled_segment_leaves_frame_unchanged() {
+        let segment = GrainTableSegment {
+            apply_grain: false,
+            scaling_shift: 0,
+            ..luma_segment()
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        apply_grain(&segment, &mut frame).expect("disabled segments are not validated");
+        assert_eq!(frame, flat_frame(ChromaSubsampling::Yuv420, 8, 128));
+    }
+
+    #[test]
+    fn luma_grain_leaves_chroma_unchanged() {
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        apply_grain(&luma_segment(), &mut frame).expect("valid segment");
+
+        let original = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        assert_ne!(frame.y_plane, original.y_plane);
+        assert_eq!(frame.u_plane, original.u_plane);
+        assert_eq!(frame.v_plane, original.v_plane);
+    }
+
+    #[test]
+    fn chroma_scaling_from_luma_applies_grain_to_chroma() {
+        let segment = GrainTableSegment {
+            chroma_scaling_from_luma: true,
+            ..luma_segment()
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        apply_grain(&segment, &mut frame).expect("valid segment");
+
+        let original = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        assert_ne!(frame.u_plane, original.u_plane);
+        assert_ne!(frame.v_plane, original.v_plane);
+        assert_ne!(frame.u_plane, frame.v_plane);
+    }
+
+    #[test]
+    fn first_sample_comes_from_random_template_offset() {
+        let segment = luma_segment();
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        apply_grain(&segment, &mut frame).expect("valid segment");
+
+        let expected = 128 + first_sample_noise(&segment, 8);
+        assert_eq!(frame.y_plane.pixel(0, 0), Some(expected as u8));
+    }
+
+    #[test]
+    fn high_bit_depth_grain_is_scaled_to_bit_depth() {
+        let segment = GrainTableSegment {
+            grain_scale_shift: 1,
+            ..luma_segment()
+        };
+        for bit_depth in [10, 12] {
+            let mid_grey = 128 << (bit_depth - 8);
+            let mut frame = flat_frame::<u16>(ChromaSubsampling::Yuv420, bit_depth, mid_grey);
+            apply_grain(&segment, &mut frame).expect("valid segment");
+
+            let expected = i32::from(mid_grey) + first_sample_noise(&segment, bit_depth);
+            assert_eq!(
+                frame.y_plane.pixel(0, 0),
+                Some(expected as u16),
+                "bit_depth={bit_depth}"
+            );
+        }
+    }
+
+    #[test]
+    fn clip_to_restricted_range_clips_to_studio_range() {
+        let min_pixel = |frame: &Frame<u8>| {
+            [Some(&frame.y_plane), frame.u_plane.as_ref()]
+                .into_iter()
+                .flatten()
+                .flat_map(|plane| plane.rows().flatten().copied())
+                .min()
+                .expect("the frame is not empty")
+        };
+        let segment = GrainTableSegment {
+            chroma_scaling_from_luma: true,
+            ..luma_segment()
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 16);
+        apply_grain(&segment, &mut frame).expect("valid segment");
+        assert!(min_pixel(&frame) < 16);
+
+        let segment = GrainTableSegment {
+            clip_to_restricted_range: true,
+            ..segment
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 16);
+        apply_grain(&segment, &mut frame).expect("valid segment");
+        assert_eq!(min_pixel(&frame), 16);
+    }
+
+    #[test]
+    fn rejects_unsupported_bit_depth() {
+        let mut frame = flat_frame::<u16>(ChromaSubsampling::Yuv420, 9, 256);
+        assert!(apply_grain(&luma_segment(), &mut frame).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_parameters() {
+        let segment = GrainTableSegment {
+            ar_coeff_lag: 4,
+            ..luma_segment()
+        };
+        let mut frame = flat_frame::<u8>(ChromaSubsampling::Yuv420, 8, 128);
+        assert!(apply_grain(&segment, &mut frame).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use super::{pixel_max, round2};
+use crate::GrainTableSegment;
+
+/// The piecewise-linear scaling functions for each plane of a
+/// [`GrainTableSegment`], evaluated at every 8-bit input value.
+///
+/// The scaling function gives the strength of the grain applied to a
+/// pixel as a function of its value. For chroma planes, the function is
+/// indexed by a combination of the chroma value and the co-located luma
+/// value, unless `chroma_scaling_from_luma` is set, in which case the luma
+/// scaling function is used with the luma value alone.
+#[derive(Debug, Clone)]
+pub struct ScalingLuts {
+    y: [u8; 256],
+    cb: ChromaScaling,
+    cr: ChromaScaling,
+}
+
+impl ScalingLuts {
+    /// Builds the scaling lookup tables for each plane of `segment`,
+    /// following the scaling lookup initialization process of the AV1
+    /// specification. Planes without scaling points have no grain,
+    /// and a scaling function which is zero everywhere.
+    #[inline]
+    #[must_use]
+    pub fn new(segment: &GrainTableSegment) -> Self {
+        let y = scaling_lut(&segment.scaling_points_y);
+        let (cb, cr) = if segment.chroma_scaling_from_luma {
+            (
+                ChromaScaling {
+                    lut: y,
+                    multipliers: None,
+                },
+                ChromaScaling {
+                    lut: y,
+                    multipliers: None,
+                },
+            )
+        } else {
+            (
+                ChromaScaling {
+                    lut: scaling_lut(&segment.scaling_points_cb),
+                    multipliers: Some(ChromaMultipliers {
+                        mult: segment.cb_mult,
+                        luma_mult: segment.cb_luma_mult,
+                        offset: segment.cb_offset,
+                    }),
+                },
+                ChromaScaling {
+                    lut: scaling_lut(&segment.scaling_points_cr),
+                    multipliers: Some(ChromaMultipliers {
+                        mult: segment.cr_mult,
+                        luma_mult: segment.cr_luma_mult,
+                        offset: segment.cr_offset,
+                    }),
+                },
+            )
+        };
+
+        Self { y, cb, cr }
+    }
+
+    /// The lookup table of the luma scaling function.
+    #[inline]
+    #[must_use]
+    pub const fn y_lut(&self) -> &[u8; 256] {
+        &self.y
+    }
+
+    /// The lookup table of the Cb scaling function.
+    #[inline]
+    #[must_use]
+    pub const fn cb_lut(&self) -> &[u8; 256] {
+        &self.cb.lut
+    }
+
+    /// The lookup table of the Cr scaling function.
+    #[inline]
+    #[must_use]
+    pub const fn cr_lut(&self) -> &[u8; 256] {
+        &self.cr.lut
+    }
+
+    /// Evaluates the luma scaling function for a pixel `value` at
+    /// `bit_depth`. For high bit depths, the result is interpolated
+    /// between the two nearest entries of the lookup table.
+    ///
+    /// Values above the maximum for `bit_depth` are clamped.
+    ///
+    /// # Panics
+    ///
+    /// - If `bit_depth` is not 8, 10 or 12
+    #[inline]
+    #[must_use]
+    pub fn scale_y(&self, value: u16, bit_depth: u8) -> u8 {
+        assert_bit_depth(bit_depth);
+        scale(&self.y, clamp_pixel(value, bit_depth), bit_depth) as u8
+    }
+
+    /// Evaluates the Cb scaling function for a pixel with chroma value `cb`
+    /// and co-located luma value `luma`, both at `bit_depth`. When chroma is
+    /// horizontally subsampled, `luma` is the rounded average of the two
+    /// co-located luma values.
+    ///
+    /// Values above the maximum for `bit_depth` are clamped.
+    ///
+    /// # Panics
+    ///
+    /// - If `bit_depth` is not 8, 10 or 12
+    #[inline]
+    #[must_use]
+    pub fn scale_cb(&self, luma: u16, cb: u16, bit_depth: u8) -> u8 {
+        self.scale_chroma(&self.cb, luma, cb, bit_depth)
+    }
+
+    /// Evaluates the Cr scaling function for a pixel with chroma value `cr`
+    /// and co-located luma value `luma`. See [`scale_cb`] for details.
+    ///
+    /// # Panics
+    ///
+    /// - If `bit_depth` is not 8, 10 or 12
+    ///
+    /// [`scale_cb`]: Self::scale_cb
+    #[inline]
+    #[must_use]
+    pub fn scale_cr(&self, luma: u16, cr: u16, bit_depth: u8) -> u8 {
+        self.scale_chroma(&self.cr, luma, cr, bit_depth)
+    }
+
+    /// Returns the index into the Cb scaling function for a pixel with
+    /// chroma value `cb` and co-located luma value `luma`, combined using
+    /// `cb_mult`, `cb_luma_mult` and `cb_offset`. With
+    /// `chroma_scaling_from_luma`, this is the luma value.
+    ///
+    /// # Panics
+    ///
+    /// - If `bit_depth` is not 8, 10 or 12
+    #[inline]
+    #[must_use]
+    pub fn cb_index(&self, luma: u16, cb: u16, bit_depth: u8) -> u16 {
+        assert_bit_depth(bit_depth);
+        self.cb.index(
+            clamp_pixel(luma, bit_depth),
+            clamp_pixel(cb, bit_depth),
+            bit_depth,
+        ) as u16
+    }
+
+    /// Returns the index into the Cr scaling function for a pixel with
+    /// chroma value `cr` and co-located luma value `luma`. See [`cb_index`]
+    /// for details.
+    ///
+    /// # Panics
+    ///
+    /// - If `bit_depth` is not 8, 10 or 12
+    ///
+    /// [`cb_index`]: Self::cb_index
+    #[inline]
+    #[must_use]
+    pub fn cr_index(&self, luma: u16, cr: u16, bit_depth: u8) -> u16 {
+        assert_bit_depth(bit_depth);
+        self.cr.index(
+            clamp_pixel(luma, bit_depth),
+            clamp_pixel(cr, bit_depth),
+            bit_depth,
+        ) as u16
+    }
+
+    pub(super) const fn cb(&self) -> &ChromaScaling {
+        &self.cb
+    }
+
+    pub(super) const fn cr(&self) -> &ChromaScaling {
+        &self.cr
+    }
+
+    fn scale_chroma(&self, plane: &ChromaScaling, luma: u16, chroma: u16, bit_depth: u8) -> u8 {
+        assert_bit_depth(bit_depth);
+        plane.scale(
+            clamp_pixel(luma, bit_depth),
+            clamp_pixel(chroma, bit_depth),
+            bit_depth,
+        ) as u8
+    }
+}
+
+/// The scaling function of a chroma plane, and how it is indexed.
+#[derive(Debug, Clone)]
+pub(super) struct ChromaScaling {
+    lut: [u8; 256],
+    /// `None` if the plane is scaled based on luma alone.
+    multipliers: Option<ChromaMultipliers>,
+}
+
+impl ChromaScaling {
+    /// Returns the index into the scaling function for a chroma value
+    /// and its co-located luma value.
+    pub fn index(&self, luma: i32, chroma: i32, bit_depth: u8) -> i32 {
+        self.multipliers.map_or(luma, |multipliers| {
+            multipliers.combine(luma, chroma, bit_depth)
+        })
+    }
+
+    /// Evaluates the scaling function for a chroma value and its co-located
+    /// luma value.
+    pub fn scale(&self, luma: i32, chroma: i32, bit_depth: u8) -> i32 {
+        scale(&self.lut, self.index(luma, chroma, bit_depth), bit_depth)
+    }
+}
+
+/// The parameters used to derive the scaling function index
+/// for a chroma plane from its own value and the co-located luma value.
+#[derive(Debug, Clone, Copy)]
+struct ChromaMultipliers {
+    mult: u8,
+    luma_mult: u8,
+    offset: u16,
+}
+
+impl ChromaMultipliers {
+    /// Combines a chroma value with its co-located luma value into
+    /// an index into the chroma scaling function.
+    fn combine(self, luma: i32, chroma: i32, bit_depth: u8) -> i32 {
+        let combined =
+            luma * (i32::from(self.luma_mult) - 128) + chroma * (i32::from(self.mult) - 128);
+        let offset = (i32::from(self.offset) - 256) << (bit_depth - 8);
+        ((combined >> 6) + offset).clamp(0, pixel_max(bit_depth))
+    }
+}
+
+/// Evaluates the scaling function at `index`, a pixel value at `bit_depth`.
+/// For high bit depths, the value is linearly interpolated between the
+/// two nearest entries of the 8-bit lookup table.
+pub(super) fn scale(lut: &[u8; 256], index: i32, bit_depth: u8) -> i32 {
+    let shift = u32::from(bit_depth - 8);
+    let x = (index >> shift) as usize;
+    let rem = index - ((x as i32) << shift);
+    let start = i32::from(*lut.get(x).expect("index is within the bit depth"));
+    if bit_depth == 8 || x == 255 {
+        return start;
+    }
+
+    let end = i32::from(*lut.get(x + 1).expect("index is below 255"));
+    start + round2((end - start) * rem, shift)
+}
+
+fn assert_bit_depth(bit_depth: u8) {
+    assert!(
+        matches!(bit_depth, 8 | 10 | 12),
+        "bit depth must be 8, 10 or 12, got {bit_depth}"
+    );
+}
+
+fn clamp_pixel(value: u16, bit_depth: u8) -> i32 {
+    i32::from(value).min(pixel_max(bit_depth))
+}
+
+/// Interpolates `points` into a lookup table, as described by the
+/// scaling lookup initialization process of the AV1 specification.
+fn scaling_lut(points: &[[u8; 2]]) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    let (Some(&[first_x, first_y]), Some(&[last_x, last_y])) = (points.first(), points.last())
+    else {
+        return lut;
+    };
+
+    for value in lut.iter_mut().take(usize::from(first_x)) {
+        *value = first_y;
+    }
+
+    for pair in points.windows(2) {
+        let [[x0, y0], [x1, y1]] = pair else {
+            unreachable!("windows(2) only yields two-point windows");
+        };
+        let delta_x = i32::from(*x1) - i32::from(*x0);
+        if delta_x <= 0 {
+            continue;
+        }
+        let delta_y = i32::from(*y1) - i32::from(*y0);
+        let delta = delta_y * ((65536 + (delta_x >> 1)) / delta_x);
+        for (x, value) in lut
+            .iter_mut()
+            .skip(usize::from(*x0))
+            .take(delta_x as usize)
+            .enumerate()
+        {
+            *value = (i32::from(*y0) + ((x as i32 * delta + 32768) >> 16)) as u8;
+        }
+    }
+
+    for value in lut.iter_mut().skip(usize::from(last_x)) {
+        *value = last_y;
+    }
+
+    lut
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            end_time: 100,
+            scaling_points_y: ArrayVec::from_iter([[20, 10], [120, 60], [200, 20]]),
+            scaling_points_cb: ArrayVec::from_iter([[0, 30], [255, 30]]),
+            cb_mult: 128 + 64,
+            cb_luma_mult: 128,
+            cb_offset: 256 + 16,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    #[test]
+    fn lut_extends_end_points_and_interpolates() {
+        let luts = ScalingLuts::new(&segment());
+        let lut = luts.y_lut();
+
+        assert_eq!(lut[0], 10);
+        assert_eq!(lut[20], 10);
+        assert_eq!(lut[70], 35);
+        assert_eq!(lut[120], 60);
+        assert_eq!(lut[160], 40);
+        assert_eq!(lut[200], 20);
+        assert_eq!(lut[255], 20);
+        assert!(luts.cr_lut().iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn chroma_index_combines_luma_and_chroma() {
+        let luts = ScalingLuts::new(&segment());
+
+        // A chroma multiplier of 1.0, a luma multiplier of 0.0 and an
+        // offset of 16, scaled to the bit depth.
+        assert_eq!(luts.cb_index(200, 100, 8), 116);
+        assert_eq!(luts.cb_index(800, 400, 10), 464);
+        assert_eq!(luts.cb_index(0, 255, 8), 255);
+        assert_eq!(luts.scale_cb(200, 100, 8), 30);
+    }
+
+    #[test]
+    fn chroma_scaling_from_luma_uses_luma_function() {
+        let segment = GrainTableSegment {
+            chroma_scaling_from_luma: true,
+            ..segment()
+        };
+        let luts = ScalingLuts::new(&segment);
+
+        assert_eq!(luts.cb_lut(), luts.y_lut());
+        assert_eq!(luts.cr_lut(), luts.y_lut());
+        assert_eq!(luts.cr_index(70, 255, 8), 70);
+        assert_eq!(luts.scale_cr(70, 255, 8), luts.scale_y(70, 8));
+    }
+
+    #[test]
+    fn high_bit_depth_scaling_interpolates_lut() {
+        let mut lut = [0; 256];
+        lut[100] = 10;
+        lut[101] = 20;
+        lut[255] = 40;
+
+        assert_eq!(scale(&lut, 100, 8), 10);
+        assert_eq!(scale(&lut, 100 << 2, 10), 10);
+        assert_eq!(scale(&lut, (100 << 2) + 2, 10), 15);
+        assert_eq!(scale(&lut, (100 << 4) + 4, 12), 13);
+        assert_eq!(scale(&lut, (255 << 2) + 3, 10), 40);
+    }
+}
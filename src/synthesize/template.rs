@@ -0,0 +1,383 @@
+use arrayvec::ArrayVec;
+use v_frame::chroma::ChromaSubsampling;
+
+use super::{grain_max, grain_min, round2, subsampling_factors};
+use crate::{
+    GrainTableSegment, NUM_UV_COEFFS,
+    random::{RandomGenerator, template_seed},
+};
+
+/// The width of the luma grain template.
+pub const GRAIN_TEMPLATE_WIDTH: usize = 82;
+/// The height of the luma grain template.
+pub const GRAIN_TEMPLATE_HEIGHT: usize = 73;
+/// The number of samples on the top, left and right edges of a template
+/// which are not filtered by the auto-regressive filter.
+const AR_PADDING: usize = 3;
+
+/// Storage for a single grain template. Chroma templates use the same
+/// storage, but only the top-left `chroma_width x chroma_height` samples
+/// are meaningful.
+pub(super) type GrainBlock = [[i16; GRAIN_TEMPLATE_WIDTH]; GRAIN_TEMPLATE_HEIGHT];
+
+/// The grain templates generated for a [`GrainTableSegment`], after the
+/// auto-regressive filter has been applied. These are the textures which
+/// are sampled from, scaled and blended onto each plane of a frame.
+///
+/// Templates for planes without grain are filled with zeros.
+#[derive(Debug, Clone)]
+pub struct GrainTemplates {
+    pub(super) luma: Box<GrainBlock>,
+    pub(super) cb: Box<GrainBlock>,
+    pub(super) cr: Box<GrainBlock>,
+    chroma_width: usize,
+    chroma_height: usize,
+}
+
+impl GrainTemplates {
+    pub(super) fn new(
+        segment: &GrainTableSegment,
+        subsampling: ChromaSubsampling,
+        bit_depth: u8,
+    ) -> Self {
+        let luma = generate_luma_grain(segment, bit_depth);
+        let (chroma_width, chroma_height) = match subsampling {
+            ChromaSubsampling::Monochrome => (0, 0),
+            _ => {
+                let (sub_x, sub_y) = subsampling_factors(subsampling);
+                (
+                    if sub_x { 44 } else { GRAIN_TEMPLATE_WIDTH },
+                    if sub_y { 38 } else { GRAIN_TEMPLATE_HEIGHT },
+                )
+            }
+        };
+        let cb = generate_chroma_grain(
+            segment,
+            &luma,
+            (chroma_width, chroma_height),
+            bit_depth,
+            1,
+            !segment.scaling_points_cb.is_empty(),
+            &segment.ar_coeffs_cb,
+        );
+        let cr = generate_chroma_grain(
+            segment,
+            &luma,
+            (chroma_width, chroma_height),
+            bit_depth,
+            2,
+            !segment.scaling_points_cr.is_empty(),
+            &segment.ar_coeffs_cr,
+        );
+
+        Self {
+            luma,
+            cb,
+            cr,
+            chroma_width,
+            chroma_height,
+        }
+    }
+
+    /// The luma template, which is always
+    /// `GRAIN_TEMPLATE_WIDTH x GRAIN_TEMPLATE_HEIGHT` samples.
+    #[inline]
+    #[must_use]
+    pub fn luma(&self) -> &[[i16; GRAIN_TEMPLATE_WIDTH]; GRAIN_TEMPLATE_HEIGHT] {
+        &self.luma
+    }
+
+    /// The rows of the Cb template, each [`chroma_width`] samples long.
+    ///
+    /// [`chroma_width`]: Self::chroma_width
+    #[inline]
+    pub fn cb(&self) -> impl ExactSizeIterator<Item = &[i16]> + '_ {
+        self.chroma_rows(&self.cb)
+    }
+
+    /// The rows of the Cr template, each [`chroma_width`] samples long.
+    ///
+    /// [`chroma_width`]: Self::chroma_width
+    #[inline]
+    pub fn cr(&self) -> impl ExactSizeIterator<Item = &[i16]> + '_ {
+        self.chroma_rows(&self.cr)
+    }
+
+    /// The width of the chroma templates. This is 44 for horizontally
+    /// subsampled chroma, 82 otherwise, and 0 for monochrome.
+    #[inline]
+    #[must_use]
+    pub const fn chroma_width(&self) -> usize {
+        self.chroma_width
+    }
+
+    /// The height of the chroma templates. This is 38 for vertically
+    /// subsampled chroma, 73 otherwise, and 0 for monochrome.
+    #[inline]
+    #[must_use]
+    pub const fn chroma_height(&self) -> usize {
+        self.chroma_height
+    }
+
+    fn chroma_rows<'a>(
+        &self,
+        template: &'a GrainBlock,
+    ) -> impl ExactSizeIterator<Item = &'a [i16]> + 'a {
+        let width = self.chroma_width;
+        template
+            .iter()
+            .take(self.chroma_height)
+            .map(move |row| row.get(..width).unwrap_or_default())
+    }
+}
+
+fn generate_luma_grain(segment: &GrainTableSegment, bit_depth: u8) -> Box<GrainBlock> {
+    let mut grain = Box::new([[0i16; GRAIN_TEMPLATE_WIDTH]; GRAIN_TEMPLATE_HEIGHT]);
+    if segment.scaling_points_y.is_empty() {
+        return grain;
+    }
+
+    // The Gaussian sequence has 12-bit precision, so it is scaled down
+    // to the bit depth of the frame.
+    let shift = u32::from(12 - bit_depth + segment.grain_scale_shift);
+    let mut rng = RandomGenerator::new(template_seed(segment.random_seed, 0));
+    for value in grain.iter_mut().flatten() {
+        *value = round2(i32::from(rng.next_gaussian()), shift) as i16;
+    }
+
+    let lag = usize::from(segment.ar_coeff_lag);
+    let taps = ar_taps(lag);
+    let coeffs = padded_coeffs(&segment.ar_coeffs_y);
+    let shift = u32::from(segment.ar_coeff_shift);
+    for y in AR_PADDING..GRAIN_TEMPLATE_HEIGHT {
+        for x in AR_PADDING..(GRAIN_TEMPLATE_WIDTH - AR_PADDING) {
+            let sum: i32 = taps
+                .iter()
+                .zip(coeffs)
+                .map(|(&(dy, dx), coeff)| coeff * sample(&grain, y - dy, x.wrapping_add_signed(dx)))
+                .sum();
+            let value = sample(&grain, y, x) + round2(sum, shift);
+            set_sample(
+                &mut grain,
+                y,
+                x,
+                value.clamp(grain_min(bit_depth), grain_max(bit_depth)),
+            );
+        }
+    }
+
+    grain
+}
+
+fn generate_chroma_grain(
+    segment: &GrainTableSegment,
+    luma: &GrainBlock,
+    (chroma_width, chroma_height): (usize, usize),
+    bit_depth: u8,
+    plane: usize,
+    has_points: bool,
+    coeffs: &[i8],
+) -> Box<GrainBlock> {
+    let mut grain = Box::new([[0i16; GRAIN_TEMPLATE_WIDTH]; GRAIN_TEMPLATE_HEIGHT]);
+    if chroma_width == 0 || (!has_points && !segment.chroma_scaling_from_luma) {
+        return grain;
+    }
+
+    // The Gaussian sequence has 12-bit precision, so it is scaled down
+    // to the bit depth of the frame.
+    let shift = u32::from(12 - bit_depth + segment.grain_scale_shift);
+    let mut rng = RandomGenerator::new(template_seed(segment.random_seed, plane));
+    for row in grain.iter_mut().take(chroma_height) {
+        for value in row.iter_mut().take(chroma_width) {
+            *value = round2(i32::from(rng.next_gaussian()), shift) as i16;
+        }
+    }
+
+    let lag = usize::from(segment.ar_coeff_lag);
+    let taps = ar_taps(lag);
+    let coeffs = padded_coeffs(coeffs);
+    let luma_coeff = coeffs.get(taps.len()).copied().unwrap_or(0);
+    let has_luma = !segment.scaling_points_y.is_empty();
+    let sub_x = usize::from(chroma_width < GRAIN_TEMPLATE_WIDTH);
+    let sub_y = usize::from(chroma_height < GRAIN_TEMPLATE_HEIGHT);
+    let shift = u32::from(segment.ar_coeff_shift);
+    for y in AR_PADDING..chroma_height {
+        for x in AR_PADDING..(chroma_width - AR_PADDING) {
+            let mut sum: i32 = taps
+                .iter()
+                .zip(coeffs)
+                .map(|(&(dy, dx), coeff)| coeff * sample(&grain, y - dy, x.wrapping_add_signed(dx)))
+                .sum();
+            if has_luma {
+                let luma_x = ((x - AR_PADDING) << sub_x) + AR_PADDING;
+                let luma_y = ((y - AR_PADDING) << sub_y) + AR_PADDING;
+                let mut luma_sum = 0;
+                for i in 0..=sub_y {
+                    for j in 0..=sub_x {
+                        luma_sum += sample(luma, luma_y + i, luma_x + j);
+                    }
+                }
+                sum += round2(luma_sum, (sub_x + sub_y) as u32) * luma_coeff;
+            }
+            let value = sample(&grain, y, x) + round2(sum, shift);
+            set_sample(
+                &mut grain,
+                y,
+                x,
+                value.clamp(grain_min(bit_depth), grain_max(bit_depth)),
+            );
+        }
+    }
+
+    grain
+}
+
+/// Returns the `(rows above, column offset)` pairs of the causal
+/// neighbourhood used by the auto-regressive filter, in the order
+/// the coefficients are signalled.
+fn ar_taps(lag: usize) -> ArrayVec<(usize, isize), NUM_UV_COEFFS> {
+    let lag = lag.min(AR_PADDING);
+    let mut taps = ArrayVec::new();
+    for dy in (0..=lag).rev() {
+        for dx in -(lag as isize)..=(lag as isize) {
+            if dy == 0 && dx == 0 {
+                return taps;
+            }
+            taps.push((dy, dx));
+        }
+    }
+    taps
+}
+
+/// Widens the AR coefficients, treating any missing coefficients as zero.
+fn padded_coeffs(coeffs: &[i8]) -> [i32; NUM_UV_COEFFS] {
+    let mut output = [0; NUM_UV_COEFFS];
+    for (output, &coeff) in output.iter_mut().zip(coeffs) {
+        *output = i32::from(coeff);
+    }
+    output
+}
+
+fn sample(grain: &GrainBlock, y: usize, x: usize) -> i32 {
+    debug_assert!(y < GRAIN_TEMPLATE_HEIGHT && x < GRAIN_TEMPLATE_WIDTH);
+    // SAFETY: Callers only access positions within the template. The AR
+    // filter never reaches past the padding because the lag is limited to 3.
+    i32::from(*unsafe { grain.get_unchecked(y).get_unchecked(x) })
+}
+
+fn set_sample(grain: &mut GrainBlock, y: usize, x: usize, value: i32) {
+    debug_assert!(y < GRAIN_TEMPLATE_HEIGHT && x < GRAIN_TEMPLATE_WIDTH);
+    // SAFETY: See `sample`.
+    *unsafe { grain.get_unchecked_mut(y).get_unchecked_mut(x) } = value as i16;
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    fn segment(ar_coeff_lag: u8, ar_coeffs_y: &[i8], ar_coeffs_cb: &[i8]) -> GrainTableSegment {
+        GrainTableSegment {
+            end_time: 100,
+            scaling_points_y: ArrayVec::from_iter([[0, 64], [255, 64]]),
+            scaling_points_cb: ArrayVec::from_iter([[0, 64], [255, 64]]),
+            ar_coeff_lag,
+            ar_coeffs_y: ar_coeffs_y.iter().copied().collect(),
+            ar_coeffs_cb: ar_coeffs_cb.iter().copied().collect(),
+            ar_coeff_shift: 7,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    /// The sum of the products of horizontally adjacent samples, within the
+    /// area of the template which is filtered.
+    fn horizontal_correlation<'a>(rows: impl Iterator<Item = &'a [i16]>) -> i64 {
+        rows.skip(AR_PADDING)
+            .flat_map(|row| {
+                row.windows(2)
+                    .skip(AR_PADDING)
+                    .map(|pair| pair.iter().map(|&v| i64::from(v)).product::<i64>())
+            })
+            .sum()
+    }
+
+    #[test]
+    fn chroma_dimensions_follow_subsampling() {
+        let segment = segment(0, &[], &[0]);
+        for (subsampling, width, height) in [
+            (ChromaSubsampling::Yuv420, 44, 38),
+            (ChromaSubsampling::Yuv422, 44, GRAIN_TEMPLATE_HEIGHT),
+            (
+                ChromaSubsampling::Yuv444,
+                GRAIN_TEMPLATE_WIDTH,
+                GRAIN_TEMPLATE_HEIGHT,
+            ),
+            (ChromaSubsampling::Monochrome, 0, 0),
+        ] {
+            let templates = GrainTemplates::new(&segment, subsampling, 8);
+            assert_eq!(templates.chroma_width(), width);
+            assert_eq!(templates.chroma_height(), height);
+            assert_eq!(templates.cb().len(), height);
+            assert!(templates.cb().all(|row| row.len() == width));
+        }
+    }
+
+    #[test]
+    fn unfiltered_luma_is_the_gaussian_sequence() {
+        let segment = segment(0, &[], &[0]);
+        let templates = GrainTemplates::new(&segment, ChromaSubsampling::Yuv420, 8);
+        let mut rng = RandomGenerator::new(template_seed(segment.random_seed, 0));
+        for &value in templates.luma().iter().flatten() {
+            assert_eq!(i32::from(value), round2(i32::from(rng.next_gaussian()), 4));
+        }
+    }
+
+    #[test]
+    fn planes_without_grain_are_zero() {
+        let segment = segment(0, &[], &[0]);
+        let templates = GrainTemplates::new(&segment, ChromaSubsampling::Yuv420, 8);
+        assert!(templates.cb().flatten().any(|&v| v != 0));
+        assert!(templates.cr().flatten().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn ar_filter_correlates_neighbours() {
+        // With a lag of 1, the coefficient of the sample to the left is the
+        // fourth one signalled.
+        let unfiltered = segment(1, &[0; 4], &[0; 5]);
+        let filtered = segment(1, &[0, 0, 0, 96], &[0, 0, 0, 96, 0]);
+        let unfiltered = GrainTemplates::new(&unfiltered, ChromaSubsampling::Yuv444, 8);
+        let filtered = GrainTemplates::new(&filtered, ChromaSubsampling::Yuv444, 8);
+
+        assert!(
+            horizontal_correlation(filtered.luma().iter().map(|row| &row[..]))
+                > 4 * horizontal_correlation(unfiltered.luma().iter().map(|row| &row[..])).abs()
+        );
+        assert!(
+            horizontal_correlation(filtered.cb())
+                > 4 * horizontal_correlation(unfiltered.cb()).abs()
+        );
+    }
+
+    #[test]
+    fn luma_tap_correlates_chroma_with_luma() {
+        // The final chroma coefficient applies to the co-located luma grain.
+        let segment = segment(0, &[], &[127]);
+        let templates = GrainTemplates::new(&segment, ChromaSubsampling::Yuv444, 8);
+        let correlation: i64 = templates
+            .luma()
+            .iter()
+            .zip(templates.cb())
+            .skip(AR_PADDING)
+            .flat_map(|(luma, cb)| {
+                luma.iter()
+                    .zip(cb)
+                    .skip(AR_PADDING)
+                    .take(GRAIN_TEMPLATE_WIDTH - 2 * AR_PADDING)
+            })
+            .map(|(&luma, &cb)| i64::from(luma) * i64::from(cb))
+            .sum();
+        assert!(correlation > 0);
+    }
+}
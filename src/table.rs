@@ -0,0 +1,332 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// A grain table which keeps its segments sorted and non-overlapping, so that
+// the segment of a frame can be found without a linear search.
+
+#[cfg(feature = "parse")]
+use std::str::FromStr;
+use std::{ops::Deref, ops::Range, slice, vec};
+
+use crate::{Error, GrainTableSegment};
+
+/// The end time of segments which last until the end of the video.
+///
+/// libaom reads timestamps as signed 64-bit integers, so this is the
+/// largest end time it accepts. [`GrainTable`] lowers later end times, such
+/// as `u64::MAX`, to it.
+pub const OPEN_END_TIME: u64 = i64::MAX as u64;
+
+/// A grain table, whose segments are sorted by start time and do not
+/// overlap.
+///
+/// It dereferences to a slice of its segments, so it can be passed to
+/// functions taking a grain table such as
+/// [`write_grain_table`](crate::write_grain_table), and it can be parsed
+/// with [`str::parse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrainTable {
+    segments: Vec<GrainTableSegment>,
+}
+
+impl GrainTable {
+    /// Creates a grain table from segments in any order.
+    ///
+    /// End times after [`OPEN_END_TIME`] are lowered to it.
+    ///
+    /// # Errors
+    ///
+    /// - If a segment starts before the previous one ends, once sorted by
+    ///   start time
+    #[inline]
+    pub fn new(mut segments: Vec<GrainTableSegment>) -> Result<Self, Error> {
+        for segment in &mut segments {
+            segment.end_time = segment.end_time.min(OPEN_END_TIME);
+        }
+        segments.sort_by_key(|segment| segment.start_time);
+        let mut previous_end = None;
+        for segment in &segments {
+            if let Some(previous_end) = previous_end
+                && segment.start_time < previous_end
+            {
+                return Err(Error::OverlappingSegments {
+                    start_time: segment.start_time,
+                    previous_end_time: previous_end,
+                });
+            }
+            previous_end = Some(segment.end_time);
+        }
+        Ok(Self { segments })
+    }
+
+    /// Returns the segments of the table, sorted by start time.
+    #[must_use]
+    #[inline]
+    pub fn segments(&self) -> &[GrainTableSegment] {
+        &self.segments
+    }
+
+    /// Returns the segments of the table, sorted by start time.
+    #[must_use]
+    #[inline]
+    pub fn into_segments(self) -> Vec<GrainTableSegment> {
+        self.segments
+    }
+
+    /// Adds a segment to the table, keeping it sorted.
+    ///
+    /// End times after [`OPEN_END_TIME`] are lowered to it.
+    ///
+    /// # Errors
+    ///
+    /// - If the segment overlaps a segment of the table, in which case the
+    ///   table is unchanged
+    #[inline]
+    pub fn insert(&mut self, mut segment: GrainTableSegment) -> Result<(), Error> {
+        segment.end_time = segment.end_time.min(OPEN_END_TIME);
+        let index = self
+            .segments
+            .partition_point(|other| other.start_time <= segment.start_time);
+        if let Some(previous) = index.checked_sub(1).and_then(|i| self.segments.get(i))
+            && segment.start_time < previous.end_time
+        {
+            return Err(Error::OverlappingSegments {
+                start_time: segment.start_time,
+                previous_end_time: previous.end_time,
+            });
+        }
+        if let Some(next) = self.segments.get(index)
+            && next.start_time < segment.end_time
+        {
+            return Err(Error::OverlappingSegments {
+                start_time: next.start_time,
+                previous_end_time: segment.end_time,
+            });
+        }
+        self.segments.insert(index, segment);
+        Ok(())
+    }
+
+    /// Returns the segment covering `timestamp`, in units of 10 MHz as in
+    /// the segments, or `None` if it falls in a gap of the table.
+    #[must_use]
+    #[inline]
+    pub fn segment_at(&self, timestamp: u64) -> Option<&GrainTableSegment> {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start_time <= timestamp);
+        index
+            .checked_sub(1)
+            .and_then(|index| self.segments.get(index))
+            .filter(|segment| timestamp < segment.end_time)
+    }
+
+    /// Returns the segment covering the frame at index `frame` of a video
+    /// shown at a constant `fps_num / fps_den` frames per second, or `None`
+    /// if it falls in a gap of the table or either part of the frame rate is
+    /// 0.
+    #[must_use]
+    #[inline]
+    pub fn segment_for_frame(
+        &self,
+        frame: u64,
+        fps_num: u64,
+        fps_den: u64,
+    ) -> Option<&GrainTableSegment> {
+        self.segment_at(Self::frame_timestamp(frame, fps_num, fps_den)?)
+    }
+
+    /// Returns the timestamp of the frame at index `frame` of a video shown
+    /// at a constant `fps_num / fps_den` frames per second, in units of
+    /// 10 MHz, or `None` if either part of the frame rate is 0 or the
+    /// timestamp does not fit in a `u64`.
+    #[must_use]
+    #[inline]
+    pub fn frame_timestamp(frame: u64, fps_num: u64, fps_den: u64) -> Option<u64> {
+        if fps_num == 0 || fps_den == 0 {
+            return None;
+        }
+        let timestamp = u128::from(frame) * 10_000_000 * u128::from(fps_den) / u128::from(fps_num);
+        u64::try_from(timestamp).ok()
+    }
+
+    /// Returns the time ranges from 0 to [`OPEN_END_TIME`] which no segment
+    /// covers, in increasing order.
+    #[must_use]
+    #[inline]
+    pub fn gaps(&self) -> Vec<Range<u64>> {
+        let mut gaps = Vec::new();
+        let mut covered = 0;
+        for segment in &self.segments {
+            if segment.start_time > covered {
+                gaps.push(covered..segment.start_time);
+            }
+            covered = covered.max(segment.end_time);
+        }
+        if covered < OPEN_END_TIME {
+            gaps.push(covered..OPEN_END_TIME);
+        }
+        gaps
+    }
+}
+
+impl Deref for GrainTable {
+    type Target = [GrainTableSegment];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.segments
+    }
+}
+
+impl TryFrom<Vec<GrainTableSegment>> for GrainTable {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(segments: Vec<GrainTableSegment>) -> Result<Self, Error> {
+        Self::new(segments)
+    }
+}
+
+impl From<GrainTable> for Vec<GrainTableSegment> {
+    #[inline]
+    fn from(table: GrainTable) -> Self {
+        table.segments
+    }
+}
+
+#[cfg(feature = "parse")]
+impl FromStr for GrainTable {
+    type Err = Error;
+
+    /// Parses a grain table with [`parse_grain_table`](crate::parse_grain_table).
+    #[inline]
+    fn from_str(input: &str) -> Result<Self, Error> {
+        Self::new(crate::parse_grain_table(input)?)
+    }
+}
+
+impl IntoIterator for GrainTable {
+    type Item = GrainTableSegment;
+    type IntoIter = vec::IntoIter<GrainTableSegment>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a GrainTable {
+    type Item = &'a GrainTableSegment;
+    type IntoIter = slice::Iter<'a, GrainTableSegment>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_time: u64, end_time: u64) -> GrainTableSegment {
+        GrainTableSegment {
+            start_time,
+            end_time,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    fn times(table: &GrainTable) -> Vec<(u64, u64)> {
+        table
+            .iter()
+            .map(|segment| (segment.start_time, segment.end_time))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_and_normalizes_segments() {
+        let table = GrainTable::new(vec![segment(20, u64::MAX), segment(0, 10)])
+            .expect("segments do not overlap");
+        assert_eq!(times(&table), [(0, 10), (20, OPEN_END_TIME)]);
+        assert_eq!(table.gaps(), vec![10..20]);
+
+        assert!(matches!(
+            GrainTable::new(vec![segment(10, 30), segment(0, 20)]),
+            Err(Error::OverlappingSegments {
+                start_time: 10,
+                previous_end_time: 20
+            })
+        ));
+    }
+
+    #[test]
+    fn inserts_segments_in_order() {
+        let mut table = GrainTable::default();
+        assert_eq!(table.gaps(), vec![0..OPEN_END_TIME]);
+        table.insert(segment(20, 30)).expect("table is empty");
+        table.insert(segment(0, 10)).expect("segment fits before");
+        table.insert(segment(10, 20)).expect("segment fits between");
+        assert!(table.insert(segment(25, 40)).is_err());
+        assert!(table.insert(segment(5, 15)).is_err());
+        assert_eq!(times(&table), [(0, 10), (10, 20), (20, 30)]);
+        assert_eq!(table.gaps(), vec![30..OPEN_END_TIME]);
+    }
+
+    #[test]
+    fn looks_up_segments() {
+        let table = GrainTable::new(vec![segment(0, 10), segment(20, 30), segment(30, 30)])
+            .expect("segments do not overlap");
+        let start = |timestamp| {
+            table
+                .segment_at(timestamp)
+                .map(|segment| segment.start_time)
+        };
+        assert_eq!(start(0), Some(0));
+        assert_eq!(start(9), Some(0));
+        assert_eq!(start(10), None);
+        assert_eq!(start(25), Some(20));
+        assert_eq!(start(30), None);
+        assert_eq!(start(u64::MAX), None);
+    }
+
+    #[test]
+    fn looks_up_frames() {
+        let table = GrainTable::new(vec![segment(0, 10_000_000), segment(10_000_000, u64::MAX)])
+            .expect("segments do not overlap");
+        assert_eq!(
+            GrainTable::frame_timestamp(24, 24_000, 1001),
+            Some(10_010_000)
+        );
+        assert_eq!(GrainTable::frame_timestamp(24, 0, 1), None);
+        assert_eq!(GrainTable::frame_timestamp(24, 24, 0), None);
+        assert_eq!(GrainTable::frame_timestamp(u64::MAX, 1, 1), None);
+        let start = |frame| {
+            table
+                .segment_for_frame(frame, 24_000, 1001)
+                .map(|segment| segment.start_time)
+        };
+        assert_eq!(start(23), Some(0));
+        assert_eq!(start(24), Some(10_000_000));
+        assert_eq!(start(u64::MAX), None);
+    }
+
+    #[cfg(all(feature = "parse", feature = "create"))]
+    #[test]
+    fn parses_and_writes_tables() {
+        let input = crate::grain_table_to_string(&[segment(0, 10), segment(10, u64::MAX)]);
+        let table: GrainTable = input.parse().expect("table is valid");
+        assert_eq!(times(&table), [(0, 10), (10, OPEN_END_TIME)]);
+        assert_eq!(
+            crate::grain_table_to_string(&table),
+            crate::grain_table_to_string(&[segment(0, 10), segment(10, OPEN_END_TIME)])
+        );
+    }
+}
@@ -1,5 +1,12 @@
 #[cfg(feature = "diff")]
 use std::{borrow::Cow, mem::size_of};
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+use std::{ffi::c_int, ops::RangeInclusive};
+
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+use anyhow::{Context, Result, ensure};
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+use arrayvec::ArrayVec;
 
 #[cfg(feature = "diff")]
 use v_frame::{frame::Frame, pixel::Pixel};
@@ -62,3 +69,95 @@ pub fn frame_into_u8<T: Pixel>(frame: &Frame<T>, bit_depth: usize) -> Cow<'_, Fr
         unimplemented!("Bit depths greater than 16 are not currently supported");
     }
 }
+
+/// Copies `src` to the start of `dst`, leaving the rest of `dst` unchanged.
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+pub fn copy_prefix<T: Copy>(dst: &mut [T], src: &[T]) {
+    for (dst, &src) in dst.iter_mut().zip(src) {
+        *dst = src;
+    }
+}
+
+/// Converts an integer field of a C struct to the type used by segments,
+/// checking that it is within `range`.
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+pub fn checked_field<T: TryFrom<c_int>>(
+    value: c_int,
+    range: RangeInclusive<c_int>,
+    name: &str,
+) -> Result<T> {
+    ensure!(
+        range.contains(&value),
+        "{name} must be between {} and {}, got {value}",
+        range.start(),
+        range.end()
+    );
+    T::try_from(value)
+        .ok()
+        .with_context(|| format!("{name} is out of range"))
+}
+
+/// Converts a field of a C struct which is centered on zero to the type used
+/// by segments, which store it as coded, offset by `center`.
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+pub fn centered_field<T: TryFrom<c_int>>(value: c_int, center: c_int, name: &str) -> Result<T> {
+    let value: c_int = checked_field(value, -center..=center - 1, name)?;
+    checked_field(value + center, 0..=2 * center - 1, name)
+}
+
+/// Takes the first `count` scaling points of `points`.
+#[cfg(any(feature = "dav1d", feature = "ffmpeg"))]
+pub fn scaling_points<const N: usize>(
+    points: &[[u8; 2]; N],
+    count: c_int,
+    name: &str,
+) -> Result<ArrayVec<[u8; 2], N>> {
+    let count = checked_field::<usize>(count, 0..=N as c_int, name)?;
+    Ok(points.iter().copied().take(count).collect())
+}
+
+/// The names of the values on the `p` line of a grain table, in order.
+#[cfg(any(feature = "create", feature = "parse"))]
+const P_FIELDS: [&str; 13] = [
+    "ar_coeff_lag",
+    "ar_coeff_shift",
+    "grain_scale_shift",
+    "scaling_shift",
+    "chroma_scaling_from_luma",
+    "overlap_flag",
+    "cb_mult",
+    "cb_luma_mult",
+    "cb_offset",
+    "cr_mult",
+    "cr_luma_mult",
+    "cr_offset",
+    "clip_to_restricted_range",
+];
+
+/// Returns the comment labelling the values of a grain table line with their
+/// names, which annotated tables end `p` and scaling point lines with, or
+/// `None` for other lines.
+#[cfg(any(feature = "create", feature = "parse"))]
+pub fn line_annotation(tag: &str, values: &[&str]) -> Option<String> {
+    let labels: Vec<String> = match tag {
+        "p" => P_FIELDS
+            .iter()
+            .zip(values)
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect(),
+        "sY" | "sCb" | "sCr" => {
+            let plane = tag.trim_start_matches('s').to_ascii_lowercase();
+            let (count, points) = values.split_first()?;
+            std::iter::once(format!("num_{plane}_points={count}"))
+                .chain(
+                    points
+                        .chunks_exact(2)
+                        .enumerate()
+                        .map(|(i, point)| format!("point_{plane}[{i}]=({})", point.join(","))),
+                )
+                .collect()
+        }
+        _ => return None,
+    };
+    Some(labels.join(" "))
+}
@@ -0,0 +1,397 @@
+// Copyright (c) 2022-2022, The rav1e contributors. All rights reserved
+//
+// This source code is subject to the terms of the BSD 2 Clause License and
+// the Alliance for Open Media Patent License 1.0. If the BSD 2 Clause License
+// was not distributed with this source code in the LICENSE file, you can
+// obtain it at www.aomedia.org/license/software. If the Alliance for Open
+// Media Patent License 1.0 was not distributed with this source code in the
+// PATENTS file, you can obtain it at www.aomedia.org/license/patent.
+
+// Checks of grain table segments against the constraints of the AV1
+// specification, so that problems can be reported before a table reaches
+// an encoder or decoder.
+
+use std::fmt;
+
+use crate::GrainTableSegment;
+
+/// The chroma format of the video a segment is applied to, which decides
+/// the scaling points that can be signalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaFormat {
+    Monochrome,
+    Yuv420,
+    Yuv422,
+    Yuv444,
+}
+
+/// How serious a [`Violation`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Part of the segment is ignored, but it can still be signalled.
+    Warning,
+    /// The segment cannot be signalled in AV1, or is ambiguous.
+    Error,
+}
+
+/// A constraint which a segment or table does not meet.
+///
+/// Planes are numbered 0 for Y, 1 for Cb and 2 for Cr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// `scaling_shift` is not between 8 and 11.
+    ScalingShift(u8),
+    /// `ar_coeff_lag` is greater than 3.
+    ArCoeffLag(u8),
+    /// `ar_coeff_shift` is not between 6 and 9.
+    ArCoeffShift(u8),
+    /// `grain_scale_shift` is greater than 3.
+    GrainScaleShift(u8),
+    /// The x values of the scaling points of a plane do not strictly
+    /// increase.
+    UnorderedScalingPoints { plane: usize },
+    /// The number of auto-regressive coefficients of a plane does not
+    /// match `ar_coeff_lag`.
+    CoefficientCount {
+        plane: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The offset of a chroma plane does not fit in 9 bits.
+    ChromaOffset { plane: usize, offset: u16 },
+    /// Chroma scaling points are given along with
+    /// `chroma_scaling_from_luma`, which replaces them.
+    ChromaPointsWithScalingFromLuma,
+    /// Chroma scaling points are given without luma scaling points, which
+    /// cannot be signalled for 4:2:0 video.
+    ChromaPointsWithoutLumaPoints,
+    /// Chroma grain is given for monochrome video, which has no chroma.
+    ChromaGrainForMonochrome,
+    /// The segment does not end after it starts, so it covers no frames.
+    EmptySegment,
+    /// The segment starts before the previous segment of the table ends.
+    OverlapsPrevious,
+}
+
+impl Violation {
+    /// Returns how serious this violation is.
+    #[must_use]
+    #[inline]
+    pub const fn severity(self) -> Severity {
+        match self {
+            Self::ChromaPointsWithScalingFromLuma
+            | Self::ChromaGrainForMonochrome
+            | Self::EmptySegment => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PLANES: [&str; 3] = ["Y", "Cb", "Cr"];
+        let name = |plane: usize| PLANES.get(plane).copied().unwrap_or("unknown");
+        match *self {
+            Self::ScalingShift(value) => {
+                write!(f, "scaling_shift must be between 8 and 11, got {value}")
+            }
+            Self::ArCoeffLag(value) => {
+                write!(f, "ar_coeff_lag must be between 0 and 3, got {value}")
+            }
+            Self::ArCoeffShift(value) => {
+                write!(f, "ar_coeff_shift must be between 6 and 9, got {value}")
+            }
+            Self::GrainScaleShift(value) => {
+                write!(f, "grain_scale_shift must be between 0 and 3, got {value}")
+            }
+            Self::UnorderedScalingPoints { plane } => write!(
+                f,
+                "{} scaling points must be in increasing order",
+                name(plane)
+            ),
+            Self::CoefficientCount {
+                plane,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "expected {expected} {} coefficients, got {actual}",
+                name(plane)
+            ),
+            Self::ChromaOffset { plane, offset } => write!(
+                f,
+                "{} offset must be between 0 and 511, got {offset}",
+                name(plane)
+            ),
+            Self::ChromaPointsWithScalingFromLuma => write!(
+                f,
+                "chroma scaling points are ignored with chroma_scaling_from_luma"
+            ),
+            Self::ChromaPointsWithoutLumaPoints => write!(
+                f,
+                "chroma scaling points require luma scaling points for 4:2:0 video"
+            ),
+            Self::ChromaGrainForMonochrome => {
+                write!(f, "chroma grain is ignored for monochrome video")
+            }
+            Self::EmptySegment => write!(f, "segment does not end after it starts"),
+            Self::OverlapsPrevious => {
+                write!(f, "segment starts before the previous segment ends")
+            }
+        }
+    }
+}
+
+impl GrainTableSegment {
+    /// Checks this segment against the constraints of the AV1
+    /// specification, returning every violation found.
+    ///
+    /// Checks which depend on the chroma format of the video are skipped if
+    /// `chroma_format` is `None`. The parameters of segments with
+    /// `apply_grain` unset are not checked, as they are never used.
+    #[must_use]
+    #[inline]
+    pub fn validate(&self, chroma_format: Option<ChromaFormat>) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if self.end_time <= self.start_time {
+            violations.push(Violation::EmptySegment);
+        }
+        if !self.apply_grain {
+            return violations;
+        }
+
+        if !(8..=11).contains(&self.scaling_shift) {
+            violations.push(Violation::ScalingShift(self.scaling_shift));
+        }
+        if self.ar_coeff_lag > 3 {
+            violations.push(Violation::ArCoeffLag(self.ar_coeff_lag));
+        }
+        if !(6..=9).contains(&self.ar_coeff_shift) {
+            violations.push(Violation::ArCoeffShift(self.ar_coeff_shift));
+        }
+        if self.grain_scale_shift > 3 {
+            violations.push(Violation::GrainScaleShift(self.grain_scale_shift));
+        }
+
+        let points: [&[[u8; 2]]; 3] = [
+            &self.scaling_points_y,
+            &self.scaling_points_cb,
+            &self.scaling_points_cr,
+        ];
+        for (plane, points) in points.into_iter().enumerate() {
+            if !points.is_sorted_by(|[x0, _], [x1, _]| x0 < x1) {
+                violations.push(Violation::UnorderedScalingPoints { plane });
+            }
+        }
+
+        let lag = usize::from(self.ar_coeff_lag);
+        let num_pos_luma = 2 * lag * (lag + 1);
+        let coeffs: [(&[i8], usize); 3] = [
+            (&self.ar_coeffs_y, num_pos_luma),
+            (&self.ar_coeffs_cb, num_pos_luma + 1),
+            (&self.ar_coeffs_cr, num_pos_luma + 1),
+        ];
+        for (plane, (coeffs, expected)) in coeffs.into_iter().enumerate() {
+            if coeffs.len() != expected {
+                violations.push(Violation::CoefficientCount {
+                    plane,
+                    expected,
+                    actual: coeffs.len(),
+                });
+            }
+        }
+
+        for (plane, offset) in [(1, self.cb_offset), (2, self.cr_offset)] {
+            if offset > 511 {
+                violations.push(Violation::ChromaOffset { plane, offset });
+            }
+        }
+
+        let has_chroma_points =
+            !self.scaling_points_cb.is_empty() || !self.scaling_points_cr.is_empty();
+        if chroma_format == Some(ChromaFormat::Monochrome) {
+            if has_chroma_points || self.chroma_scaling_from_luma {
+                violations.push(Violation::ChromaGrainForMonochrome);
+            }
+        } else if has_chroma_points {
+            if self.chroma_scaling_from_luma {
+                violations.push(Violation::ChromaPointsWithScalingFromLuma);
+            } else if chroma_format == Some(ChromaFormat::Yuv420)
+                && self.scaling_points_y.is_empty()
+            {
+                violations.push(Violation::ChromaPointsWithoutLumaPoints);
+            }
+        }
+
+        violations
+    }
+}
+
+/// Checks every segment of a table with [`GrainTableSegment::validate`],
+/// and that the segments are ordered by time without overlapping. Each
+/// violation is returned with the index of the segment it was found in.
+#[must_use]
+#[inline]
+pub fn validate_grain_table(
+    segments: &[GrainTableSegment],
+    chroma_format: Option<ChromaFormat>,
+) -> Vec<(usize, Violation)> {
+    let mut violations = Vec::new();
+    let mut previous_end = None;
+    for (index, segment) in segments.iter().enumerate() {
+        if previous_end.is_some_and(|end| segment.start_time < end) {
+            violations.push((index, Violation::OverlapsPrevious));
+        }
+        violations.extend(
+            segment
+                .validate(chroma_format)
+                .into_iter()
+                .map(|violation| (index, violation)),
+        );
+        previous_end = Some(segment.end_time);
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use arrayvec::ArrayVec;
+
+    use super::*;
+
+    fn segment() -> GrainTableSegment {
+        GrainTableSegment {
+            end_time: 100,
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [255, 40]]),
+            scaling_points_cb: ArrayVec::from_iter([[128, 10]]),
+            ar_coeff_lag: 1,
+            ar_coeffs_y: ArrayVec::from_iter([1, -1, 2, -2]),
+            ar_coeffs_cb: ArrayVec::from_iter([0, 0, 0, 4, 127]),
+            ar_coeffs_cr: ArrayVec::from_iter([0; 5]),
+            cb_mult: 128,
+            cb_luma_mult: 192,
+            cb_offset: 256,
+            ..GrainTableSegment::default()
+        }
+    }
+
+    #[test]
+    fn valid_segment_has_no_violations() {
+        for chroma_format in [
+            None,
+            Some(ChromaFormat::Yuv420),
+            Some(ChromaFormat::Yuv422),
+            Some(ChromaFormat::Yuv444),
+        ] {
+            assert_eq!(segment().validate(chroma_format), [], "{chroma_format:?}");
+        }
+        let default = GrainTableSegment {
+            end_time: 1,
+            ..GrainTableSegment::default()
+        };
+        assert_eq!(default.validate(None), []);
+    }
+
+    #[test]
+    fn reports_out_of_range_parameters() {
+        let segment = GrainTableSegment {
+            scaling_points_y: ArrayVec::from_iter([[0, 20], [128, 30], [128, 40]]),
+            scaling_shift: 12,
+            ar_coeff_shift: 5,
+            grain_scale_shift: 4,
+            ar_coeffs_cr: ArrayVec::from_iter([0; 4]),
+            cr_offset: 512,
+            ..segment()
+        };
+        let violations = segment.validate(None);
+        assert_eq!(
+            violations,
+            [
+                Violation::ScalingShift(12),
+                Violation::ArCoeffShift(5),
+                Violation::GrainScaleShift(4),
+                Violation::UnorderedScalingPoints { plane: 0 },
+                Violation::CoefficientCount {
+                    plane: 2,
+                    expected: 5,
+                    actual: 4
+                },
+                Violation::ChromaOffset {
+                    plane: 2,
+                    offset: 512
+                },
+            ]
+        );
+        assert!(
+            violations
+                .iter()
+                .all(|violation| violation.severity() == Severity::Error)
+        );
+    }
+
+    #[test]
+    fn reports_chroma_format_constraints() {
+        let without_luma = GrainTableSegment {
+            scaling_points_y: ArrayVec::new(),
+            ..segment()
+        };
+        assert_eq!(without_luma.validate(None), []);
+        assert_eq!(without_luma.validate(Some(ChromaFormat::Yuv444)), []);
+        assert_eq!(
+            without_luma.validate(Some(ChromaFormat::Yuv420)),
+            [Violation::ChromaPointsWithoutLumaPoints]
+        );
+        assert_eq!(
+            segment().validate(Some(ChromaFormat::Monochrome)),
+            [Violation::ChromaGrainForMonochrome]
+        );
+
+        let from_luma = GrainTableSegment {
+            chroma_scaling_from_luma: true,
+            ..segment()
+        };
+        let violations = from_luma.validate(Some(ChromaFormat::Yuv420));
+        assert_eq!(violations, [Violation::ChromaPointsWithScalingFromLuma]);
+        assert!(
+            violations
+                .iter()
+                .all(|violation| violation.severity() == Severity::Warning)
+        );
+    }
+
+    #[test]
+    fn disabled_segments_are_not_checked() {
+        let segment = GrainTableSegment {
+            apply_grain: false,
+            scaling_shift: 0,
+            ar_coeff_shift: 0,
+            ..segment()
+        };
+        assert_eq!(segment.validate(Some(ChromaFormat::Monochrome)), []);
+    }
+
+    #[test]
+    fn reports_overlapping_and_empty_segments() {
+        let segments = [
+            segment(),
+            GrainTableSegment {
+                start_time: 100,
+                end_time: 200,
+                ..segment()
+            },
+            GrainTableSegment {
+                start_time: 150,
+                end_time: 150,
+                ..segment()
+            },
+        ];
+        assert_eq!(
+            validate_grain_table(&segments, Some(ChromaFormat::Yuv420)),
+            [
+                (2, Violation::OverlapsPrevious),
+                (2, Violation::EmptySegment)
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Exercises the C API from C. The build script compiles this file when the
+ * `capi` feature is enabled, and the tests of src/capi.rs call it.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "av1_grain.h"
+
+#define CHECK(condition)     \
+    do {                     \
+        if (!(condition))    \
+            return __LINE__; \
+    } while (0)
+
+static const char TABLE[] =
+    "filmgrn1\n"
+    "E 0 10000000 1 7391 1\n"
+    "\tp 0 6 0 8 0 1 128 192 256 128 192 256\n"
+    "\tsY 2  0 20 255 40\n"
+    "\tsCb 0\n"
+    "\tsCr 0\n"
+    "\tcY\n"
+    "\tcCb 0\n"
+    "\tcCr 0\n";
+
+size_t av1_grain_capi_segment_size(void)
+{
+    return sizeof(Av1GrainSegment);
+}
+
+size_t av1_grain_capi_noise_args_size(void)
+{
+    return sizeof(Av1GrainNoiseArgs);
+}
+
+static char *read_file(const char *path)
+{
+    FILE *file = fopen(path, "rb");
+    if (!file)
+        return NULL;
+    char *text = calloc(1, 65536);
+    if (text)
+        fread(text, 1, 65535, file);
+    fclose(file);
+    return text;
+}
+
+static int run(Av1GrainTable *table, const char *path)
+{
+    Av1GrainSegment segment = { .struct_size = sizeof(Av1GrainSegment) };
+    CHECK(av1_grain_last_error() == NULL);
+    CHECK(av1_grain_table_len(table) == 1);
+    CHECK(av1_grain_table_get(table, 0, &segment) == 0);
+    CHECK(segment.end_time == 10000000);
+    CHECK(segment.random_seed == 7391);
+    CHECK(segment.num_y_points == 2);
+    CHECK(segment.scaling_points_y[1][0] == 255 && segment.scaling_points_y[1][1] == 40);
+    CHECK(segment.scaling_shift == 8);
+    CHECK(segment.num_cb_coeffs == 1);
+    CHECK(segment.struct_size == sizeof(Av1GrainSegment));
+
+    /* Structs from a newer header are accepted, and their size is kept */
+    struct {
+        Av1GrainSegment segment;
+        uint64_t future_field;
+    } newer = { .segment.struct_size = sizeof(newer), .future_field = 42 };
+    CHECK(av1_grain_table_get(table, 0, &newer.segment) == 0);
+    CHECK(newer.segment.struct_size == sizeof(newer) && newer.future_field == 42);
+    CHECK(newer.segment.random_seed == 7391);
+
+    /* Errors are reported without touching the table */
+    CHECK(av1_grain_table_get(table, 1, &segment) == -1);
+    CHECK(av1_grain_last_error() != NULL);
+    CHECK(av1_grain_table_remove(table, 1) == -1);
+    segment.num_y_points = AV1_GRAIN_NUM_Y_POINTS + 1;
+    CHECK(av1_grain_table_push(table, &segment) == -1);
+    segment.num_y_points = 2;
+    segment.struct_size = sizeof(Av1GrainSegment) - 1;
+    CHECK(av1_grain_table_push(table, &segment) == -1);
+    CHECK(strstr(av1_grain_last_error(), "struct_size") != NULL);
+    CHECK(av1_grain_table_get(table, 0, &segment) == -1);
+    segment.struct_size = sizeof(Av1GrainSegment);
+    CHECK(av1_grain_table_len(table) == 1);
+
+    Av1GrainNoiseArgs args = {
+        .struct_size = sizeof(Av1GrainNoiseArgs),
+        .iso_setting = 800,
+        .width = 1920,
+        .height = 1080,
+        .transfer_function = AV1_GRAIN_TRANSFER_BT1886,
+        .full_range = false,
+        .chroma_grain = true,
+        .has_random_seed = true,
+        .random_seed = 1234,
+    };
+    CHECK(av1_grain_generate_photon_noise(10000000, 20000000, &args, &segment) == 0);
+    CHECK(segment.random_seed == 1234);
+    CHECK(segment.chroma_scaling_from_luma);
+    CHECK(segment.num_y_points > 0);
+    CHECK(av1_grain_table_push(table, &segment) == 0);
+    args.transfer_function = 2;
+    CHECK(av1_grain_generate_photon_noise(0, 1, &args, &segment) == -1);
+
+    CHECK(av1_grain_table_write(table, path) == 0);
+    char *text = read_file(path);
+    CHECK(text != NULL);
+    Av1GrainTable *written = av1_grain_table_parse(text);
+    free(text);
+    CHECK(written != NULL);
+    size_t len = av1_grain_table_len(written);
+    Av1GrainSegment first = { .struct_size = sizeof(Av1GrainSegment) };
+    int got = av1_grain_table_get(written, 1, &first);
+    av1_grain_table_free(written);
+    CHECK(len == 2);
+    CHECK(got == 0 && first.start_time == 10000000 && first.random_seed == 1234);
+
+    CHECK(av1_grain_table_remove(table, 0) == 0);
+    CHECK(av1_grain_table_len(table) == 1);
+    return 0;
+}
+
+int av1_grain_capi_test(const char *path)
+{
+    CHECK(av1_grain_table_parse("filmgrn2\n") == NULL);
+    const char *error = av1_grain_last_error();
+    CHECK(error != NULL && strlen(error) > 0);
+    CHECK(av1_grain_table_parse(NULL) == NULL);
+
+    Av1GrainTable *table = av1_grain_table_parse(TABLE);
+    CHECK(table != NULL);
+    int failed_line = run(table, path);
+    av1_grain_table_free(table);
+    av1_grain_table_free(NULL);
+    return failed_line;
+}